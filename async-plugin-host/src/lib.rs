@@ -0,0 +1,26 @@
+use dynamic_plugin::{plugin_interface, PluginError, PluginStr, PluginString};
+
+plugin_interface! {
+    extern trait AsyncPlugin {
+        /// Wait for a number of milliseconds, then return them
+        async fn delay(millis: u64) -> u64;
+        /// Greet someone
+        async fn greet(name: PluginStr) -> PluginString;
+        /// Divide one number by another
+        async fn divide(a: u32, b: u32) -> Result<u32, PluginError>;
+        /// Panic with a message
+        async fn panic(message: PluginStr);
+    }
+}
+
+plugin_interface! {
+    #[non_reentrant]
+    extern trait BlockingPlugin {
+        /// Work for a number of milliseconds, returning how many calls were running at once
+        fn work(millis: u64) -> u32;
+        /// Count the words in some text
+        fn count_words(text: PluginStr) -> u64;
+        /// Count the words in some text, returning the instance's total
+        fn tally(&mut self, text: PluginStr) -> u64;
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use async_plugin_host::AsyncPlugin;
+use dynamic_plugin::{Error, Result};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let path = std::env::current_exe()?.with_file_name(format!(
+        "{}async_plugin{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ));
+    let plugin = AsyncPlugin::load_plugin_and_check(&path)?;
+
+    // Each call starts in the plugin, and its future resolves when the
+    // plugin completes it
+    let greeting = plugin.greet("Jens")?;
+    let delay = plugin.delay(100)?;
+    println!("{}", greeting.await?.as_str());
+    println!("Waited {}ms", delay.await?);
+
+    // Results and panics come back through the future
+    match plugin.divide(1, 0)?.await? {
+        Ok(quotient) => println!("1 / 0 = {quotient}"),
+        Err(error) => println!("The plugin couldn't divide: {error}"),
+    }
+    match plugin.panic("Oh no")?.await {
+        Err(Error::PluginPanicked { message, .. }) => println!("The plugin panicked: {message}"),
+        result => panic!("the call should have panicked, but returned {result:?}"),
+    }
+
+    // Calls can be abandoned, but the plugin waits for them to complete
+    // before it is unloaded
+    let abandoned = plugin.delay(200)?;
+    let _ = tokio::time::timeout(Duration::from_millis(10), abandoned).await;
+    drop(plugin);
+    println!("The plugin has been unloaded");
+    Ok(())
+}
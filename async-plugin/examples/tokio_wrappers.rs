@@ -0,0 +1,31 @@
+//! Calling a plugin's blocking functions from tokio, without blocking
+//! the runtime.
+
+use std::{sync::Arc, time::Instant};
+
+use async_plugin_host::BlockingPlugin;
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let plugin = Arc::new(BlockingPlugin::from_static(
+        &async_plugin::blocking::STATIC_PLUGIN,
+    )?);
+
+    // Each `_async` method runs the call on tokio's blocking pool, so it
+    // can be spawned like any other future
+    let words = tokio::spawn(plugin.count_words_async("the quick brown fox"));
+    println!("There are {} words", words.await??);
+
+    // The interface is `#[non_reentrant]`, so calls wait their turn
+    let start = Instant::now();
+    let calls: Vec<_> = (0..3)
+        .map(|_| tokio::spawn(plugin.work_async(100)))
+        .collect();
+    for call in calls {
+        assert_eq!(call.await??, 1);
+    }
+    println!("Three calls took {:?}, one at a time", start.elapsed());
+
+    // Blocking calls wait their turn too
+    assert_eq!(plugin.work(10)?, 1);
+    Ok(())
+}
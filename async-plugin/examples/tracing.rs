@@ -0,0 +1,26 @@
+//! Tracing calls to a plugin's `async` functions.
+
+use async_plugin_host::AsyncPlugin;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Print each `plugin_call` span when it closes, with its duration
+    tracing_subscriber::fmt()
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+
+    let plugin = AsyncPlugin::from_static(&async_plugin::STATIC_PLUGIN)?;
+
+    // The span is entered each time the future is polled, and closes
+    // when the call completes, so it covers the whole call
+    let millis = plugin.delay(100)?.await?;
+    println!("Waited for {millis}ms");
+
+    // A call which fails records its error on the span, and emits an
+    // error event in it
+    if let Err(error) = plugin.panic("Oh no")?.await {
+        println!("The call failed: {error}");
+    }
+    Ok(())
+}
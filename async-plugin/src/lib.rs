@@ -0,0 +1,95 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use dynamic_plugin::{plugin_impl, PluginError};
+
+plugin_impl! {
+    async_plugin_host::AsyncPlugin,
+
+    async fn delay(millis: u64) -> u64 {
+        // Each call runs on a thread of its own, so it can block
+        std::thread::sleep(Duration::from_millis(millis));
+        millis
+    }
+
+    async fn greet(name: &str) -> String {
+        format!("Hello, {name}!")
+    }
+
+    async fn divide(a: u32, b: u32) -> Result<u32, PluginError> {
+        a.checked_div(b)
+            .ok_or_else(|| PluginError::new(1, "division by zero"))
+    }
+
+    async fn panic(message: &str) {
+        panic!("{message}");
+    }
+}
+
+/// A plugin which blocks, and can only run one call at a time, linked
+/// statically into hosts alongside the plugin above.
+pub mod blocking {
+    use super::*;
+
+    /// How many calls are running.
+    static RUNNING: AtomicU32 = AtomicU32::new(0);
+    /// The most calls which have run at once.
+    static MOST_AT_ONCE: AtomicU32 = AtomicU32::new(0);
+
+    /// Work for `millis` as part of a call, returning how many calls
+    /// were running at once.
+    fn work_for(millis: u64) -> u32 {
+        let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+        MOST_AT_ONCE.fetch_max(running, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(millis));
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+        running
+    }
+
+    /// The most calls which have run at once, including creating and
+    /// destroying instances.
+    pub fn most_at_once() -> u32 {
+        MOST_AT_ONCE.load(Ordering::SeqCst)
+    }
+
+    /// The state of an instance, which takes a while to create and
+    /// destroy.
+    struct Tally {
+        words: u64,
+    }
+
+    impl Default for Tally {
+        fn default() -> Self {
+            work_for(10);
+            Self { words: 0 }
+        }
+    }
+
+    impl Drop for Tally {
+        fn drop(&mut self) {
+            work_for(10);
+        }
+    }
+
+    plugin_impl! {
+        async_plugin_host::BlockingPlugin,
+        state: Tally,
+        reentrant: false,
+        link: static,
+
+        fn work(millis: u64) -> u32 {
+            work_for(millis)
+        }
+
+        fn count_words(text: &str) -> u64 {
+            text.split_whitespace().count() as u64
+        }
+
+        fn tally(&mut self, text: &str) -> u64 {
+            self.words += text.split_whitespace().count() as u64;
+            self.words
+        }
+    }
+}
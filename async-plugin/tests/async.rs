@@ -0,0 +1,112 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use async_plugin_host::AsyncPlugin;
+use dynamic_plugin::Error;
+
+fn plugin_path() -> PathBuf {
+    let deps = std::env::current_exe().unwrap();
+    deps.with_file_name(format!(
+        "{}async_plugin{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ))
+}
+
+fn load() -> AsyncPlugin {
+    AsyncPlugin::load_plugin_and_check(plugin_path()).unwrap()
+}
+
+/// Another plugin, linked into the tests alongside the first.
+mod other {
+    use dynamic_plugin::{plugin_impl, PluginError};
+
+    plugin_impl! {
+        async_plugin_host::AsyncPlugin,
+        link: static,
+
+        async fn delay(millis: u64) -> u64 {
+            millis
+        }
+
+        async fn greet(name: &str) -> String {
+            name.to_string()
+        }
+
+        async fn divide(_a: u32, _b: u32) -> Result<u32, PluginError> {
+            Err(PluginError::new(2, "not supported"))
+        }
+
+        async fn panic(_message: &str) {}
+    }
+}
+
+#[tokio::test]
+async fn completes_calls() {
+    let plugin = load();
+    assert_eq!(plugin.delay(10).unwrap().await.unwrap(), 10);
+    assert_eq!(
+        plugin.greet("Jens").unwrap().await.unwrap().as_str(),
+        "Hello, Jens!"
+    );
+    assert_eq!(plugin.divide(6, 3).unwrap().await.unwrap().unwrap(), 2);
+    let error = plugin.divide(6, 0).unwrap().await.unwrap().unwrap_err();
+    assert_eq!(error.code(), 1);
+}
+
+#[tokio::test]
+async fn completes_calls_at_the_same_time() {
+    let plugin = load();
+    let start = Instant::now();
+    let calls = [plugin.delay(200).unwrap(), plugin.delay(200).unwrap()];
+    for call in calls {
+        assert_eq!(call.await.unwrap(), 200);
+    }
+    assert!(start.elapsed() < Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn reports_panics() {
+    let plugin = load();
+    match plugin.panic("Oh no").unwrap().await {
+        Err(Error::PluginPanicked { function, message }) => {
+            assert_eq!(function, "panic");
+            assert!(message.starts_with("Oh no"));
+        }
+        result => panic!("the call should have panicked, but returned {result:?}"),
+    }
+}
+
+#[tokio::test]
+async fn rejects_invalid_utf8_in_strings() {
+    let plugin = load();
+    let name = c"J\xffns";
+    assert!(matches!(
+        plugin.greet(name).unwrap().await,
+        Err(Error::InvalidUtf8)
+    ));
+}
+
+#[test]
+fn waits_for_calls_before_unloading() {
+    let plugin = load();
+    // The call keeps running after the host stops waiting for it
+    drop(plugin.delay(300).unwrap());
+    let start = Instant::now();
+    drop(plugin);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn waits_only_for_the_plugins_own_calls() {
+    let busy = AsyncPlugin::from_static(&async_plugin::STATIC_PLUGIN).unwrap();
+    let idle = AsyncPlugin::from_static(&other::STATIC_PLUGIN).unwrap();
+    drop(busy.delay(1000).unwrap());
+    let start = Instant::now();
+    drop(idle);
+    assert!(start.elapsed() < Duration::from_millis(500));
+    drop(busy);
+    assert!(start.elapsed() >= Duration::from_millis(500));
+}
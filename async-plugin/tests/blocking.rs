@@ -0,0 +1,78 @@
+use std::{
+    sync::{Arc, OnceLock},
+    thread,
+};
+
+use async_plugin_host::BlockingPlugin;
+
+/// The plugin, loaded once so that calls from every test wait for each
+/// other.
+fn load() -> Arc<BlockingPlugin> {
+    static PLUGIN: OnceLock<Arc<BlockingPlugin>> = OnceLock::new();
+    let plugin = PLUGIN.get_or_init(|| {
+        Arc::new(BlockingPlugin::from_static(&async_plugin::blocking::STATIC_PLUGIN).unwrap())
+    });
+    Arc::clone(plugin)
+}
+
+#[test]
+fn runs_one_call_at_a_time() {
+    let plugin = load();
+    let calls: Vec<_> = (0..4)
+        .map(|_| {
+            let plugin = Arc::clone(&plugin);
+            thread::spawn(move || plugin.work(20).unwrap())
+        })
+        .collect();
+    for call in calls {
+        assert_eq!(call.join().unwrap(), 1);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_calls_on_the_blocking_pool() {
+    let plugin = load();
+    let words = tokio::spawn(plugin.count_words_async("one two three"));
+    assert_eq!(words.await.unwrap().unwrap(), 3);
+
+    // Calls from either path wait for each other
+    let calls: Vec<_> = (0..4)
+        .map(|_| tokio::spawn(plugin.work_async(20)))
+        .collect();
+    let blocking = {
+        let plugin = Arc::clone(&plugin);
+        thread::spawn(move || plugin.work(20).unwrap())
+    };
+    for call in calls {
+        assert_eq!(call.await.unwrap().unwrap(), 1);
+    }
+    assert_eq!(blocking.join().unwrap(), 1);
+}
+
+#[test]
+fn creates_and_destroys_instances_one_call_at_a_time() {
+    let plugin = load();
+    let instances: Vec<_> = (0..2)
+        .map(|_| {
+            let plugin = Arc::clone(&plugin);
+            thread::spawn(move || {
+                let mut instance = plugin.create_instance().unwrap();
+                assert_eq!(instance.tally("one two").unwrap(), 2);
+                assert_eq!(instance.tally("three").unwrap(), 3);
+            })
+        })
+        .collect();
+    let calls: Vec<_> = (0..2)
+        .map(|_| {
+            let plugin = Arc::clone(&plugin);
+            thread::spawn(move || plugin.work(20).unwrap())
+        })
+        .collect();
+    for instance in instances {
+        instance.join().unwrap();
+    }
+    for call in calls {
+        assert_eq!(call.join().unwrap(), 1);
+    }
+    assert_eq!(async_plugin::blocking::most_at_once(), 1);
+}
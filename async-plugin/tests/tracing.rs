@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use async_plugin_host::AsyncPlugin;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, Layer};
+
+/// The fields of a `plugin_call` span.
+#[derive(Debug, Default, Clone)]
+struct CallFields {
+    function: String,
+    duration_ms: Option<f64>,
+    error: Option<String>,
+}
+
+impl Visit for CallFields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "duration_ms" {
+            self.duration_ms = Some(value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "function" {
+            self.function = value.to_string();
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "error" {
+            self.error = Some(format!("{value:?}"));
+        }
+    }
+}
+
+/// Collects the fields of each `plugin_call` span once it has closed.
+#[derive(Default, Clone)]
+struct Calls {
+    open: Arc<Mutex<HashMap<Id, CallFields>>>,
+    closed: Arc<Mutex<Vec<CallFields>>>,
+}
+
+impl<S: Subscriber> Layer<S> for Calls {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+        if attrs.metadata().name() == "plugin_call" {
+            let mut fields = CallFields::default();
+            attrs.record(&mut fields);
+            self.open.lock().unwrap().insert(id.clone(), fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        if let Some(fields) = self.open.lock().unwrap().get_mut(id) {
+            values.record(fields);
+        }
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        if let Some(fields) = self.open.lock().unwrap().remove(&id) {
+            self.closed.lock().unwrap().push(fields);
+        }
+    }
+}
+
+impl Calls {
+    /// Record the calls made on this thread until the guard is dropped.
+    fn record() -> (Self, tracing::subscriber::DefaultGuard) {
+        let calls = Self::default();
+        let guard = tracing_subscriber::registry()
+            .with(calls.clone())
+            .set_default();
+        (calls, guard)
+    }
+
+    fn closed(&self) -> Vec<CallFields> {
+        self.closed.lock().unwrap().clone()
+    }
+}
+
+#[tokio::test]
+async fn spans_async_calls_until_they_complete() {
+    let (calls, _guard) = Calls::record();
+    let plugin = AsyncPlugin::from_static(&async_plugin::STATIC_PLUGIN).unwrap();
+    let call = plugin.delay(200).unwrap();
+    // The span stays open while the call runs
+    assert!(calls.closed().is_empty());
+    assert_eq!(call.await.unwrap(), 200);
+
+    let closed = calls.closed();
+    assert_eq!(closed.len(), 1);
+    assert_eq!(closed[0].function, "delay");
+    assert!(closed[0].duration_ms.unwrap() >= 200.0);
+    assert!(closed[0].error.is_none());
+}
+
+#[tokio::test]
+async fn records_errors_of_async_calls() {
+    let (calls, _guard) = Calls::record();
+    let plugin = AsyncPlugin::from_static(&async_plugin::STATIC_PLUGIN).unwrap();
+    assert!(plugin.panic("Oh no").unwrap().await.is_err());
+
+    let closed = calls.closed();
+    assert_eq!(closed.len(), 1);
+    assert_eq!(closed[0].function, "panic");
+    assert!(closed[0].error.as_ref().unwrap().contains("Oh no"));
+}
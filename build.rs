@@ -0,0 +1,11 @@
+fn main() {
+    // Only `TARGET` is read, which changes the whole build anyway
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Plugins report the target they were built for, so that the host
+    // can check it matches its own
+    println!(
+        "cargo:rustc-env=DYNAMIC_PLUGIN_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+}
@@ -0,0 +1,112 @@
+//! Calling plugin functions from async code, on tokio's blocking pool.
+//!
+//! With the `tokio` feature, each host method for a plugin function has
+//! an `_async` variant. It copies any borrowed arguments, then runs the
+//! host method on the blocking pool so that a slow plugin doesn't block
+//! the runtime.
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{FnArg, Type};
+
+use crate::{
+    def::{PluginDefinition, PluginFunction},
+    lowering::{self, ArgLowering},
+    threading::Threading,
+};
+
+/// Generate the `_async` variant of the host method for `pf`, a function
+/// of `plugin_def`, if the `tokio` feature is enabled and its arguments
+/// can be moved to another thread. Methods of plugin instances borrow
+/// the plugin, and plugins which are `main_thread_only` can't be moved
+/// to another thread, so don't have one.
+pub fn async_method(pf: &PluginFunction, plugin_def: &PluginDefinition) -> Option<TokenStream2> {
+    if !cfg!(feature = "tokio")
+        || plugin_def.threading == Threading::MainThreadOnly
+        || pf.asyncness.is_some()
+        || pf.receiver().is_some()
+        || !pf.arguments.iter().all(movable)
+    {
+        return None;
+    }
+    let name = &pf.name;
+    let async_name = format_ident!("{name}_async");
+    let function_name = name.to_string();
+    let doc = format!(
+        "Call [`{name}`](Self::{name}) on tokio's blocking thread pool, so that it doesn't block the runtime."
+    );
+    let mut params = vec![];
+    let mut generics = None;
+    let mut owned = vec![];
+    let mut checks = vec![];
+    let mut values = vec![];
+    for (idx, arg) in pf.arguments.iter().enumerate() {
+        let (Some(host_arg), FnArg::Typed(typed)) = (lowering::host_arg(arg, idx), arg) else {
+            continue;
+        };
+        let name = &host_arg.name;
+        if host_arg.borrows_str {
+            generics = Some(quote!(<'s>));
+        }
+        params.push(host_arg.param);
+        match ArgLowering::of(&typed.ty) {
+            ArgLowering::Plain => values.push(quote!(#name)),
+            ArgLowering::Str { .. } => {
+                owned.push(
+                    quote!(let #name = #name.into().to_c_str().map(::std::borrow::Cow::into_owned);),
+                );
+                checks.push(quote!(let #name = #name?;));
+                values.push(quote!(&#name));
+            }
+            ArgLowering::Slice { .. } => {
+                owned.push(quote!(let #name = #name.to_vec();));
+                values.push(quote!(&#name));
+            }
+        }
+    }
+    let ret = pf
+        .return_type
+        .as_ref()
+        .map_or_else(|| quote!(()), |typ| quote!(#typ));
+    // Non-reentrant plugins' calls wait for their turn here, rather than
+    // each holding a thread of the blocking pool while they wait
+    let (permit, call) = if plugin_def.non_reentrant {
+        (
+            Some(quote!(let permit = ::dynamic_plugin::__private::CallPermit::acquire(&plugin.calls).await;)),
+            quote!(move || permit.run(|| plugin.#name(#(#values),*))),
+        )
+    } else {
+        (None, quote!(move || plugin.#name(#(#values),*)))
+    };
+    Some(quote! {
+        #[doc = #doc]
+        pub fn #async_name #generics(
+            self: &::std::sync::Arc<Self>,
+            #(#params),*
+        ) -> impl ::std::future::Future<Output = ::dynamic_plugin::Result<#ret>> + ::std::marker::Send + 'static {
+            let plugin = ::std::sync::Arc::clone(self);
+            #(#owned)*
+            async move {
+                #(#checks)*
+                #permit
+                ::dynamic_plugin::__private::run_blocking(#function_name, #call).await
+            }
+        }
+    })
+}
+
+/// Whether an argument can be moved to the blocking pool, once strings
+/// and slices have been copied.
+fn movable(arg: &FnArg) -> bool {
+    let FnArg::Typed(typed) = arg else {
+        return true;
+    };
+    match ArgLowering::of(&typed.ty) {
+        ArgLowering::Slice { mutable, .. } => !mutable,
+        ArgLowering::Str { .. } => true,
+        ArgLowering::Plain => !matches!(
+            *typed.ty,
+            Type::Ptr(_) | Type::Reference(_) | Type::BareFn(_)
+        ),
+    }
+}
@@ -4,18 +4,30 @@ use syn::{
     braced, parenthesized,
     parse::{Parse, ParseStream},
     punctuated::Punctuated,
-    Attribute, FnArg, Ident, Result, Token, Type,
+    Attribute, Error, FnArg, Ident, LitInt, Receiver, Result, Token, Type,
 };
 
+use crate::threading::Threading;
+
 pub struct PluginDefinition {
     pub name: Ident,
     pub functions: Vec<PluginFunction>,
+    /// Set if a plugin can only run one call at a time.
+    pub non_reentrant: bool,
+    /// How the plugin's functions may be called from the host's threads.
+    pub threading: Threading,
 }
 
+/// The signature of an interface covers what the plugin must agree with
+/// the host on, including how it may be called. Timeouts are left out,
+/// as only the host uses them: a plugin built against an interface with
+/// different timeouts is called in the same way, and is still loaded.
 impl Hash for PluginDefinition {
     fn hash<H: Hasher>(&self, state: &mut H) {
         // Hash name
         self.name.hash(state);
+        self.threading.hash(state);
+        crate::threading::hash_non_reentrant(self.non_reentrant, state);
 
         // Sort functions
         let mut functions = self.functions.clone();
@@ -24,20 +36,26 @@ impl Hash for PluginDefinition {
             // Hash function ident
             "fn".hash(state);
             function.name.hash(state);
+            if function.asyncness.is_some() {
+                "async".hash(state);
+            }
 
             for inp in function.arguments {
                 // Hash argument types only
-                if let FnArg::Typed(typed) = inp {
-                    let ty = typed.ty;
-                    "arg".hash(state);
-                    crate::hash_type(state, *ty);
+                match inp {
+                    FnArg::Typed(typed) => {
+                        let ty = typed.ty;
+                        "arg".hash(state);
+                        crate::hash_type(state, *ty);
+                    }
+                    FnArg::Receiver(receiver) => crate::hash_receiver(state, &receiver),
                 }
             }
 
             // Hash return type
             if let Some(ty) = function.return_type {
                 "ret".hash(state);
-                crate::hash_type(state, ty);
+                crate::hash_return_type(state, ty);
             }
         }
     }
@@ -45,6 +63,37 @@ impl Hash for PluginDefinition {
 
 impl Parse for PluginDefinition {
     fn parse(input: ParseStream) -> Result<Self> {
+        // An interface's attributes are the default timeout of its
+        // functions, whether its plugins are reentrant, and how they may
+        // be called from the host's threads
+        let mut interface_timeout = None;
+        let mut non_reentrant = false;
+        let mut threading = None;
+        for attr in Attribute::parse_outer(input)? {
+            if attr.path().is_ident("timeout") {
+                interface_timeout = Some(parse_timeout(&attr)?);
+            } else if attr.path().is_ident("non_reentrant") {
+                attr.meta.require_path_only()?;
+                non_reentrant = true;
+            } else if let Some(contract) = attr.path().get_ident().and_then(Threading::from_ident) {
+                attr.meta.require_path_only()?;
+                if threading.is_some() {
+                    return Err(Error::new_spanned(
+                        attr,
+                        format!("an interface can only be one of {}", Threading::NAMES),
+                    ));
+                }
+                threading = Some(contract);
+            } else {
+                return Err(Error::new_spanned(
+                    attr,
+                    format!(
+                        "plugin interfaces only support the `timeout` and `non_reentrant` attributes, and one of {}",
+                        Threading::NAMES
+                    ),
+                ));
+            }
+        }
         let _: Token![extern] = input.parse()?;
         let _: Token![trait] = input.parse()?;
         let name = input.parse()?;
@@ -56,23 +105,39 @@ impl Parse for PluginDefinition {
         while !plugin_content.is_empty() {
             let lookahead = plugin_content.lookahead1();
             let mut attrs = vec![];
+            let mut timeout = interface_timeout;
             if lookahead.peek(Token![#]) {
                 // Parse attributes
                 attrs = Attribute::parse_outer(&plugin_content)?;
             }
+            // Timeouts are handled by the host, rather than passed on to its methods
+            for attr in &attrs {
+                if attr.path().is_ident("timeout") {
+                    timeout = Some(parse_timeout(attr)?);
+                }
+            }
+            attrs.retain(|attr| !attr.path().is_ident("timeout"));
             // Parse as function
+            let asyncness: Option<Token![async]> = plugin_content.parse()?;
             let _: Token![fn] = plugin_content.parse()?;
             let fn_name = plugin_content.parse()?;
             let args_content;
             parenthesized!(args_content in plugin_content);
             let vars: Punctuated<FnArg, Token![,]> =
                 args_content.parse_terminated(FnArg::parse, Token![,])?;
+            crate::lowering::check_receiver(&vars)?;
+            for var in &vars {
+                crate::lowering::check_interface_arg(var)?;
+            }
+            crate::lowering::check_async(asyncness.as_ref(), &vars)?;
 
             let mut return_type = None;
             let lookahead = plugin_content.lookahead1();
             if lookahead.peek(Token![->]) {
                 let _: Token![->] = plugin_content.parse()?;
-                return_type = Some(plugin_content.parse()?);
+                let ty = plugin_content.parse()?;
+                crate::lowering::check_interface_return(&ty)?;
+                return_type = Some(ty);
                 let _: Token![;] = plugin_content.parse()?;
             } else if lookahead.peek(Token![;]) {
                 let _: Token![;] = plugin_content.parse()?;
@@ -80,22 +145,184 @@ impl Parse for PluginDefinition {
                 return Err(lookahead.error());
             }
 
+            // Calls to `async` functions run after the host method
+            // returns, so can't be held to one at a time
+            let exclusive = if non_reentrant {
+                Some("non_reentrant")
+            } else {
+                threading
+                    .filter(|threading| *threading == Threading::SingleThreaded)
+                    .map(Threading::name)
+            };
+            if let (Some(exclusive), Some(asyncness)) = (exclusive, asyncness) {
+                return Err(Error::new_spanned(
+                    asyncness,
+                    format!("`#[{exclusive}]` interfaces can't have `async` functions, as their calls run after the host method returns"),
+                ));
+            }
+
             functions.push(PluginFunction {
                 attributes: attrs,
+                asyncness,
                 name: fn_name,
                 arguments: vars.into_iter().collect(),
                 return_type,
+                timeout,
             });
         }
 
-        Ok(Self { name, functions })
+        Ok(Self {
+            name,
+            functions,
+            non_reentrant,
+            threading: threading.unwrap_or_default(),
+        })
     }
 }
 
 #[derive(Clone)]
 pub struct PluginFunction {
     pub attributes: Vec<Attribute>,
+    /// Set if the function is `async`, so completes by calling back
+    /// to the host.
+    pub asyncness: Option<Token![async]>,
     pub name: Ident,
     pub arguments: Vec<FnArg>,
     pub return_type: Option<Type>,
+    /// How many milliseconds an isolated plugin has to return from
+    /// this function, if it was given a timeout. This is only used by
+    /// the host, so isn't part of the interface's signature.
+    pub timeout: Option<u64>,
+}
+
+impl PluginFunction {
+    /// The receiver of this function, if it is a method of a plugin
+    /// instance.
+    pub fn receiver(&self) -> Option<&Receiver> {
+        match self.arguments.first() {
+            Some(FnArg::Receiver(receiver)) => Some(receiver),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a `#[timeout(millis = 500)]` attribute, returning the timeout in
+/// milliseconds.
+fn parse_timeout(attr: &Attribute) -> Result<u64> {
+    let mut millis = None;
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("millis") {
+            let value: LitInt = meta.value()?.parse()?;
+            millis = Some(value.base10_parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `millis`"))
+        }
+    })?;
+    millis.ok_or_else(|| Error::new_spanned(attr, "expected `#[timeout(millis = ...)]`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{Hash, Hasher};
+
+    use super::PluginDefinition;
+
+    fn signature(definition: &str) -> u64 {
+        let definition = syn::parse_str::<PluginDefinition>(definition).unwrap();
+        let mut hasher = std::hash::DefaultHasher::new();
+        definition.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn parse_error(definition: &str) -> String {
+        match syn::parse_str::<PluginDefinition>(definition) {
+            Ok(_) => panic!("`{definition}` should be rejected"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn accepts_plugin_str_and_slices() {
+        syn::parse_str::<PluginDefinition>(
+            "extern trait Plugin { fn f(s: PluginStr, a: &[u8], b: &mut [u32]) -> u32; }",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_borrowed_strings() {
+        assert!(parse_error("extern trait Plugin { fn f(s: &str); }").contains("PluginStr"));
+        assert!(parse_error("extern trait Plugin { fn f(s: &CStr); }").contains("PluginStr"));
+    }
+
+    #[test]
+    fn accepts_results_of_plugin_errors() {
+        syn::parse_str::<PluginDefinition>(
+            "extern trait Plugin { fn f() -> Result<u32, PluginError>; fn g() -> Result<String, dynamic_plugin::PluginError>; }",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_other_error_types() {
+        assert!(
+            parse_error("extern trait Plugin { fn f() -> Result<u32, String>; }")
+                .contains("PluginError")
+        );
+        assert!(parse_error(
+            "extern trait Plugin { fn f() -> Result<Result<u32, PluginError>, PluginError>; }"
+        )
+        .contains("nested"));
+    }
+
+    #[test]
+    fn rejects_borrowed_returns() {
+        assert!(parse_error("extern trait Plugin { fn f() -> &str; }").contains("arguments"));
+    }
+
+    #[test]
+    fn rejects_other_references() {
+        assert!(
+            parse_error("extern trait Plugin { fn f(n: &u32); }").contains("only borrow slices")
+        );
+        assert!(parse_error("extern trait Plugin { fn f(s: &[&str]); }").contains("not supported"));
+    }
+
+    #[test]
+    fn leaves_timeouts_out_of_signatures() {
+        let plain = signature("extern trait Plugin { fn f(); }");
+        assert_eq!(
+            signature(
+                "#[timeout(millis = 10)] extern trait Plugin { #[timeout(millis = 5)] fn f(); }"
+            ),
+            plain
+        );
+        assert_ne!(signature("extern trait Plugin { fn g(); }"), plain);
+    }
+
+    #[test]
+    fn includes_reentrancy_in_signatures() {
+        assert_ne!(
+            signature("#[non_reentrant] extern trait Plugin { fn f(); }"),
+            signature("extern trait Plugin { fn f(); }")
+        );
+    }
+
+    #[test]
+    fn rejects_async_functions_in_exclusive_interfaces() {
+        assert!(
+            parse_error("#[non_reentrant] extern trait Plugin { async fn f(); }")
+                .contains("non_reentrant")
+        );
+        assert!(
+            parse_error("#[single_threaded] extern trait Plugin { async fn f(); }")
+                .contains("single_threaded")
+        );
+        syn::parse_str::<PluginDefinition>("extern trait Plugin { async fn f(); }").unwrap();
+        syn::parse_str::<PluginDefinition>(
+            "#[main_thread_only] extern trait Plugin { async fn f(); }",
+        )
+        .unwrap();
+    }
 }
@@ -1,13 +1,35 @@
 use std::hash::{Hash, Hasher};
 
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
 use syn::{
     parse::{Parse, ParseStream},
-    Attribute, FnArg, ItemFn, Result, ReturnType, Token, TypePath,
+    spanned::Spanned,
+    Attribute, FnArg, Ident, ItemFn, Path, Result, ReturnType, Token, TypePath,
 };
 
+use crate::{metadata::PluginMetadata, threading::Threading};
+
 pub struct PluginImplementation {
     pub target_plugin: TypePath,
+    pub host: Option<TypePath>,
+    pub state: Option<TypePath>,
+    /// The function which runs the tasks of `async` functions, if they
+    /// don't each run on a thread of their own.
+    pub spawner: Option<Path>,
+    /// How the host may call the plugin's functions, which must match
+    /// its interface.
+    pub threading: Threading,
+    /// Set if the plugin can only run one call at a time, which must
+    /// match its interface.
+    pub non_reentrant: bool,
+    /// Whether the plugin is only linked statically, so doesn't export
+    /// its functions by name.
+    pub static_link: bool,
+    pub metadata: PluginMetadata,
     pub functions: Vec<MaybeUnsafeFn>,
+    pub on_load: Option<MaybeUnsafeFn>,
+    pub on_unload: Option<MaybeUnsafeFn>,
 }
 
 impl Hash for PluginImplementation {
@@ -22,6 +44,8 @@ impl Hash for PluginImplementation {
             .ident
             .clone();
         type_ident.hash(state);
+        self.threading.hash(state);
+        crate::threading::hash_non_reentrant(self.non_reentrant, state);
 
         // Sort functions
         let mut functions = self.functions.clone();
@@ -31,20 +55,26 @@ impl Hash for PluginImplementation {
             "fn".hash(state);
             // Hash function ident
             function.sig.ident.hash(state);
+            if function.sig.asyncness.is_some() {
+                "async".hash(state);
+            }
 
             for inp in function.sig.inputs {
                 // Hash argument types only
-                if let FnArg::Typed(typed) = inp {
-                    let ty = typed.ty;
-                    "arg".hash(state);
-                    crate::hash_type(state, *ty);
+                match inp {
+                    FnArg::Typed(typed) => {
+                        let ty = typed.ty;
+                        "arg".hash(state);
+                        crate::hash_type(state, *ty);
+                    }
+                    FnArg::Receiver(receiver) => crate::hash_receiver(state, &receiver),
                 }
             }
 
             // Hash return type
             if let ReturnType::Type(_, ty) = function.sig.output {
                 "ret".hash(state);
-                crate::hash_type(state, *ty);
+                crate::hash_return_type(state, *ty);
             }
         }
     }
@@ -54,31 +84,453 @@ impl Parse for PluginImplementation {
     fn parse(input: ParseStream) -> Result<Self> {
         let target_plugin = input.parse()?;
         let _: Token![,] = input.parse()?;
+
+        // Parse options, in the form `key: value,`
+        let mut host = None;
+        let mut state = None;
+        let mut spawner = None;
+        let mut threading = Threading::default();
+        let mut non_reentrant = false;
+        let mut static_link = false;
+        let mut metadata = PluginMetadata::default();
+        while input.peek(Ident) && input.peek2(Token![:]) && !input.peek2(Token![::]) {
+            let key: Ident = input.parse()?;
+            let _: Token![:] = input.parse()?;
+            if key == "host" {
+                host = Some(input.parse()?);
+            } else if key == "state" {
+                state = Some(input.parse()?);
+            } else if key == "spawner" {
+                spawner = Some(input.parse()?);
+            } else if key == "threading" {
+                let contract: Ident = input.parse()?;
+                threading = Threading::from_ident(&contract).ok_or_else(|| {
+                    syn::Error::new(contract.span(), format!("expected {}", Threading::NAMES))
+                })?;
+            } else if key == "reentrant" {
+                let reentrant: syn::LitBool = input.parse()?;
+                non_reentrant = !reentrant.value;
+            } else if key == "link" {
+                static_link = parse_static_link(input)?;
+            } else if key == "metadata" {
+                metadata = input.parse()?;
+            } else {
+                return Err(syn::Error::new(key.span(), "unknown plugin option"));
+            }
+            let _: Token![,] = input.parse()?;
+        }
+
         let mut functions = vec![];
+        let mut on_load = None;
+        let mut on_unload = None;
         while !input.is_empty() {
-            functions.push(input.parse()?);
+            let func: MaybeUnsafeFn = input.parse()?;
+            crate::lowering::check_receiver(&func.func.sig.inputs)?;
+            crate::lowering::check_async(func.func.sig.asyncness.as_ref(), &func.func.sig.inputs)?;
+            if state.is_none() {
+                if let Some(FnArg::Receiver(receiver)) = func.func.sig.inputs.first() {
+                    return Err(syn::Error::new(
+                        receiver.span(),
+                        "methods need a plugin state, given with `state: Type,`",
+                    ));
+                }
+            }
+            // Lifecycle hooks are not part of the interface
+            let hook = if func.has_attr("on_load") {
+                &mut on_load
+            } else if func.has_attr("on_unload") {
+                &mut on_unload
+            } else {
+                functions.push(func);
+                continue;
+            };
+            if hook.is_some() {
+                return Err(syn::Error::new(
+                    func.func.sig.ident.span(),
+                    "a plugin can only have one of each lifecycle hook",
+                ));
+            }
+            if !func.func.sig.inputs.is_empty() {
+                return Err(syn::Error::new(
+                    func.func.sig.inputs.span(),
+                    "lifecycle hooks cannot take arguments",
+                ));
+            }
+            if let Some(asyncness) = func.func.sig.asyncness {
+                return Err(syn::Error::new(
+                    asyncness.span(),
+                    "lifecycle hooks cannot be async",
+                ));
+            }
+            *hook = Some(func);
         }
 
         Ok(Self {
             target_plugin,
+            host,
+            state,
+            spawner,
+            threading,
+            non_reentrant,
+            static_link,
+            metadata,
             functions,
+            on_load,
+            on_unload,
         })
     }
 }
 
+/// Parse the value of the `link` option, returning whether the plugin is
+/// only linked statically.
+fn parse_static_link(input: ParseStream) -> Result<bool> {
+    if input.peek(Token![static]) {
+        let _: Token![static] = input.parse()?;
+        return Ok(true);
+    }
+    let linkage: Ident = input.parse()?;
+    if linkage != "dynamic" {
+        return Err(syn::Error::new(
+            linkage.span(),
+            "expected `static` or `dynamic`",
+        ));
+    }
+    Ok(false)
+}
+
+impl PluginImplementation {
+    /// Generate the functions exported from the plugin to create and
+    /// destroy instances of its state, if it has any.
+    pub fn export_instances(&self) -> Option<TokenStream2> {
+        self.state.as_ref().map(|state| {
+            let no_mangle = self.no_mangle();
+            quote! {
+                #no_mangle
+                pub extern "C" fn _dynamic_plugin_create() -> ::std::mem::MaybeUninit<*mut ::std::ffi::c_void> {
+                    ::dynamic_plugin::__private::catch_panic(|| {
+                        ::std::boxed::Box::into_raw(::std::boxed::Box::new(<#state as ::std::default::Default>::default()))
+                            .cast::<::std::ffi::c_void>()
+                    })
+                }
+
+                #no_mangle
+                pub unsafe extern "C" fn _dynamic_plugin_destroy(instance: *mut ::std::ffi::c_void) {
+                    let _ = ::dynamic_plugin::__private::catch_panic(|| unsafe {
+                        ::std::mem::drop(::std::boxed::Box::from_raw(instance.cast::<#state>()));
+                    });
+                }
+            }
+        })
+    }
+}
+
+impl PluginImplementation {
+    /// Whether any of the plugin's functions are `async`.
+    fn has_async(&self) -> bool {
+        self.functions
+            .iter()
+            .any(|func| func.func.sig.asyncness.is_some())
+    }
+
+    /// Generate the plugin's running `async` calls, and the function
+    /// exported from the plugin which waits for them to complete before
+    /// it is unloaded, if it has any `async` functions.
+    pub fn export_wait_tasks(&self) -> Option<TokenStream2> {
+        self.has_async().then(|| {
+            let no_mangle = self.no_mangle();
+            quote! {
+                static _DYNAMIC_PLUGIN_TASKS: ::dynamic_plugin::__private::RunningTasks =
+                    ::dynamic_plugin::__private::RunningTasks::new();
+
+                #no_mangle
+                pub extern "C" fn _dynamic_plugin_wait_tasks() -> bool {
+                    _DYNAMIC_PLUGIN_TASKS.wait()
+                }
+            }
+        })
+    }
+
+    /// The attribute which exports a function from the plugin by name.
+    /// Plugins given `link: static,` are found through their
+    /// `STATIC_PLUGIN` instead, so that many can be linked into one host
+    /// without their names clashing.
+    pub fn no_mangle(&self) -> TokenStream2 {
+        if self.static_link {
+            quote!()
+        } else {
+            quote!(#[no_mangle])
+        }
+    }
+
+    /// The names of the functions exported from the plugin.
+    pub fn exported_symbols(&self) -> Vec<Ident> {
+        let mut symbols = vec![
+            format_ident!("_dynamic_plugin_signature"),
+            format_ident!("_dynamic_plugin_take_panic"),
+            format_ident!("_dynamic_plugin_metadata"),
+            format_ident!("_dynamic_plugin_abi"),
+        ];
+        if self.host.is_some() {
+            symbols.push(format_ident!("_dynamic_plugin_set_host_api"));
+        }
+        if self.on_load.is_some() {
+            symbols.push(format_ident!("_dynamic_plugin_on_load"));
+        }
+        if self.on_unload.is_some() {
+            symbols.push(format_ident!("_dynamic_plugin_on_unload"));
+        }
+        if self.has_async() {
+            symbols.push(format_ident!("_dynamic_plugin_wait_tasks"));
+        }
+        if self.state.is_some() {
+            symbols.push(format_ident!("_dynamic_plugin_create"));
+            symbols.push(format_ident!("_dynamic_plugin_destroy"));
+        }
+        symbols.extend(
+            self.functions
+                .iter()
+                .map(|func| func.func.sig.ident.clone()),
+        );
+        symbols
+    }
+}
+
 #[derive(Clone)]
 pub struct MaybeUnsafeFn {
-    pub _attrs: Vec<Attribute>,
-    pub _unsafe: Option<Token![unsafe]>,
+    pub attrs: Vec<Attribute>,
+    pub unsafety: Option<Token![unsafe]>,
     pub func: ItemFn,
 }
 
 impl Parse for MaybeUnsafeFn {
     fn parse(input: ParseStream) -> Result<Self> {
         Ok(Self {
-            _attrs: Attribute::parse_outer(input)?,
-            _unsafe: input.parse()?,
+            attrs: Attribute::parse_outer(input)?,
+            unsafety: input.parse()?,
             func: input.parse()?,
         })
     }
 }
+
+impl MaybeUnsafeFn {
+    /// Generate the function exported from the plugin, which rebuilds
+    /// any lowered arguments and calls this function. Methods are
+    /// implemented on the plugin's `state`, and called on the instance
+    /// whose handle the host passes first.
+    /// Functions are exported by name with the attribute `no_mangle`.
+    pub fn export(
+        &self,
+        state: Option<&TypePath>,
+        spawner: Option<&Path>,
+        no_mangle: &TokenStream2,
+    ) -> TokenStream2 {
+        let mut func = self.func.clone();
+        func.sig.unsafety = self.unsafety;
+        if func.sig.asyncness.is_some() {
+            return export_async(&func, spawner, no_mangle);
+        }
+        let name = &func.sig.ident;
+        let (method, instance) = match (state, func.sig.inputs.first()) {
+            (Some(state), Some(FnArg::Receiver(receiver))) => {
+                let instance = if receiver.mutability.is_some() {
+                    quote!(&mut *instance.cast::<#state>())
+                } else {
+                    quote!(&*instance.cast::<#state>())
+                };
+                (Some(state), Some(instance))
+            }
+            _ => (None, None),
+        };
+        let args = func
+            .sig
+            .inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, arg)| crate::lowering::client_arg(arg, idx))
+            .collect::<Vec<_>>();
+        let instance_param = instance
+            .is_some()
+            .then(|| quote!(instance: *mut ::std::ffi::c_void));
+        let abi_params = instance_param
+            .iter()
+            .chain(args.iter().flat_map(|arg| &arg.abi_params));
+        let values = instance.iter().chain(args.iter().map(|arg| &arg.value));
+        // Methods must be defined outside of the exported function
+        let (outer, inner, call) = if let Some(state) = method {
+            (
+                quote!(impl #state { #func }),
+                quote!(),
+                quote!(<#state>::#name),
+            )
+        } else {
+            (quote!(), quote!(#func), quote!(#name))
+        };
+        let (ret, convert) = crate::lowering::client_return(&func.sig.output);
+        // Panics are caught and recorded, and an uninitialised value
+        // returned in place of the real one
+        let (ret, body) = if let Some(ret) = ret {
+            (
+                quote!(-> ::std::mem::MaybeUninit<#ret>),
+                quote!(::dynamic_plugin::__private::catch_panic(|| unsafe { #convert(#call(#(#values),*)) })),
+            )
+        } else {
+            (
+                quote!(),
+                quote!(let _ = ::dynamic_plugin::__private::catch_panic(|| unsafe { #call(#(#values),*) });),
+            )
+        };
+        // Rebuilding lowered arguments or the instance dereferences
+        // pointers from the host
+        let unsafe_ = if func.sig.unsafety.is_some()
+            || method.is_some()
+            || args.iter().any(|arg| arg.lowered)
+        {
+            Some(quote!(unsafe))
+        } else {
+            None
+        };
+        quote! {
+            #outer
+
+            #no_mangle
+            #[allow(unused_unsafe)]
+            pub #unsafe_ extern "C" fn #name(#(#abi_params),*) #ret {
+                #inner
+
+                #body
+            }
+        }
+    }
+
+    /// Whether this function is marked with `#[name]`.
+    pub fn has_attr(&self, name: &str) -> bool {
+        self.attrs.iter().any(|attr| attr.path().is_ident(name))
+    }
+}
+
+/// Generate the function exported from the plugin for the `async`
+/// function `func`. It copies any borrowed arguments, as the host only
+/// keeps them until it returns, then runs the function as a task on
+/// `spawner` which calls back to the host when it completes.
+fn export_async(func: &ItemFn, spawner: Option<&Path>, no_mangle: &TokenStream2) -> TokenStream2 {
+    let name = &func.sig.ident;
+    let args = func
+        .sig
+        .inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, arg)| Some((idx, crate::lowering::client_arg(arg, idx)?)))
+        .collect::<Vec<_>>();
+    let abi_params = args.iter().flat_map(|(_, arg)| &arg.abi_params);
+    let mut owned = vec![];
+    let mut values = vec![];
+    for (idx, arg) in &args {
+        let name = format_ident!("arg{idx}");
+        let value = &arg.value;
+        if arg.lowered {
+            owned.push(quote!(let #name = ::std::borrow::ToOwned::to_owned(#value);));
+            values.push(quote!(&#name));
+        } else {
+            values.push(quote!(#name));
+        }
+    }
+    let (ret, convert) = crate::lowering::client_return(&func.sig.output);
+    // The host is passed an uninitialised value if the function panics
+    let (complete_ty, complete) = if let Some(ret) = ret {
+        (
+            quote!(unsafe extern "C" fn(*mut ::std::ffi::c_void, ::std::mem::MaybeUninit<#ret>, *const ::dynamic_plugin::libc::c_char)),
+            quote!(move |ret, panic| unsafe { complete(context.get(), ret, panic) }),
+        )
+    } else {
+        (
+            quote!(
+                unsafe extern "C" fn(
+                    *mut ::std::ffi::c_void,
+                    *const ::dynamic_plugin::libc::c_char,
+                )
+            ),
+            quote!(move |_, panic| unsafe { complete(context.get(), panic) }),
+        )
+    };
+    let spawner = spawner.map_or_else(
+        || quote!(::std::option::Option::None),
+        |spawner| quote!(::std::option::Option::Some(#spawner)),
+    );
+    let unsafe_ = (func.sig.unsafety.is_some() || args.iter().any(|(_, arg)| arg.lowered))
+        .then(|| quote!(unsafe));
+    quote! {
+        #no_mangle
+        #[allow(unused_unsafe)]
+        pub #unsafe_ extern "C" fn #name(#(#abi_params,)* complete: #complete_ty, context: *mut ::std::ffi::c_void) {
+            #func
+
+            let context = ::dynamic_plugin::__private::AsyncContext::new(context);
+            ::dynamic_plugin::__private::spawn_async(
+                &_DYNAMIC_PLUGIN_TASKS,
+                #spawner,
+                || unsafe {
+                    #(#owned)*
+                    async move { #convert(#name(#(#values),*).await) }
+                },
+                #complete,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    use super::PluginImplementation;
+    use crate::def::PluginDefinition;
+
+    fn signature(value: &impl Hash) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn parse(implementation: &str) -> syn::Result<PluginImplementation> {
+        syn::parse_str::<PluginImplementation>(implementation)
+    }
+
+    #[test]
+    fn exports_functions_by_name_by_default() {
+        let plugin = parse("Plugin, fn f() {}").unwrap();
+        assert!(!plugin.static_link);
+        assert!(!plugin.no_mangle().is_empty());
+    }
+
+    #[test]
+    fn selects_linkage_per_plugin() {
+        let plugin = parse("Plugin, link: static, fn f() {}").unwrap();
+        assert!(plugin.static_link);
+        assert!(plugin.no_mangle().is_empty());
+        assert!(
+            !parse("Plugin, link: dynamic, fn f() {}")
+                .unwrap()
+                .static_link
+        );
+        assert!(parse("Plugin, link: both, fn f() {}").is_err());
+    }
+
+    #[test]
+    fn agrees_to_non_reentrant_interfaces() {
+        let definition = |definition| syn::parse_str::<PluginDefinition>(definition).unwrap();
+
+        let plugin = parse("Plugin, reentrant: false, fn f() {}").unwrap();
+        assert!(plugin.non_reentrant);
+        assert_eq!(
+            signature(&plugin),
+            signature(&definition(
+                "#[non_reentrant] extern trait Plugin { fn f(); }"
+            ))
+        );
+        let plugin = parse("Plugin, fn f() {}").unwrap();
+        assert_eq!(
+            signature(&plugin),
+            signature(&definition("extern trait Plugin { fn f(); }"))
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! Calling plugin functions in the worker process of an isolated plugin.
+//!
+//! The host method encodes its arguments and sends them to the worker,
+//! where the interface's `__isolated_call` function decodes them, calls
+//! the plugin, and encodes the results to send back.
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{FnArg, Type};
+
+use crate::{
+    def::PluginFunction,
+    lowering::{self, ArgLowering, ReturnLowering},
+};
+
+/// The primitive types which can be sent to another process.
+const PRIMITIVES: &[&str] = &[
+    "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32",
+    "f64", "bool",
+];
+
+/// Generate the code in a host method which calls `pf` in the worker,
+/// if `library` is isolated.
+pub fn host_call(
+    pf: &PluginFunction,
+    library: &TokenStream2,
+    handle: Option<&TokenStream2>,
+) -> TokenStream2 {
+    let function_name = pf.name.to_string();
+    let body = if isolatable(pf) {
+        let mut encode_args = vec![];
+        let mut read_backs = vec![];
+        for (idx, arg) in pf.arguments.iter().enumerate() {
+            let (Some(host_arg), FnArg::Typed(typed)) = (lowering::host_arg(arg, idx), arg) else {
+                continue;
+            };
+            let name = &host_arg.name;
+            encode_args.push(match ArgLowering::of(&typed.ty) {
+                ArgLowering::Plain => encode(&typed.ty, &quote!(#name), &quote!(worker_args)),
+                // The prelude has made a C string
+                ArgLowering::Str { .. } => quote!(worker_args.write_bytes(#name.to_bytes());),
+                ArgLowering::Slice { mutable, .. } => {
+                    if mutable {
+                        read_backs.push(quote!(worker_reply.read_slice_into(#name)?;));
+                    }
+                    quote!(worker_args.write_slice(#name);)
+                }
+            });
+        }
+        let handle = handle.map_or_else(|| quote!(::std::ptr::null_mut()), Clone::clone);
+        let timeout = pf.timeout.map_or_else(
+            || quote!(::std::option::Option::None),
+            |millis| quote!(::std::option::Option::Some(::std::time::Duration::from_millis(#millis))),
+        );
+        let result = pf
+            .return_type
+            .as_ref()
+            .map_or_else(|| quote!(()), |typ| decode(typ, &quote!(worker_reply)));
+        quote! {
+            let mut worker_args = ::dynamic_plugin::__private::Writer::new();
+            #(#encode_args)*
+            let worker_reply = worker.call(#function_name, #handle, &worker_args, #timeout)?;
+            let mut worker_reply = ::dynamic_plugin::__private::Reader::new(&worker_reply);
+            #(#read_backs)*
+            let ret = #result;
+            return Ok(ret);
+        }
+    } else {
+        let message = if pf.asyncness.is_some() {
+            format!("`{function_name}` is async, which isolated plugins don't support")
+        } else {
+            format!(
+                "`{function_name}` takes or returns a type which can't be sent to an isolated plugin"
+            )
+        };
+        quote! {
+            return Err(::dynamic_plugin::Error::Isolation(#message.to_string()));
+        }
+    };
+    quote! {
+        #[cfg(unix)]
+        if let ::dynamic_plugin::PluginLibrary::Isolated { worker, .. } = &#library {
+            #body
+        }
+    }
+}
+
+/// Generate the functions which run the worker for an interface with
+/// `functions` and `signature`.
+pub fn worker(functions: &[PluginFunction], signature: u64) -> TokenStream2 {
+    let arms = functions
+        .iter()
+        .filter(|pf| isolatable(pf))
+        .map(worker_call);
+    quote! {
+        /// Serve this interface and exit, if this process was started
+        /// as the worker for an isolated plugin. Otherwise, return
+        /// immediately.
+        ///
+        /// Workers run the host's own executable, so hosts which load
+        /// plugins with `PluginLoader::isolate` must call this at the
+        /// start of `main`.
+        #[cfg(unix)]
+        pub fn run_isolated_worker() {
+            ::dynamic_plugin::__private::run_isolated_worker(#signature, Self::__isolated_call);
+        }
+
+        #[doc(hidden)]
+        #[cfg(unix)]
+        #[allow(unused_unsafe, unused_variables)]
+        pub unsafe fn __isolated_call(
+            library: &::dynamic_plugin::PluginLibrary,
+            function: &str,
+            handle: *mut ::std::ffi::c_void,
+            args: &mut ::dynamic_plugin::__private::Reader<'_>,
+            results: &mut ::dynamic_plugin::__private::Writer,
+        ) -> ::dynamic_plugin::Result<()> {
+            unsafe {
+                match function {
+                    #(#arms)*
+                    _ => Err(::dynamic_plugin::Error::Isolation(format!("the plugin has no function `{function}` to call"))),
+                }
+            }
+        }
+    }
+}
+
+/// Generate the arm of `__isolated_call` which calls `pf` in the worker.
+fn worker_call(pf: &PluginFunction) -> TokenStream2 {
+    let name = &pf.name;
+    let function_name = name.to_string();
+    let name_as_str = format!(r#"b"{name}""#).parse::<TokenStream2>().unwrap();
+    let mut decode_args = vec![];
+    let mut arg_values = vec![];
+    let mut write_backs = vec![];
+    if pf.receiver().is_some() {
+        arg_values.push(quote!(handle));
+    }
+    for (idx, arg) in pf.arguments.iter().enumerate() {
+        let FnArg::Typed(typed) = arg else {
+            continue;
+        };
+        let ty = &typed.ty;
+        let arg = format_ident!("arg{idx}");
+        match ArgLowering::of(ty) {
+            ArgLowering::Plain => {
+                let value = decode(ty, &quote!(args));
+                decode_args.push(quote!(let #arg = #value;));
+                arg_values.push(quote!(#arg));
+            }
+            ArgLowering::Str { .. } => {
+                decode_args.push(quote!(let #arg = args.read_c_string()?;));
+                arg_values.push(quote!(#arg.as_ptr()));
+            }
+            ArgLowering::Slice { elem, mutable } => {
+                if mutable {
+                    decode_args.push(quote!(let mut #arg = args.read_vec::<#elem>()?;));
+                    arg_values.push(quote!(#arg.as_mut_ptr()));
+                    write_backs.push(quote!(results.write_slice(&#arg);));
+                } else {
+                    decode_args.push(quote!(let #arg = args.read_vec::<#elem>()?;));
+                    arg_values.push(quote!(#arg.as_ptr()));
+                }
+                arg_values.push(quote!(#arg.len()));
+            }
+        }
+    }
+    let (sig, _, result) = crate::abi_signature(pf);
+    let encode_result = pf
+        .return_type
+        .as_ref()
+        .map(|typ| encode(typ, &quote!(ret), &quote!(results)));
+    quote! {
+        #function_name => {
+            #(#decode_args)*
+            let func: #sig = library.get(#name_as_str)?;
+            let ret = func(#(#arg_values),*);
+            ::dynamic_plugin::__private::check_panic(library, #function_name)?;
+            #(#write_backs)*
+            let ret = #result?;
+            #encode_result
+            Ok(())
+        }
+    }
+}
+
+/// Whether `pf` can be called in a worker. It must return before the
+/// host's call does, and its arguments and return value must be able to
+/// be sent between the host and the worker.
+fn isolatable(pf: &PluginFunction) -> bool {
+    pf.asyncness.is_none()
+        && pf.arguments.iter().all(|arg| match arg {
+            FnArg::Typed(typed) => isolatable_type(&typed.ty),
+            FnArg::Receiver(_) => true,
+        })
+        && pf.return_type.as_ref().is_none_or(isolatable_type)
+}
+
+/// Whether values of `ty` can be sent to another process. Other types
+/// may hold pointers, which mean nothing there, or have values which
+/// aren't valid, so only strings, buffers, slices, results and errors
+/// are sent, along with the primitives `WireValue` is implemented for
+/// and arrays of them.
+fn isolatable_type(ty: &Type) -> bool {
+    match ty {
+        Type::Array(array) => isolatable_type(&array.elem),
+        Type::Group(inner) => isolatable_type(&inner.elem),
+        Type::Paren(inner) => isolatable_type(&inner.elem),
+        Type::Tuple(tuple) => tuple.elems.is_empty(),
+        Type::Reference(_) => match ArgLowering::of(ty) {
+            ArgLowering::Slice { elem, .. } => isolatable_type(&elem),
+            ArgLowering::Str { .. } => true,
+            ArgLowering::Plain => false,
+        },
+        Type::Path(path) => match ReturnLowering::of(ty) {
+            ReturnLowering::Result { ok, err } => isolatable_type(&ok) && isolatable_type(&err),
+            ReturnLowering::String | ReturnLowering::Buffer => true,
+            ReturnLowering::Plain => {
+                last_ident_is(ty, "PluginError")
+                    || matches!(ArgLowering::of(ty), ArgLowering::Str { .. })
+                    || path.qself.is_none()
+                        && path.path.get_ident().is_some_and(|ident| {
+                            PRIMITIVES.iter().any(|primitive| ident == primitive)
+                        })
+            }
+        },
+        _ => false,
+    }
+}
+
+/// Generate the code which writes `value`, a value of `ty` as returned
+/// by a host method, to `writer`.
+fn encode(ty: &Type, value: &TokenStream2, writer: &TokenStream2) -> TokenStream2 {
+    match ReturnLowering::of(ty) {
+        ReturnLowering::String => quote!(#writer.write_str(&#value);),
+        ReturnLowering::Buffer => quote!(#writer.write_bytes(&#value);),
+        ReturnLowering::Result { ok, err } => {
+            let encode_ok = encode(&ok, &quote!(value), writer);
+            let encode_err = encode(&err, &quote!(error), writer);
+            quote! {
+                match #value {
+                    ::std::result::Result::Ok(value) => {
+                        #writer.write_bool(true);
+                        #encode_ok
+                    }
+                    ::std::result::Result::Err(error) => {
+                        #writer.write_bool(false);
+                        #encode_err
+                    }
+                }
+            }
+        }
+        ReturnLowering::Plain if last_ident_is(ty, "PluginError") => {
+            quote!(#writer.write_plugin_error(&#value);)
+        }
+        ReturnLowering::Plain => quote!(#writer.write_value(&#value);),
+    }
+}
+
+/// Generate the expression which reads a value of `ty`, as returned by
+/// a host method, from `reader`.
+fn decode(ty: &Type, reader: &TokenStream2) -> TokenStream2 {
+    match ReturnLowering::of(ty) {
+        ReturnLowering::String if last_ident_is(ty, "PluginString") => {
+            quote!(::dynamic_plugin::PluginString::from(#reader.read_str()?))
+        }
+        ReturnLowering::String => quote!(#reader.read_str()?),
+        ReturnLowering::Buffer if last_ident_is(ty, "PluginBuffer") => {
+            quote!(::dynamic_plugin::PluginBuffer::from(#reader.read_bytes()?))
+        }
+        ReturnLowering::Buffer => quote!(#reader.read_bytes()?),
+        ReturnLowering::Result { ok, err } => {
+            let decode_ok = decode(&ok, reader);
+            let decode_err = decode(&err, reader);
+            quote! {
+                if #reader.read_bool()? {
+                    ::std::result::Result::Ok(#decode_ok)
+                } else {
+                    ::std::result::Result::Err(#decode_err)
+                }
+            }
+        }
+        ReturnLowering::Plain if last_ident_is(ty, "PluginError") => {
+            quote!(#reader.read_plugin_error()?)
+        }
+        ReturnLowering::Plain => quote!(#reader.read_value::<#ty>()?),
+    }
+}
+
+/// Whether `ty` is a path ending in `ident`.
+fn last_ident_is(ty: &Type, ident: &str) -> bool {
+    matches!(ty, Type::Path(path) if path.path.segments.last().is_some_and(|seg| seg.ident == ident))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::isolatable_type;
+
+    fn isolatable(ty: &str) -> bool {
+        isolatable_type(&syn::parse_str(ty).unwrap())
+    }
+
+    #[test]
+    fn sends_primitives_strings_and_buffers() {
+        for ty in [
+            "u32",
+            "f64",
+            "bool",
+            "()",
+            "[[u8; 4]; 2]",
+            "&[i64]",
+            "&mut [u8]",
+            "PluginStr",
+            "PluginString",
+            "String",
+            "Vec<u8>",
+            "PluginBuffer",
+            "Result<(), PluginError>",
+            "Result<[u16; 3], PluginError>",
+        ] {
+            assert!(isolatable(ty), "{ty}");
+        }
+    }
+
+    #[test]
+    fn rejects_other_types() {
+        for ty in [
+            "*const u8",
+            "extern \"C\" fn()",
+            "&u32",
+            "char",
+            "Point",
+            "std::ffi::c_int",
+            "Option<u32>",
+            "Vec<u32>",
+            "(u32, u32)",
+            "[*mut u8; 2]",
+            "&[Point]",
+            "Result<Point, PluginError>",
+        ] {
+            assert!(!isolatable(ty), "{ty}");
+        }
+    }
+}
@@ -3,20 +3,31 @@
 
 //! # Macros for the [`dynamic-plugin`](https://docs.rs/dynamic-plugin/latest/dynamic_plugin/) crate.
 
-use std::hash::{Hash, Hasher};
+use std::{
+    fmt::Write,
+    hash::{Hash, Hasher},
+};
 
 use def::PluginDefinition;
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
 use proc_macro_error2::{abort, proc_macro_error};
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::{parse_macro_input, FnArg, Lit, ReturnType, Type};
 
-use crate::hasher::PluginSignatureHasher;
+use crate::{hasher::PluginSignatureHasher, threading::Threading};
 
+mod blocking;
 mod def;
 mod hasher;
+#[cfg(feature = "client")]
 mod implementation;
+mod isolation;
+mod lowering;
+#[cfg(feature = "client")]
+mod metadata;
+mod threading;
+mod trace;
 
 /// Define an interface for a plugin. See the `dynamic_plugin` crate documentation for more.
 ///
@@ -27,7 +38,7 @@ mod implementation;
 ///         /// Ask the plugin to do a thing
 ///         fn do_a_thing();
 ///         /// Say hello to a person
-///         fn say_hello(to: *const c_char) -> bool;
+///         fn say_hello(to: PluginStr) -> bool;
 ///     }
 /// }
 /// ```
@@ -46,7 +57,6 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
         {
             let hash_debug = format!("{hasher:?}");
             Some(quote! {
-                #[no_mangle]
                 pub fn _dynamic_plugin_signature_unhashed() -> &'static str {
                     #hash_debug
                 }
@@ -58,38 +68,152 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
         }
     };
 
+    let call_limit_field = threading::call_limit_field(plugin_def.non_reentrant);
+    let threading_field = plugin_def.threading.field();
+    // Fields added to the plugin for `non_reentrant` and `threading`
+    let extra_init = threading::call_limit_init(plugin_def.non_reentrant)
+        .into_iter()
+        .chain(plugin_def.threading.init())
+        .collect::<TokenStream2>();
     let host_impl = if cfg!(feature = "host") {
-        let funcs = plugin_def.functions.iter().map(|pf| {
-            let attributes = &pf.attributes;
-            let name = &pf.name;
-            let name_as_str = format!(r#"b"{name}""#).parse::<TokenStream2>().unwrap();
-            let args = &pf.arguments;
-            let mut arg_types = vec![];
-            let mut arg_names = vec![];
-            for arg in args {
-                if let FnArg::Typed(typed) = arg {
-                    arg_types.push(typed.ty.clone());
-                    arg_names.push(typed.pat.clone());
-                }
-            }
-            let ret = if let Some(typ) = &pf.return_type { quote! { #typ } } else { quote! { () } };
-            let sig = quote! { unsafe extern fn(#(#arg_types),*) -> #ret };
+        let (methods, funcs): (Vec<_>, Vec<_>) = plugin_def
+            .functions
+            .iter()
+            .partition(|pf| pf.receiver().is_some());
+        let (api_decls, api_impls): (Vec<_>, Vec<_>) = funcs.iter().copied().map(api_method).unzip();
+        let async_funcs = funcs
+            .iter()
+            .filter_map(|pf| blocking::async_method(pf, &plugin_def));
+        let funcs = funcs
+            .iter()
+            .map(|pf| host_method(pf, &plugin_def));
+        let api_ident = format_ident!("{plugin_ident}Api");
+        let api_doc = format!(
+            "The functions of a [`{plugin_ident}`], so that hosts can use other implementations of the interface, such as ones built into the host or mocks for testing."
+        );
+
+        let instance_fns = if methods.is_empty() {
+            vec![]
+        } else {
+            vec!["_dynamic_plugin_create".to_string(), "_dynamic_plugin_destroy".to_string()]
+        };
+        let worker = isolation::worker(&plugin_def.functions, hash);
+        let fn_checks = plugin_def
+            .functions
+            .iter()
+            .map(|f| f.name.to_string())
+            .chain(instance_fns)
+            .map(|name_bytes| {
+                quote! {
+                    let _: ::dynamic_plugin::PluginLibrarySymbol<unsafe extern fn()> =
+                        library.get(#name_bytes.as_bytes()).map_err(|_| ::dynamic_plugin::Error::NotAPlugin)?;
+                }
+            });
+
+        let load_with = trace::load(
+            plugin_ident,
             quote! {
-                #(#attributes)*
-                pub extern "C" fn #name(&self, #(#args),*) -> ::dynamic_plugin::Result<#ret> {
-                    unsafe {
-                        let func: ::dynamic_plugin::PluginLibrarySymbol<#sig> = self.library.get(#name_as_str)?;
-                        Ok(func(#(#arg_names),*))
-                    }
+                let library = loader.open(path, #hash)?;
+                let metadata = loader.initialise(&library)?;
+
+                Ok(Self {
+                    library: ::std::mem::ManuallyDrop::new(library),
+                    metadata,
+                    #extra_init
+                })
+            },
+        );
+        let load_compat = trace::load(
+            plugin_ident,
+            quote! {
+                ::dynamic_plugin::__private::check_header(&path)?;
+
+                unsafe {
+                    // Attempt to load library
+                    let library = ::dynamic_plugin::PluginDynamicLibrary::new(&path)?;
+
+                    // Check that each function exists
+                    #(#fn_checks)*
+
+                    let library = ::dynamic_plugin::PluginLibrary::Dynamic {
+                        library,
+                        path: ::std::path::PathBuf::from(path.as_ref()),
+                        digest: ::std::option::Option::None,
+                    };
+                    let metadata = ::dynamic_plugin::PluginLoader::new().initialise(&library)?;
+
+                    Ok(Self {
+                        library: ::std::mem::ManuallyDrop::new(library),
+                        metadata,
+                        #extra_init
+                    })
                 }
-            }
-        });
+            },
+        );
+        let trace_report = trace::report(plugin_ident);
 
-        let fn_checks = plugin_def.functions.iter().map(|f| {
-            let name_bytes = f.name.to_string();
+        let instance = (!methods.is_empty()).then(|| {
+            let instance_ident = format_ident!("{plugin_ident}Instance");
+            let instance_doc = format!("An instance of a [`{plugin_ident}`], holding its own plugin state.");
+            let instance_api_ident = format_ident!("{plugin_ident}InstanceApi");
+            let instance_api_doc = format!("The methods of a [`{instance_ident}`], so that hosts can use other implementations of them.");
+            let create_guard = plugin_def.threading.guard(&quote!(self), "create_instance");
+            let create_call_guard = threading::call_limit_guard(plugin_def.non_reentrant, &quote!(self), "create_instance");
+            let destroy_guard = plugin_def.threading.guard(&quote!(self.plugin), "drop");
+            let destroy_call_guard = threading::call_limit_guard(plugin_def.non_reentrant, &quote!(self.plugin), "drop");
+            let (api_decls, api_impls): (Vec<_>, Vec<_>) = methods.iter().copied().map(api_method).unzip();
+            let methods = methods
+                .into_iter()
+                .map(|pf| host_method(pf, &plugin_def));
             quote! {
-                let _: ::dynamic_plugin::PluginLibrarySymbol<unsafe extern fn()> =
-                    library.get(#name_bytes.as_bytes()).map_err(|_| ::dynamic_plugin::Error::NotAPlugin)?;
+                #[doc = #instance_doc]
+                pub struct #instance_ident<'a> {
+                    plugin: &'a #plugin_ident,
+                    handle: *mut ::std::ffi::c_void,
+                }
+
+                impl #plugin_ident {
+                    /// Create a new instance of the plugin, with its own state.
+                    ///
+                    /// # Errors
+                    ///
+                    /// - [`::dynamic_plugin::Error::PluginPanicked`] if the plugin panics while creating its state.
+                    /// - [`::dynamic_plugin::Error::Reentrant`] if the plugin runs one call at a time, and this thread is already calling it.
+                    pub fn create_instance(&self) -> ::dynamic_plugin::Result<#instance_ident<'_>> {
+                        #create_guard
+                        #create_call_guard
+                        Ok(#instance_ident {
+                            plugin: self,
+                            handle: unsafe { ::dynamic_plugin::__private::create_instance(&self.library)? },
+                        })
+                    }
+                }
+
+                impl #instance_ident<'_> {
+                    #(#methods)*
+                }
+
+                #[doc = #instance_api_doc]
+                pub trait #instance_api_ident {
+                    #(#api_decls)*
+                }
+
+                impl #instance_api_ident for #instance_ident<'_> {
+                    #(#api_impls)*
+                }
+
+                impl ::std::ops::Drop for #instance_ident<'_> {
+                    fn drop(&mut self) {
+                        // An instance dropped by a re-entrant call is leaked,
+                        // as destroying it would wait for the call to return
+                        let _ = (|| -> ::dynamic_plugin::Result<()> {
+                            #destroy_guard
+                            #destroy_call_guard
+                            unsafe { ::dynamic_plugin::__private::destroy_instance(&self.plugin.library, self.handle) };
+                            Ok(())
+                        })();
+                    }
+                }
             }
         });
 
@@ -102,21 +226,52 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
                 where
                     P: ::std::convert::AsRef<::std::path::Path>,
                 {
-                    let mut plugins = vec![];
+                    Self::find_plugins_with(path, &::dynamic_plugin::PluginLoader::new())
+                }
+
+                /// Search `path` to find compatible plugins, loading them with `loader`.
+                pub fn find_plugins_with<P>(path: P, loader: &::dynamic_plugin::PluginLoader) -> ::std::vec::Vec<Self>
+                where
+                    P: ::std::convert::AsRef<::std::path::Path>,
+                {
+                    Self::discover_plugins_with(path, loader).loaded
+                }
+
+                /// Search `path` to find compatible plugins, reporting
+                /// why each other file was not loaded.
+                pub fn discover_plugins<P>(path: P) -> ::dynamic_plugin::DiscoveryReport<Self>
+                where
+                    P: ::std::convert::AsRef<::std::path::Path>,
+                {
+                    Self::discover_plugins_with(path, &::dynamic_plugin::PluginLoader::new())
+                }
+
+                /// Search `path` to find compatible plugins, loading them
+                /// with `loader` and reporting why each other file was
+                /// not loaded.
+                ///
+                /// Plugins are loaded after the plugins they depend on,
+                /// and are not loaded if any of their dependencies are
+                /// missing or they depend on themselves.
+                pub fn discover_plugins_with<P>(path: P, loader: &::dynamic_plugin::PluginLoader) -> ::dynamic_plugin::DiscoveryReport<Self>
+                where
+                    P: ::std::convert::AsRef<::std::path::Path>,
+                {
+                    let mut report = ::dynamic_plugin::DiscoveryReport::default();
 
                     // Iterate through directory entries
-                    if let Ok(paths) = ::std::fs::read_dir(path) {
-                        for path in paths {
-                            if let Ok(path) = path {
-                                // Try to load each potential plugin. Catch errors and ignore.
-                                if let Ok(plugin) = Self::load_plugin_and_check(path.path()) {
-                                    plugins.push(plugin);
-                                }
-                            }
-                        }
-                    }
+                    let paths = ::std::fs::read_dir(path)
+                        .map(|paths| paths.flatten().map(|path| path.path()).collect())
+                        .unwrap_or_default();
 
-                    plugins
+                    loader.load_in_order(paths, #hash, &mut report, |library, metadata| Self {
+                        library: ::std::mem::ManuallyDrop::new(library),
+                        metadata,
+                        #extra_init
+                    });
+                    #trace_report
+
+                    report
                 }
 
                 /// Load the plugin at `path`
@@ -142,26 +297,25 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
                 where
                     P: ::std::convert::AsRef<::std::ffi::OsStr>,
                 {
-                    unsafe {
-                        // Attempt to load library
-                        let library = ::dynamic_plugin::PluginDynamicLibrary::new(path)?;
-
-                        // Check that signature function exists
-                        let func: ::dynamic_plugin::PluginLibrarySymbol<unsafe extern fn() -> u64> =
-                            library.get(b"_dynamic_plugin_signature").map_err(|_| ::dynamic_plugin::Error::NotAPlugin)?;
-                        if check_signature {
-                            // Check plugin library signature
-                            let hash = func();
-
-                            if hash != #hash {
-                                return ::dynamic_plugin::Result::Err(::dynamic_plugin::Error::InvalidPluginSignature);
-                            }
-                        }
+                    Self::load_plugin_with(path, &::dynamic_plugin::PluginLoader::new().check_signature(check_signature))
+                }
 
-                        Ok(Self {
-                            library,
-                        })
-                    }
+                /// Load the plugin at `path` with the options set on `loader`
+                ///
+                /// # Errors
+                ///
+                /// - [`::dynamic_plugin::Error::NotAPlugin`] if the file provided is determined not to be a compatible (dynamic_plugin style) plugin.
+                /// - [`::dynamic_plugin::Error::InvalidPluginSignature`] if the loader checks signatures and the signature does not match.
+                /// - [`::dynamic_plugin::Error::Filtered`] if the plugin is rejected by the loader's filter.
+                /// - [`::dynamic_plugin::Error::MissingDependency`] if a plugin it depends on is not loaded.
+                /// - [`::dynamic_plugin::Error::MissingHostApi`] if the plugin uses a host API and the loader doesn't provide one.
+                /// - [`::dynamic_plugin::Error::InvalidHostApiSignature`] if the plugin uses a different host API to the loader.
+                /// - [`::dynamic_plugin::Error::InitFailed`] if the plugin's `on_load` hook fails.
+                pub fn load_plugin_with<P>(path: P, loader: &::dynamic_plugin::PluginLoader) -> ::dynamic_plugin::Result<Self>
+                where
+                    P: ::std::convert::AsRef<::std::ffi::OsStr>,
+                {
+                    #load_with
                 }
 
                 /// Load the plugin at `path`, checking if it is valid
@@ -178,26 +332,108 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
                 ///
                 /// # Errors
                 ///
+                /// - [`::dynamic_plugin::Error::NotASharedLibrary`] if the file provided is not a shared library for the host's platform.
+                /// - [`::dynamic_plugin::Error::WrongArchitecture`] if the library is for a different architecture.
                 /// - [`::dynamic_plugin::Error::NotAPlugin`] if the file provided is determined not to be a compatible plugin, i.e. not having the required functions present and exposed.
+                /// - [`::dynamic_plugin::Error::InitFailed`] if the plugin's `on_load` hook fails.
                 pub fn load_plugin_and_check_compat<P>(path: P) -> ::dynamic_plugin::Result<Self>
                 where
                     P: ::std::convert::AsRef<::std::ffi::OsStr>,
                 {
-                    unsafe {
-                        // Attempt to load library
-                        let library = ::dynamic_plugin::PluginDynamicLibrary::new(path)?;
+                    #load_compat
+                }
+
+                /// Use a plugin linked statically into the host. Its
+                /// signature was checked when it was compiled.
+                ///
+                /// # Errors
+                ///
+                /// - [`::dynamic_plugin::Error::MissingHostApi`] if the plugin uses a host API.
+                /// - [`::dynamic_plugin::Error::InitFailed`] if the plugin's `on_load` hook fails.
+                pub fn from_static(plugin: &'static ::dynamic_plugin::StaticPlugin<Self>) -> ::dynamic_plugin::Result<Self> {
+                    Self::from_static_with(plugin, &::dynamic_plugin::PluginLoader::new())
+                }
+
+                /// Use a plugin linked statically into the host, with
+                /// the options set on `loader`. Its signature was
+                /// checked when it was compiled.
+                ///
+                /// # Errors
+                ///
+                /// - [`::dynamic_plugin::Error::Filtered`] if the plugin is rejected by the loader's filter.
+                /// - [`::dynamic_plugin::Error::MissingDependency`] if a plugin it depends on is not loaded.
+                /// - [`::dynamic_plugin::Error::MissingHostApi`] if the plugin uses a host API and the loader doesn't provide one.
+                /// - [`::dynamic_plugin::Error::InvalidHostApiSignature`] if the plugin uses a different host API to the loader.
+                /// - [`::dynamic_plugin::Error::InitFailed`] if the plugin's `on_load` hook fails.
+                pub fn from_static_with(
+                    plugin: &'static ::dynamic_plugin::StaticPlugin<Self>,
+                    loader: &::dynamic_plugin::PluginLoader,
+                ) -> ::dynamic_plugin::Result<Self> {
+                    let library = plugin.library();
+                    let metadata = loader.initialise(&library)?;
 
-                        // Check that each function exists
-                        #(#fn_checks)*
+                    Ok(Self {
+                        library: ::std::mem::ManuallyDrop::new(library),
+                        metadata,
+                        #extra_init
+                    })
+                }
 
-                        Ok(Self {
-                            library,
-                        })
-                    }
+                /// The SHA-256 digest of the plugin's library file, if
+                /// the loader that loaded it has an allowlist.
+                pub fn digest(&self) -> ::std::option::Option<&::dynamic_plugin::PluginDigest> {
+                    self.library.digest()
                 }
 
+                /// Whether the plugin can still be called. Isolated
+                /// plugins are unhealthy once their worker has crashed
+                /// or timed out.
+                pub fn is_healthy(&self) -> bool {
+                    self.library.is_healthy()
+                }
+
+                /// Kill the worker process of an isolated plugin, such
+                /// as when a call hangs. Calls in flight and later calls
+                /// return [`::dynamic_plugin::Error::WorkerKilled`]. This
+                /// does nothing for plugins which aren't isolated.
+                pub fn kill_worker(&self) {
+                    self.library.kill_worker();
+                }
+
+                /// The information the plugin gives about itself, if it
+                /// exports any.
+                pub fn metadata(&self) -> ::std::option::Option<&::dynamic_plugin::PluginMetadata> {
+                    self.metadata.as_ref()
+                }
+
+                #worker
+
                 #(#funcs)*
+
+                #(#async_funcs)*
             }
+
+            #[doc = #api_doc]
+            pub trait #api_ident {
+                #(#api_decls)*
+            }
+
+            impl #api_ident for #plugin_ident {
+                #(#api_impls)*
+            }
+
+            impl ::std::ops::Drop for #plugin_ident {
+                fn drop(&mut self) {
+                    // Give the plugin a chance to clean up before it is
+                    // unloaded, and leave it loaded if its `async` calls
+                    // are still running
+                    if unsafe { ::dynamic_plugin::__private::on_unload(&self.library) } {
+                        unsafe { ::std::mem::ManuallyDrop::drop(&mut self.library) };
+                    }
+                }
+            }
+
+            #instance
         })
     } else {
         None
@@ -206,8 +442,15 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
     let definition =
         {
             let mut s = String::new();
+            if plugin_def.threading != Threading::ThreadSafe {
+                let _ = writeln!(s, "threading: {},", plugin_def.threading.name());
+            }
+            if plugin_def.non_reentrant {
+                let _ = writeln!(s, "reentrant: false,");
+            }
             for def::PluginFunction {
                 attributes,
+                asyncness,
                 name,
                 arguments,
                 return_type,
@@ -215,34 +458,32 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
             } in &plugin_def.functions
             {
                 for attr in attributes {
-                    if attr.path().is_ident("doc") {
-                        match &attr.meta {
-                            syn::Meta::NameValue(inner) => {
-                                if inner.path.is_ident("doc") {
-                                    if let syn::Expr::Lit(expr) = &inner.value {
-                                        if let Lit::Str(doc) = &expr.lit {
-                                            s.push_str(&format!("/// {}\n", doc.value().trim()));
-                                        }
-                                    }
+                    if let syn::Meta::NameValue(inner) = &attr.meta {
+                        if inner.path.is_ident("doc") {
+                            if let syn::Expr::Lit(expr) = &inner.value {
+                                if let Lit::Str(doc) = &expr.lit {
+                                    let _ = writeln!(s, "/// {}", doc.value().trim());
                                 }
                             }
-                            _ => (),
                         }
                     }
                 }
+                if asyncness.is_some() {
+                    s.push_str("async ");
+                }
                 s.push_str("fn ");
                 s.push_str(&name.to_string());
                 s.push('(');
                 for (idx, arg) in arguments.iter().enumerate() {
                     match arg {
-                        FnArg::Receiver(..) => s.push_str("self"),
+                        FnArg::Receiver(receiver) => s.push_str(&receiver_to_string(receiver)),
                         FnArg::Typed(ty) => {
                             s.push_str("_: ");
                             s.push_str(&crate::type_to_string(*ty.ty.clone()).expect(
                                 "this should have failed earlier! please open a bug report!",
                             ));
                         }
-                    };
+                    }
                     if idx < arguments.len() - 1 {
                         s.push_str(", ");
                     }
@@ -263,7 +504,7 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
     let func_sigs = plugin_def.functions.iter().map(|f| {
         let func_name = f.name.to_string();
         let args = f.arguments.iter().map(|a| match a {
-            FnArg::Receiver(..) => "self".to_string(),
+            FnArg::Receiver(receiver) => receiver_to_string(receiver),
             FnArg::Typed(ty) => crate::type_to_string(*ty.ty.clone())
                 .expect("this should have failed earlier! please open a bug report!"),
         });
@@ -283,7 +524,10 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
 
     quote! {
         pub struct #plugin_ident {
-            library: ::dynamic_plugin::PluginDynamicLibrary,
+            library: ::std::mem::ManuallyDrop<::dynamic_plugin::PluginLibrary>,
+            metadata: ::std::option::Option<::dynamic_plugin::PluginMetadata>,
+            #call_limit_field
+            #threading_field
         }
 
         impl #plugin_ident {
@@ -308,6 +552,217 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
     .into()
 }
 
+/// Generate the host method which calls the plugin function `pf`, under
+/// the threading contract of `plugin_def`. Methods of plugin instances
+/// pass the instance's handle to the plugin before their other
+/// arguments.
+fn host_method(pf: &def::PluginFunction, plugin_def: &def::PluginDefinition) -> TokenStream2 {
+    let attributes = &pf.attributes;
+    let name = &pf.name;
+    let name_as_str = format!(r#"b"{name}""#).parse::<TokenStream2>().unwrap();
+    let args = pf
+        .arguments
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, arg)| lowering::host_arg(arg, idx))
+        .collect::<Vec<_>>();
+    let params = args.iter().map(|arg| &arg.param);
+    let generics = lowering::host_generics(&args);
+    let preludes = args.iter().map(|arg| &arg.prelude);
+    let receiver = host_receiver(pf);
+    let (plugin, handle) = if pf.receiver().is_some() {
+        (quote!(self.plugin), Some(quote!(self.handle)))
+    } else {
+        (quote!(self), None)
+    };
+    let library = quote!((*#plugin.library));
+    let function_name = name.to_string();
+    let guard = plugin_def.threading.guard(&plugin, &function_name);
+    let call_guard = threading::call_limit_guard(plugin_def.non_reentrant, &plugin, &function_name);
+    let arg_values = handle
+        .iter()
+        .cloned()
+        .chain(args.iter().flat_map(|arg| arg.abi_values.clone()));
+    let (sig, ret, result) = abi_signature(pf);
+    let isolated = isolation::host_call(pf, &library, handle.as_ref());
+    let (ret, call) = if pf.asyncness.is_some() {
+        (
+            quote!(::dynamic_plugin::PluginFuture<'_, #ret>),
+            async_call(pf, &args, &sig, &ret, &result),
+        )
+    } else {
+        let call = quote! {
+            unsafe {
+                let func: #sig = #library.get(#name_as_str)?;
+                let ret = func(#(#arg_values),*);
+                ::dynamic_plugin::__private::check_panic(&#library, #function_name)?;
+                #result
+            }
+        };
+        (ret, call)
+    };
+    let body = trace::call(
+        &plugin_def.name,
+        name,
+        pf.asyncness.is_some(),
+        &library,
+        quote! {
+            #(#preludes)*
+            #guard
+            #call_guard
+            #isolated
+            #call
+        },
+    );
+    quote! {
+        #(#attributes)*
+        pub fn #name #generics(#receiver, #(#params),*) -> ::dynamic_plugin::Result<#ret> {
+            #body
+        }
+    }
+}
+
+/// Generate the statements which start a call to the `async` plugin
+/// function `pf`, and return a future which resolves when the plugin
+/// calls back with its result.
+fn async_call(
+    pf: &def::PluginFunction,
+    args: &[lowering::HostArg],
+    sig: &TokenStream2,
+    ret: &TokenStream2,
+    result: &TokenStream2,
+) -> TokenStream2 {
+    let name = &pf.name;
+    let name_as_str = format!(r#"b"{name}""#).parse::<TokenStream2>().unwrap();
+    let function_name = name.to_string();
+    let arg_values = args.iter().flat_map(|arg| arg.abi_values.clone());
+    // The plugin calls back with an uninitialised value if it panics
+    let ret_param = pf.return_type.as_ref().map(|typ| {
+        let abi_ret = lowering::host_return(typ).0;
+        quote!(ret: ::std::mem::MaybeUninit<#abi_ret>,)
+    });
+    quote! {
+        unsafe extern "C" fn complete(
+            context: *mut ::std::ffi::c_void,
+            #ret_param
+            panic: *const ::dynamic_plugin::libc::c_char,
+        ) {
+            unsafe {
+                let result = ::dynamic_plugin::__private::async_result(panic, #function_name)
+                    .and_then(|()| #result);
+                ::dynamic_plugin::__private::complete_async::<#ret>(context, result);
+            }
+        }
+        unsafe {
+            let func: #sig = self.library.get(#name_as_str)?;
+            let (future, context) = ::dynamic_plugin::__private::async_call();
+            func(#(#arg_values,)* complete, context);
+            Ok(future)
+        }
+    }
+}
+
+/// The type of the plugin function `pf` in the C ABI, the type returned
+/// by its host method, and the expression which checks and converts the
+/// value `ret` returned by the plugin into a `Result` of the host
+/// method's return value.
+fn abi_signature(pf: &def::PluginFunction) -> (TokenStream2, TokenStream2, TokenStream2) {
+    let handle_type = pf
+        .receiver()
+        .map(|_| quote!(*mut ::std::ffi::c_void));
+    let arg_types = handle_type.into_iter().chain(
+        pf.arguments
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, arg)| lowering::host_arg(arg, idx))
+            .flat_map(|arg| arg.abi_types),
+    );
+    // `async` functions are passed a callback to complete them, and the
+    // context to give back to it
+    if pf.asyncness.is_some() {
+        let (abi_ret, ret, result) = match &pf.return_type {
+            Some(typ) => {
+                let (abi_ret, _) = lowering::host_return(typ);
+                (
+                    Some(quote!(::std::mem::MaybeUninit<#abi_ret>,)),
+                    quote!(#typ),
+                    lowering::receive_return(typ, &quote!(ret.assume_init())),
+                )
+            }
+            None => (None, quote!(()), quote!(::dynamic_plugin::Result::Ok(()))),
+        };
+        return (
+            quote! {
+                unsafe extern "C" fn(
+                    #(#arg_types,)*
+                    unsafe extern "C" fn(*mut ::std::ffi::c_void, #abi_ret *const ::dynamic_plugin::libc::c_char),
+                    *mut ::std::ffi::c_void,
+                )
+            },
+            ret,
+            result,
+        );
+    }
+    // The plugin returns an uninitialised value if it panics
+    if let Some(typ) = &pf.return_type {
+        let (abi_ret, _) = lowering::host_return(typ);
+        (
+            quote! { unsafe extern "C" fn(#(#arg_types),*) -> ::std::mem::MaybeUninit<#abi_ret> },
+            quote! { #typ },
+            lowering::receive_return(typ, &quote!(ret.assume_init())),
+        )
+    } else {
+        (
+            quote! { unsafe extern "C" fn(#(#arg_types),*) },
+            quote! { () },
+            quote! { ::dynamic_plugin::Result::Ok(ret) },
+        )
+    }
+}
+
+/// Generate the declaration of `pf` in an interface's trait, and its
+/// implementation which forwards to the generated host method.
+fn api_method(pf: &def::PluginFunction) -> (TokenStream2, TokenStream2) {
+    let attributes = &pf.attributes;
+    let name = &pf.name;
+    let args = pf
+        .arguments
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, arg)| lowering::host_arg(arg, idx))
+        .collect::<Vec<_>>();
+    let params = args.iter().map(|arg| &arg.trait_param).collect::<Vec<_>>();
+    let names = args.iter().map(|arg| &arg.name);
+    let receiver = host_receiver(pf);
+    let mut ret = pf
+        .return_type
+        .as_ref()
+        .map_or_else(|| quote!(()), |typ| quote!(#typ));
+    if pf.asyncness.is_some() {
+        ret = quote!(::dynamic_plugin::PluginFuture<'_, #ret>);
+    }
+    (
+        quote! {
+            #(#attributes)*
+            fn #name(#receiver, #(#params),*) -> ::dynamic_plugin::Result<#ret>;
+        },
+        quote! {
+            fn #name(#receiver, #(#params),*) -> ::dynamic_plugin::Result<#ret> {
+                Self::#name(self, #(#names),*)
+            }
+        },
+    )
+}
+
+/// The receiver of the host method for `pf`. Free functions are called
+/// on the plugin, and methods on a plugin instance.
+fn host_receiver(pf: &def::PluginFunction) -> TokenStream2 {
+    match pf.receiver() {
+        Some(receiver) if receiver.mutability.is_some() => quote!(&mut self),
+        _ => quote!(&self),
+    }
+}
+
 /// Write an implementation for a plugin. See the `dynamic_plugin` crate documentation for more.
 ///
 /// ## `attempt to compute '0_usize - 1_usize', which would overflow`
@@ -330,11 +785,8 @@ pub fn plugin_interface(tokens: TokenStream) -> TokenStream {
 ///         println!("A thing has been done!");
 ///     }
 ///
-///     fn say_hello(name: *const c_char) -> bool {
-///         unsafe {
-///             let name = CStr::from_ptr(name);
-///             println!("Hello, {}!", name.to_string_lossy());
-///         }
+///     fn say_hello(name: &str) -> bool {
+///         println!("Hello, {name}!");
 ///         true
 ///     }
 /// }
@@ -346,14 +798,11 @@ pub fn plugin_impl(tokens: TokenStream) -> TokenStream {
 
     let plugin = parse_macro_input!(tokens as PluginImplementation);
     let target_plugin = &plugin.target_plugin;
-    let functions = plugin.functions.iter().map(|maybe_unsafe_func| {
-        let unsafe_ = maybe_unsafe_func._unsafe;
-        let func = &maybe_unsafe_func.func;
-        quote! {
-            #[no_mangle]
-            pub #unsafe_ extern "C" #func
-        }
-    });
+    let no_mangle = plugin.no_mangle();
+    let functions = plugin
+        .functions
+        .iter()
+        .map(|func| func.export(plugin.state.as_ref(), plugin.spawner.as_ref(), &no_mangle));
     let mut hasher = PluginSignatureHasher::default();
     plugin.hash(&mut hasher);
     let hash = hasher.finish();
@@ -363,7 +812,7 @@ pub fn plugin_impl(tokens: TokenStream) -> TokenStream {
         {
             let hash_debug = format!("{hasher:?}");
             Some(quote! {
-                #[no_mangle]
+                #no_mangle
                 pub fn _dynamic_plugin_signature_unhashed() -> &'static str {
                     #hash_debug
                 }
@@ -375,6 +824,53 @@ pub fn plugin_impl(tokens: TokenStream) -> TokenStream {
         }
     };
 
+    let on_load = plugin.on_load.as_ref().map(|hook| {
+        let mut func = hook.func.clone();
+        func.sig.unsafety = hook.unsafety;
+        let name = &func.sig.ident;
+        quote! {
+            #no_mangle
+            #[allow(unused_unsafe)]
+            pub unsafe extern "C" fn _dynamic_plugin_on_load(error: *mut ::dynamic_plugin::PluginError) -> bool {
+                #func
+
+                ::dynamic_plugin::__private::on_load(error, || unsafe { #name() })
+            }
+        }
+    });
+    let on_unload = plugin.on_unload.as_ref().map(|hook| {
+        let mut func = hook.func.clone();
+        func.sig.unsafety = hook.unsafety;
+        let name = &func.sig.ident;
+        quote! {
+            #no_mangle
+            #[allow(unused_unsafe)]
+            pub extern "C" fn _dynamic_plugin_on_unload() {
+                #func
+
+                let _ = ::dynamic_plugin::__private::catch_panic(|| unsafe { #name() });
+            }
+        }
+    });
+
+    let instances = plugin.export_instances();
+    let wait_tasks = plugin.export_wait_tasks();
+    let metadata = plugin.metadata.export(&no_mangle, plugin.static_link);
+
+    let set_host_api = plugin.host.as_ref().map(|host| {
+        quote! {
+            #no_mangle
+            pub unsafe extern "C" fn _dynamic_plugin_set_host_api(vtable: *const ::std::ffi::c_void) -> bool {
+                #host::__install(vtable)
+            }
+        }
+    });
+
+    let symbols = plugin.exported_symbols().into_iter().map(|name| {
+        let name_str = name.to_string();
+        quote!(::dynamic_plugin::__private::StaticSymbol::new(#name_str, #name as *const ::std::ffi::c_void))
+    });
+
     quote! {
         ::dynamic_plugin::static_assert!(
             #target_plugin::PLUGIN_SIGNATURE == #hash,
@@ -384,18 +880,317 @@ pub fn plugin_impl(tokens: TokenStream) -> TokenStream {
             )
         );
 
-        #[no_mangle]
+        #no_mangle
         pub extern "C" fn _dynamic_plugin_signature() -> u64 {
             #hash
         }
 
+        #no_mangle
+        pub extern "C" fn _dynamic_plugin_abi() -> *const ::dynamic_plugin::__private::AbiDescriptor {
+            &::dynamic_plugin::__private::ABI
+        }
+
+        #set_host_api
+
+        #on_load
+
+        #on_unload
+
+        #instances
+
+        #wait_tasks
+
+        #metadata
+
+        #no_mangle
+        pub unsafe extern "C" fn _dynamic_plugin_take_panic(message: *mut ::dynamic_plugin::PluginString) -> bool {
+            ::dynamic_plugin::__private::take_panic(message)
+        }
+
         #hash_debug
 
         #(#functions)*
+
+        /// This plugin, to link statically into a host.
+        pub static STATIC_PLUGIN: ::dynamic_plugin::StaticPlugin<#target_plugin> =
+            ::dynamic_plugin::StaticPlugin::new(&[#(#symbols),*]);
     }
     .into()
 }
 
+/// Define the functions a host provides to its plugins. See the `dynamic_plugin` crate documentation for more.
+///
+/// With the `host` feature, this generates a `{Name}Impl` trait for the
+/// host to implement, and a `host_api` function which turns an
+/// implementation into a [`HostApi`] to give to a `PluginLoader`.
+///
+/// With the `client` feature, `{Name}::get()` returns a handle to call
+/// the host's functions from a plugin which names the host interface in
+/// its `plugin_impl!` with `host: {Name},`.
+///
+/// ## Example
+/// ```ignore
+/// host_interface! {
+///     extern trait ExampleHost {
+///         /// Write a message to the host's log
+///         fn log(message: PluginStr);
+///     }
+/// }
+/// ```
+#[proc_macro]
+#[proc_macro_error]
+pub fn host_interface(tokens: TokenStream) -> TokenStream {
+    let host_def = parse_macro_input!(tokens as PluginDefinition);
+    let host_ident = &host_def.name;
+    if let Some(hf) = host_def.functions.iter().find(|hf| hf.timeout.is_some()) {
+        abort!(
+            hf.name,
+            "Timeouts are only supported in plugin interfaces, as host functions run in the host"
+        );
+    }
+    if host_def.non_reentrant {
+        abort!(
+            host_ident,
+            "Only plugin interfaces can be non-reentrant, as host functions run in the host"
+        );
+    }
+    if host_def.threading != Threading::ThreadSafe {
+        abort!(
+            host_ident,
+            "Only plugin interfaces can declare how they are called from threads, as host functions run in the host"
+        );
+    }
+    if let Some(hf) = host_def.functions.iter().find(|hf| hf.asyncness.is_some()) {
+        abort!(
+            hf.name,
+            "Async functions are only supported in plugin interfaces"
+        );
+    }
+    let vtable_ident = format_ident!("{host_ident}VTable");
+    let impl_ident = format_ident!("{host_ident}Impl");
+
+    let mut hasher = PluginSignatureHasher::default();
+    host_def.hash(&mut hasher);
+    let hash = hasher.finish();
+
+    let vtable_fields = host_def.functions.iter().map(|hf| {
+        let name = &hf.name;
+        let arg_types = hf
+            .arguments
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, arg)| lowering::host_arg(arg, idx))
+            .flat_map(|arg| arg.abi_types);
+        let ret = hf.return_type.as_ref().map(|typ| {
+            let (abi_ret, _) = lowering::host_return(typ);
+            quote!(-> ::std::mem::MaybeUninit<#abi_ret>)
+        });
+        quote! {
+            #name: unsafe extern "C" fn(*const ::std::ffi::c_void, #(#arg_types),*) #ret
+        }
+    });
+
+    let host_impl = if cfg!(feature = "host") {
+        let trait_fns = host_def.functions.iter().map(|hf| {
+            let attributes = &hf.attributes;
+            let name = &hf.name;
+            let args = hf.arguments.iter().map(host_impl_arg);
+            let ret = hf.return_type.as_ref().map(|typ| quote!(-> #typ));
+            quote! {
+                #(#attributes)*
+                fn #name(&self, #(#args),*) #ret;
+            }
+        });
+
+        let trampoline_ident = |name: &syn::Ident| format_ident!("__{name}_trampoline");
+        let trampolines = host_def.functions.iter().map(|hf| {
+            let name = &hf.name;
+            let trampoline = trampoline_ident(name);
+            let args = hf
+                .arguments
+                .iter()
+                .map(host_impl_arg)
+                .enumerate()
+                .filter_map(|(idx, arg)| lowering::client_arg(&arg, idx))
+                .collect::<Vec<_>>();
+            let abi_params = args.iter().flat_map(|arg| &arg.abi_params);
+            let values = args.iter().map(|arg| &arg.value);
+            let call = quote!(T::#name(&*context.cast::<T>(), #(#values),*));
+            let (ret, body) = if let Some(typ) = &hf.return_type {
+                let (abi_ret, convert) = lowering::client_return(&syn::parse_quote!(-> #typ));
+                (
+                    quote!(-> ::std::mem::MaybeUninit<#abi_ret>),
+                    quote!(::dynamic_plugin::__private::catch_panic(|| unsafe { #convert(#call) })),
+                )
+            } else {
+                (
+                    quote!(),
+                    quote!(let _ = ::dynamic_plugin::__private::catch_panic(|| unsafe { #call });),
+                )
+            };
+            quote! {
+                #[doc(hidden)]
+                #[allow(unused_unsafe)]
+                pub unsafe extern "C" fn #trampoline<T: #impl_ident>(context: *const ::std::ffi::c_void, #(#abi_params),*) #ret {
+                    #body
+                }
+            }
+        });
+        let vtable_values = host_def.functions.iter().map(|hf| {
+            let name = &hf.name;
+            let trampoline = trampoline_ident(name);
+            quote!(#name: Self::#trampoline::<T>)
+        });
+
+        Some(quote! {
+            /// The functions provided by the host for the
+            #[doc = concat!("[`", stringify!(#host_ident), "`]")]
+            /// host interface.
+            pub trait #impl_ident: ::std::marker::Send + ::std::marker::Sync + 'static {
+                #(#trait_fns)*
+            }
+
+            impl #host_ident {
+                /// Create a host API from `implementation`, to give to plugins when they are loaded.
+                pub fn host_api<T: #impl_ident>(implementation: T) -> ::dynamic_plugin::HostApi {
+                    ::dynamic_plugin::__private::host_api(#hash, implementation, |context| #vtable_ident {
+                        signature: #hash,
+                        context,
+                        take_panic: ::dynamic_plugin::__private::take_panic,
+                        #(#vtable_values),*
+                    })
+                }
+
+                #(#trampolines)*
+            }
+        })
+    } else {
+        None
+    };
+
+    let client_impl = if cfg!(feature = "client") {
+        let funcs = host_def.functions.iter().map(|hf| {
+            let attributes = &hf.attributes;
+            let name = &hf.name;
+            let function_name = name.to_string();
+            let args = hf
+                .arguments
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, arg)| lowering::host_arg(arg, idx))
+                .collect::<Vec<_>>();
+            let params = args.iter().map(|arg| &arg.param);
+            let generics = lowering::host_generics(&args);
+            let preludes = args.iter().map(|arg| &arg.prelude);
+            let arg_values = args.iter().flat_map(|arg| &arg.abi_values);
+            let (ret, result) = if let Some(typ) = &hf.return_type {
+                (
+                    quote! { #typ },
+                    lowering::receive_return(typ, &quote!(ret.assume_init())),
+                )
+            } else {
+                (quote! { () }, quote! { ::dynamic_plugin::Result::Ok(ret) })
+            };
+            quote! {
+                #(#attributes)*
+                pub fn #name #generics(&self, #(#params),*) -> ::dynamic_plugin::Result<#ret> {
+                    #(#preludes)*
+                    unsafe {
+                        let ret = (self.vtable.#name)(self.vtable.context, #(#arg_values),*);
+                        ::dynamic_plugin::__private::check_host_panic(self.vtable.take_panic, #function_name)?;
+                        #result
+                    }
+                }
+            }
+        });
+
+        Some(quote! {
+            impl #host_ident {
+                fn slot() -> &'static ::std::sync::atomic::AtomicPtr<#vtable_ident> {
+                    static SLOT: ::std::sync::atomic::AtomicPtr<#vtable_ident> =
+                        ::std::sync::atomic::AtomicPtr::new(::std::ptr::null_mut());
+                    &SLOT
+                }
+
+                /// Get a handle to call the host's functions, if the
+                /// host has provided them.
+                pub fn get() -> ::std::option::Option<Self> {
+                    let vtable = Self::slot().load(::std::sync::atomic::Ordering::Acquire);
+                    // SAFETY: the host keeps the vtable alive while the plugin is loaded
+                    unsafe { vtable.as_ref() }.map(|vtable| Self { vtable })
+                }
+
+                #[doc(hidden)]
+                pub unsafe fn __install(vtable: *const ::std::ffi::c_void) -> bool {
+                    // The host clears the vtable before it is freed
+                    let vtable = vtable.cast::<#vtable_ident>();
+                    if !vtable.is_null() && (*vtable).signature != #hash {
+                        return false;
+                    }
+                    Self::slot().store(vtable.cast_mut(), ::std::sync::atomic::Ordering::Release);
+                    true
+                }
+
+                #(#funcs)*
+            }
+        })
+    } else {
+        None
+    };
+
+    let handle_field = if cfg!(feature = "client") {
+        Some(quote!(vtable: &'static #vtable_ident,))
+    } else {
+        None
+    };
+
+    quote! {
+        #[derive(Clone, Copy)]
+        pub struct #host_ident {
+            #handle_field
+        }
+
+        impl #host_ident {
+            /// The signature of this host interface. This number is
+            /// dependent on the functions, their arguments and their
+            /// return types.
+            pub const HOST_SIGNATURE: u64 = #hash;
+        }
+
+        /// The table of host functions passed to plugins.
+        #[doc(hidden)]
+        #[repr(C)]
+        pub struct #vtable_ident {
+            signature: u64,
+            context: *const ::std::ffi::c_void,
+            take_panic: unsafe extern "C" fn(*mut ::dynamic_plugin::PluginString) -> bool,
+            #(#vtable_fields),*
+        }
+
+        // SAFETY: the context is the host's implementation, which is `Send + Sync`.
+        unsafe impl ::std::marker::Send for #vtable_ident {}
+        // SAFETY: as above.
+        unsafe impl ::std::marker::Sync for #vtable_ident {}
+
+        #host_impl
+
+        #client_impl
+    }
+    .into()
+}
+
+/// An argument of a host interface function, as the host's
+/// implementation receives it.
+fn host_impl_arg(arg: &FnArg) -> FnArg {
+    match arg {
+        FnArg::Typed(typed) if lowering::is_plugin_str(&typed.ty) => {
+            let pat = &typed.pat;
+            syn::parse_quote!(#pat: &str)
+        }
+        _ => arg.clone(),
+    }
+}
+
 /// Convert a type to string, returning None if the macro would be
 /// failing elsewhere
 fn type_to_string(ty: Type) -> Option<String> {
@@ -420,13 +1215,26 @@ fn type_to_string(ty: Type) -> Option<String> {
             }
             match inner.output {
                 ReturnType::Default => (),
-                ReturnType::Type(_, ty) => s.push_str(&format!("-> {}", type_to_string(*ty)?)),
+                ReturnType::Type(_, ty) => {
+                    let _ = write!(s, "-> {}", type_to_string(*ty)?);
+                }
             }
             Some(s)
         }
         Type::Group(inner) => type_to_string(*inner.elem),
         Type::Paren(inner) => type_to_string(*inner.elem),
         Type::Ptr(inner) => type_to_string(*inner.elem),
+        Type::Reference(inner) => match *inner.elem {
+            Type::Slice(slice) => {
+                let mutability = if inner.mutability.is_some() {
+                    "mut "
+                } else {
+                    ""
+                };
+                Some(format!("&{mutability}[{}]", type_to_string(*slice.elem)?))
+            }
+            _ => None,
+        },
         Type::Never(_) => Some("!".to_string()),
         Type::Path(inner) => {
             if inner.qself.is_some() {
@@ -434,15 +1242,30 @@ fn type_to_string(ty: Type) -> Option<String> {
             }
             // Hash only last segment
             let last_segment = inner.path.segments.last().unwrap();
+            match lowering::ReturnLowering::of(&Type::Path(inner.clone())) {
+                lowering::ReturnLowering::Buffer if last_segment.ident == "Vec" => {
+                    return Some("Vec<u8>".to_string());
+                }
+                lowering::ReturnLowering::Result { ok, err } => {
+                    return Some(format!(
+                        "Result<{}, {}>",
+                        type_to_string(*ok)?,
+                        type_to_string(*err)?
+                    ));
+                }
+                _ => (),
+            }
             if !last_segment.arguments.is_none() {
                 return None;
             }
+            if last_segment.ident == "PluginStr" {
+                return Some("&str".to_string());
+            }
             Some(last_segment.ident.to_string())
         }
         Type::ImplTrait(_)
         | Type::Infer(_)
         | Type::Macro(_)
-        | Type::Reference(_)
         | Type::Slice(_)
         | Type::TraitObject(_)
         | Type::Tuple(_)
@@ -451,6 +1274,45 @@ fn type_to_string(ty: Type) -> Option<String> {
     }
 }
 
+/// Hash the return type of a plugin function.
+fn hash_return_type<H: Hasher>(hasher: &mut H, ty: Type) {
+    match lowering::ReturnLowering::of(&ty) {
+        lowering::ReturnLowering::String => return "PluginString".hash(hasher),
+        lowering::ReturnLowering::Buffer => return "PluginBuffer".hash(hasher),
+        lowering::ReturnLowering::Result { ok, err } => {
+            "Result".hash(hasher);
+            hash_return_type(hasher, *ok);
+            "err".hash(hasher);
+            return hash_type(hasher, *err);
+        }
+        lowering::ReturnLowering::Plain => (),
+    }
+    if !lowering::ArgLowering::of(&ty).is_plain() {
+        abort!(
+            ty,
+            "Strings and slices can only be passed as arguments to plugin functions"
+        );
+    }
+    hash_type(hasher, ty);
+}
+
+/// Hash the receiver of a method of a plugin instance.
+fn hash_receiver<H: Hasher>(hasher: &mut H, receiver: &syn::Receiver) {
+    "self".hash(hasher);
+    if receiver.mutability.is_some() {
+        "mut".hash(hasher);
+    }
+}
+
+/// The receiver of a method of a plugin instance, as written in Rust.
+fn receiver_to_string(receiver: &syn::Receiver) -> String {
+    if receiver.mutability.is_some() {
+        "&mut self".to_string()
+    } else {
+        "&self".to_string()
+    }
+}
+
 fn hash_type<H: Hasher>(hasher: &mut H, ty: Type) {
     match ty {
         Type::Array(inner) => {
@@ -502,10 +1364,21 @@ fn hash_type<H: Hasher>(hasher: &mut H, ty: Type) {
             last_segment.ident.hash(hasher);
         }
         Type::Ptr(inner) => hash_type(hasher, *inner.elem),
-        Type::Reference(inner) => abort!(
-            inner,
-            "References are not supported in plugin interfaces (use raw pointers instead)"
-        ),
+        Type::Reference(inner) => match lowering::ArgLowering::of(&Type::Reference(inner.clone())) {
+            lowering::ArgLowering::Str { .. } => "PluginStr".hash(hasher),
+            lowering::ArgLowering::Slice { elem, mutable } => {
+                if mutable {
+                    "mut slice".hash(hasher);
+                } else {
+                    "slice".hash(hasher);
+                }
+                hash_type(hasher, *elem);
+            }
+            lowering::ArgLowering::Plain => abort!(
+                inner,
+                "References are not supported in plugin interfaces (use raw pointers, `PluginStr` for strings, or slices)"
+            ),
+        },
         Type::Slice(inner) => abort!(
             inner,
             "Slices are not supported in plugin interfaces (use raw pointers instead)"
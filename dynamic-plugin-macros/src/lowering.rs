@@ -0,0 +1,486 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{punctuated::Punctuated, spanned::Spanned, FnArg, Ident, Pat, Token, Type};
+
+/// How an argument is passed across the C ABI.
+pub enum ArgLowering {
+    /// Passed through unchanged.
+    Plain,
+    /// A string, passed as a nul-terminated `*const c_char`. The
+    /// implementation receives it as `&str` (`utf8` is true) or `&CStr`.
+    Str { utf8: bool },
+    /// A slice, passed as a pointer and a length.
+    Slice { elem: Box<Type>, mutable: bool },
+}
+
+impl ArgLowering {
+    /// Classify an argument type, as written in either the interface
+    /// or the implementation.
+    pub fn of(ty: &Type) -> Self {
+        if is_plugin_str(ty) {
+            return Self::Str { utf8: true };
+        }
+        if let Type::Reference(reference) = ty {
+            if let Type::Slice(slice) = &*reference.elem {
+                return Self::Slice {
+                    elem: slice.elem.clone(),
+                    mutable: reference.mutability.is_some(),
+                };
+            }
+            if reference.mutability.is_none() {
+                match last_ident(&reference.elem).as_deref() {
+                    Some("str") => return Self::Str { utf8: true },
+                    Some("CStr") => return Self::Str { utf8: false },
+                    _ => (),
+                }
+            }
+        }
+        Self::Plain
+    }
+
+    pub fn is_plain(&self) -> bool {
+        matches!(self, Self::Plain)
+    }
+}
+
+/// How a return value is passed across the C ABI.
+pub enum ReturnLowering {
+    /// Passed through unchanged.
+    Plain,
+    /// A `String` returned by the implementation, passed as a
+    /// `PluginString`.
+    String,
+    /// A `Vec<u8>` returned by the implementation, passed as a
+    /// `PluginBuffer`.
+    Buffer,
+    /// A `Result`, passed as a `PluginResult`.
+    Result { ok: Box<Type>, err: Box<Type> },
+}
+
+impl ReturnLowering {
+    /// Classify a return type, as written in either the interface or
+    /// the implementation.
+    pub fn of(ty: &Type) -> Self {
+        let Type::Path(path) = ty else {
+            return Self::Plain;
+        };
+        let Some(seg) = path.path.segments.last() else {
+            return Self::Plain;
+        };
+        if seg.ident == "PluginString" || (seg.ident == "String" && seg.arguments.is_none()) {
+            return Self::String;
+        }
+        if seg.ident == "PluginBuffer" {
+            return Self::Buffer;
+        }
+        let syn::PathArguments::AngleBracketed(args) = &seg.arguments else {
+            return Self::Plain;
+        };
+        let args = args
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                syn::GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        match (seg.ident.to_string().as_str(), args.as_slice()) {
+            ("Vec", [elem]) if last_ident(elem).as_deref() == Some("u8") => Self::Buffer,
+            ("Result", [ok, err]) => Self::Result {
+                ok: Box::new((*ok).clone()),
+                err: Box::new((*err).clone()),
+            },
+            _ => Self::Plain,
+        }
+    }
+}
+
+/// The pieces needed to pass one argument from a generated host
+/// method to the plugin.
+pub struct HostArg {
+    /// The name of the parameter on the host method.
+    pub name: Ident,
+    /// The parameter as it appears on the host method. Strings can be
+    /// anything which converts to a `PluginStr`, borrowed for `'s`.
+    pub param: TokenStream2,
+    /// Whether the parameter is a string, so borrows for `'s`.
+    pub borrows_str: bool,
+    /// The parameter as it appears on the interface's trait, which
+    /// takes strings as `PluginStr` so that it stays dyn-compatible.
+    pub trait_param: TokenStream2,
+    /// Statements run before the plugin function is called.
+    pub prelude: TokenStream2,
+    /// The types of the lowered arguments in the C ABI.
+    pub abi_types: Vec<TokenStream2>,
+    /// The values of the lowered arguments in the C ABI.
+    pub abi_values: Vec<TokenStream2>,
+}
+
+/// The generic parameters of a host method taking `args`, which declare
+/// the lifetime its strings are borrowed for, if it takes any.
+pub fn host_generics(args: &[HostArg]) -> Option<TokenStream2> {
+    args.iter().any(|arg| arg.borrows_str).then(|| quote!(<'s>))
+}
+
+/// Lower the `idx`th argument of an interface function for the host.
+pub fn host_arg(arg: &FnArg, idx: usize) -> Option<HostArg> {
+    let FnArg::Typed(typed) = arg else {
+        return None;
+    };
+    let name = arg_name(&typed.pat, idx);
+    let ty = &typed.ty;
+    Some(match ArgLowering::of(ty) {
+        ArgLowering::Plain => HostArg {
+            param: quote!(#name: #ty),
+            borrows_str: false,
+            trait_param: quote!(#name: #ty),
+            prelude: quote!(),
+            abi_types: vec![quote!(#ty)],
+            abi_values: vec![quote!(#name)],
+            name,
+        },
+        ArgLowering::Str { .. } => HostArg {
+            param: quote!(#name: impl ::std::convert::Into<::dynamic_plugin::PluginStr<'s>>),
+            borrows_str: true,
+            trait_param: {
+                let ty = with_anonymous_lifetime(ty);
+                quote!(#name: #ty)
+            },
+            prelude: quote!(let #name = #name.into().to_c_str()?;),
+            abi_types: vec![quote!(*const ::dynamic_plugin::libc::c_char)],
+            abi_values: vec![quote!(#name.as_ptr())],
+            name,
+        },
+        ArgLowering::Slice { elem, mutable } => {
+            let (ptr_ty, ptr_value) = if mutable {
+                (quote!(*mut #elem), quote!(#name.as_mut_ptr()))
+            } else {
+                (quote!(*const #elem), quote!(#name.as_ptr()))
+            };
+            HostArg {
+                param: quote!(#name: #ty),
+                borrows_str: false,
+                trait_param: quote!(#name: #ty),
+                prelude: quote!(),
+                abi_types: vec![ptr_ty, quote!(usize)],
+                abi_values: vec![ptr_value, quote!(#name.len())],
+                name,
+            }
+        }
+    })
+}
+
+/// The pieces needed to receive one argument in a generated plugin
+/// function.
+pub struct ClientArg {
+    /// The parameters of the exported function in the C ABI.
+    pub abi_params: Vec<TokenStream2>,
+    /// The expression passed to the implementation's function.
+    pub value: TokenStream2,
+    /// Whether the exported function must dereference pointers to
+    /// rebuild this argument.
+    #[cfg_attr(not(feature = "client"), allow(dead_code))]
+    pub lowered: bool,
+}
+
+/// Lower the `idx`th argument of an implementation function.
+pub fn client_arg(arg: &FnArg, idx: usize) -> Option<ClientArg> {
+    let FnArg::Typed(typed) = arg else {
+        return None;
+    };
+    let name = format_ident!("arg{idx}");
+    let ty = &typed.ty;
+    let lowering = ArgLowering::of(ty);
+    let lowered = !lowering.is_plain();
+    Some(match lowering {
+        ArgLowering::Plain => ClientArg {
+            abi_params: vec![quote!(#name: #ty)],
+            value: quote!(#name),
+            lowered,
+        },
+        ArgLowering::Str { utf8 } => {
+            let convert = if utf8 {
+                quote!(::dynamic_plugin::__private::str_arg(#name))
+            } else {
+                quote!(::dynamic_plugin::__private::c_str_arg(#name))
+            };
+            ClientArg {
+                abi_params: vec![quote!(#name: *const ::dynamic_plugin::libc::c_char)],
+                value: convert,
+                lowered,
+            }
+        }
+        ArgLowering::Slice { elem, mutable } => {
+            let len = format_ident!("arg{idx}_len");
+            let (ptr_ty, convert) = if mutable {
+                (
+                    quote!(*mut #elem),
+                    quote!(::dynamic_plugin::__private::slice_arg_mut(#name, #len)),
+                )
+            } else {
+                (
+                    quote!(*const #elem),
+                    quote!(::dynamic_plugin::__private::slice_arg(#name, #len)),
+                )
+            };
+            ClientArg {
+                abi_params: vec![quote!(#name: #ptr_ty), quote!(#len: usize)],
+                value: convert,
+                lowered,
+            }
+        }
+    })
+}
+
+/// Check that any receiver is the first argument and is `&self` or
+/// `&mut self`, the only receivers that can refer to a plugin instance.
+pub fn check_receiver(args: &Punctuated<FnArg, Token![,]>) -> syn::Result<()> {
+    for (idx, arg) in args.iter().enumerate() {
+        if let FnArg::Receiver(receiver) = arg {
+            if idx > 0 {
+                return Err(syn::Error::new(
+                    receiver.span(),
+                    "`self` must be the first argument",
+                ));
+            }
+            if receiver.reference.is_none() || receiver.colon_token.is_some() {
+                return Err(syn::Error::new(
+                    receiver.span(),
+                    "plugin methods must take `&self` or `&mut self`",
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that an argument of an interface function can be passed to a
+/// plugin. Strings are passed as `PluginStr`, and slices are the only
+/// references an interface can borrow.
+pub fn check_interface_arg(arg: &FnArg) -> syn::Result<()> {
+    let FnArg::Typed(typed) = arg else {
+        return Ok(());
+    };
+    if let Type::Reference(reference) = &*typed.ty {
+        if !matches!(*reference.elem, Type::Slice(_)) {
+            return Err(syn::Error::new(
+                typed.ty.span(),
+                "plugin interfaces can only borrow slices (use `PluginStr` for strings, or raw pointers)",
+            ));
+        }
+    }
+    if crate::type_to_string((*typed.ty).clone()).is_none() {
+        return Err(syn::Error::new(
+            typed.ty.span(),
+            "this type is not supported in plugin interfaces",
+        ));
+    }
+    Ok(())
+}
+
+/// Check that the return type of an interface function can be passed
+/// back from a plugin. A `Result` must have `PluginError` as its error
+/// type, as that is the only error which can cross the C ABI.
+pub fn check_interface_return(ty: &Type) -> syn::Result<()> {
+    if let ReturnLowering::Result { ok, err } = ReturnLowering::of(ty) {
+        if last_ident(&err).as_deref() != Some("PluginError") {
+            return Err(syn::Error::new(
+                err.span(),
+                "plugin functions can only return `Result`s with `PluginError` as the error type",
+            ));
+        }
+        if matches!(ReturnLowering::of(&ok), ReturnLowering::Result { .. }) {
+            return Err(syn::Error::new(
+                ok.span(),
+                "plugin functions can't return nested `Result`s",
+            ));
+        }
+    }
+    if matches!(ty, Type::Reference(_)) {
+        return Err(syn::Error::new(
+            ty.span(),
+            "strings and slices can only be passed as arguments to plugin functions",
+        ));
+    }
+    if crate::type_to_string(ty.clone()).is_none() {
+        return Err(syn::Error::new(
+            ty.span(),
+            "this type is not supported in plugin interfaces",
+        ));
+    }
+    Ok(())
+}
+
+/// Check that an `async` function can complete after it returns to the
+/// host. It can't borrow a plugin instance, a slice for the plugin to
+/// write to, or anything else it can't copy, as the host may use those
+/// again straight away.
+pub fn check_async(
+    asyncness: Option<&Token![async]>,
+    args: &Punctuated<FnArg, Token![,]>,
+) -> syn::Result<()> {
+    if asyncness.is_none() {
+        return Ok(());
+    }
+    for arg in args {
+        match arg {
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new(
+                    receiver.span(),
+                    "async functions can't be methods of plugin instances",
+                ));
+            }
+            FnArg::Typed(typed) => match ArgLowering::of(&typed.ty) {
+                ArgLowering::Slice { mutable: true, .. } => {
+                    return Err(syn::Error::new(
+                        typed.ty.span(),
+                        "async functions can't take mutable slices",
+                    ));
+                }
+                // Strings and slices are copied, but nothing else can be
+                ArgLowering::Plain if matches!(*typed.ty, Type::Reference(_)) => {
+                    return Err(syn::Error::new(
+                        typed.ty.span(),
+                        "async functions can only borrow strings and slices",
+                    ));
+                }
+                _ => (),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// The name to give an argument in generated code.
+fn arg_name(pat: &Pat, idx: usize) -> Ident {
+    if let Pat::Ident(pat) = pat {
+        pat.ident.clone()
+    } else {
+        format_ident!("arg{idx}")
+    }
+}
+
+/// Add an anonymous lifetime to `PluginStr`, as written in an interface.
+fn with_anonymous_lifetime(ty: &Type) -> Type {
+    let mut ty = ty.clone();
+    if let Type::Path(path) = &mut ty {
+        if let Some(seg) = path.path.segments.last_mut() {
+            seg.arguments = syn::PathArguments::AngleBracketed(syn::parse_quote!(<'_>));
+        }
+    }
+    ty
+}
+
+/// Whether `ty` is the interface string type, `PluginStr`.
+pub fn is_plugin_str(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.qself.is_none()
+        && path.path.segments.last().is_some_and(|seg| seg.ident == "PluginStr"))
+}
+
+/// The last identifier of a path type.
+fn last_ident(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) => path.path.segments.last().map(|seg| seg.ident.to_string()),
+        Type::Group(inner) => last_ident(&inner.elem),
+        Type::Paren(inner) => last_ident(&inner.elem),
+        _ => None,
+    }
+}
+
+/// The return type of the plugin function in the C ABI, and the
+/// conversion applied to give the host method's return value.
+pub fn host_return(ty: &Type) -> (TokenStream2, Option<TokenStream2>) {
+    let is_owned = |name: &str| matches!(ty, Type::Path(path) if path.path.segments.last().is_some_and(|seg| seg.ident == name));
+    match ReturnLowering::of(ty) {
+        ReturnLowering::String if !is_owned("PluginString") => (
+            quote!(::dynamic_plugin::PluginString),
+            Some(quote!(::dynamic_plugin::PluginString::into_string)),
+        ),
+        ReturnLowering::Buffer if !is_owned("PluginBuffer") => (
+            quote!(::dynamic_plugin::PluginBuffer),
+            Some(quote!(::dynamic_plugin::PluginBuffer::into_vec)),
+        ),
+        ReturnLowering::Result { ok, err } => {
+            let (abi_ok, convert_ok) = host_return(&ok);
+            let convert = if let Some(convert_ok) = convert_ok {
+                quote!((|r: ::dynamic_plugin::PluginResult<#abi_ok, #err>| r.into_result().map(#convert_ok)))
+            } else {
+                quote!(::dynamic_plugin::PluginResult::into_result)
+            };
+            (
+                quote!(::dynamic_plugin::PluginResult<#abi_ok, #err>),
+                Some(convert),
+            )
+        }
+        _ => (quote!(#ty), None),
+    }
+}
+
+/// The expression which turns `ret`, a value of the return type `ty` in
+/// the C ABI received from the other side of the plugin boundary, into
+/// a `Result` of the generated method's return value. Any strings it
+/// holds are checked to be valid UTF-8 first.
+pub fn receive_return(ty: &Type, ret: &TokenStream2) -> TokenStream2 {
+    let (_, convert) = host_return(ty);
+    if let Some(check) = return_check(ty) {
+        let convert = convert.map(|convert| quote!(.map(#convert)));
+        quote!(::dynamic_plugin::__private::received(#ret, #check)#convert)
+    } else {
+        quote!(::dynamic_plugin::Result::Ok(#convert(#ret)))
+    }
+}
+
+/// The function which checks a value of the return type `ty` in the C
+/// ABI, if it holds any strings.
+fn return_check(ty: &Type) -> Option<TokenStream2> {
+    match ReturnLowering::of(ty) {
+        ReturnLowering::String => Some(quote!(::dynamic_plugin::__private::check_string)),
+        ReturnLowering::Result { ok, err } => {
+            let unchecked = || quote!(::dynamic_plugin::__private::unchecked);
+            let ok = return_check(&ok).unwrap_or_else(unchecked);
+            let err = return_check(&err).unwrap_or_else(unchecked);
+            Some(quote!(|ret: &_| ::dynamic_plugin::__private::check_result(ret, #ok, #err)))
+        }
+        ReturnLowering::Plain if last_ident(ty).as_deref() == Some("PluginError") => {
+            Some(quote!(::dynamic_plugin::__private::check_error))
+        }
+        ReturnLowering::Plain | ReturnLowering::Buffer => None,
+    }
+}
+
+/// The return type of the exported function for an implementation
+/// function, and the conversion applied to the implementation's return
+/// value.
+pub fn client_return(output: &syn::ReturnType) -> (Option<TokenStream2>, Option<TokenStream2>) {
+    let syn::ReturnType::Type(_, ty) = output else {
+        return (None, None);
+    };
+    let (abi_ty, convert) = client_return_type(ty);
+    (Some(abi_ty), convert)
+}
+
+fn client_return_type(ty: &Type) -> (TokenStream2, Option<TokenStream2>) {
+    match ReturnLowering::of(ty) {
+        ReturnLowering::Plain => (quote!(#ty), None),
+        ReturnLowering::String => (
+            quote!(::dynamic_plugin::PluginString),
+            Some(quote!(::dynamic_plugin::PluginString::from)),
+        ),
+        ReturnLowering::Buffer => (
+            quote!(::dynamic_plugin::PluginBuffer),
+            Some(quote!(::dynamic_plugin::PluginBuffer::from)),
+        ),
+        ReturnLowering::Result { ok, err } => {
+            let (abi_ok, convert_ok) = client_return_type(&ok);
+            let convert = if let Some(convert_ok) = convert_ok {
+                quote!((|r: ::std::result::Result<_, #err>| ::dynamic_plugin::PluginResult::from(r.map(#convert_ok))))
+            } else {
+                quote!(::dynamic_plugin::PluginResult::from)
+            };
+            (
+                quote!(::dynamic_plugin::PluginResult<#abi_ok, #err>),
+                Some(convert),
+            )
+        }
+    }
+}
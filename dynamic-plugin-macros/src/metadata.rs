@@ -0,0 +1,152 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    braced, bracketed,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    Ident, LitStr, Result, Token,
+};
+
+/// The information a plugin gives about itself, given with
+/// `metadata: { ... },` in `plugin_impl!`.
+#[derive(Default)]
+pub struct PluginMetadata {
+    name: Option<String>,
+    version: Option<String>,
+    authors: Option<Vec<String>>,
+    description: Option<String>,
+    homepage: Option<String>,
+    license: Option<String>,
+    dependencies: Vec<(String, String)>,
+}
+
+impl Parse for PluginMetadata {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let content;
+        braced!(content in input);
+
+        let mut metadata = Self::default();
+        while !content.is_empty() {
+            let key: Ident = content.parse()?;
+            let _: Token![:] = content.parse()?;
+            if key == "dependencies" {
+                // Parse as `{ "name": "requirement", ... }`
+                let dependencies;
+                braced!(dependencies in content);
+                while !dependencies.is_empty() {
+                    let name: LitStr = dependencies.parse()?;
+                    let _: Token![:] = dependencies.parse()?;
+                    let requirement: LitStr = dependencies.parse()?;
+                    if let Err(e) = semver::VersionReq::parse(&requirement.value()) {
+                        return Err(syn::Error::new(
+                            requirement.span(),
+                            format!("the dependency must have a version requirement: {e}"),
+                        ));
+                    }
+                    metadata
+                        .dependencies
+                        .push((name.value(), requirement.value()));
+                    if dependencies.is_empty() {
+                        break;
+                    }
+                    let _: Token![,] = dependencies.parse()?;
+                }
+            } else if key == "authors" {
+                let authors;
+                bracketed!(authors in content);
+                let authors: Punctuated<LitStr, Token![,]> =
+                    authors.parse_terminated(<LitStr as Parse>::parse, Token![,])?;
+                metadata.authors = Some(authors.iter().map(LitStr::value).collect());
+            } else {
+                let value: LitStr = content.parse()?;
+                let field = match key.to_string().as_str() {
+                    "name" => &mut metadata.name,
+                    "version" => {
+                        if let Err(e) = semver::Version::parse(&value.value()) {
+                            return Err(syn::Error::new(
+                                value.span(),
+                                format!("the version must be a semantic version: {e}"),
+                            ));
+                        }
+                        &mut metadata.version
+                    }
+                    "description" => &mut metadata.description,
+                    "homepage" => &mut metadata.homepage,
+                    "license" => &mut metadata.license,
+                    _ => return Err(syn::Error::new(key.span(), "unknown metadata field")),
+                };
+                *field = Some(value.value());
+            }
+            if content.is_empty() {
+                break;
+            }
+            let _: Token![,] = content.parse()?;
+        }
+
+        Ok(metadata)
+    }
+}
+
+impl PluginMetadata {
+    /// Generate the function exported from the plugin which returns its
+    /// encoded metadata. Fields which weren't given are taken from the
+    /// plugin crate's `Cargo.toml` when the plugin is compiled. Unless
+    /// the plugin is only linked statically, the metadata is also placed
+    /// in a section of its own, so hosts can read it from the file.
+    pub fn export(&self, no_mangle: &TokenStream2, static_link: bool) -> TokenStream2 {
+        let name = field(self.name.as_ref(), "CARGO_PKG_NAME");
+        let version = field(self.version.as_ref(), "CARGO_PKG_VERSION");
+        let authors = self.authors.as_ref().map_or_else(
+            || {
+                quote! {
+                    const JOINED: &str = ::std::env!("CARGO_PKG_AUTHORS");
+                    const AUTHORS: [&str; ::dynamic_plugin::__private::cargo_list_len(JOINED)] =
+                        ::dynamic_plugin::__private::cargo_list(JOINED);
+                }
+            },
+            |authors| {
+                let len = authors.len();
+                quote!(const AUTHORS: [&str; #len] = [#(#authors),*];)
+            },
+        );
+        let description = field(self.description.as_ref(), "CARGO_PKG_DESCRIPTION");
+        let homepage = field(self.homepage.as_ref(), "CARGO_PKG_HOMEPAGE");
+        let license = field(self.license.as_ref(), "CARGO_PKG_LICENSE");
+        let dependencies = self
+            .dependencies
+            .iter()
+            .map(|(name, requirement)| quote!((#name, #requirement)));
+        let section = (!static_link).then(|| {
+            quote! {
+                #[cfg_attr(target_vendor = "apple", link_section = "__DATA,__dp_metadata")]
+                #[cfg_attr(windows, link_section = ".dpmeta")]
+                #[cfg_attr(not(any(windows, target_vendor = "apple")), link_section = ".dynamic_plugin_metadata")]
+            }
+        });
+        quote! {
+            #no_mangle
+            pub extern "C" fn _dynamic_plugin_metadata() -> *const u8 {
+                #authors
+                const FIELDS: ::dynamic_plugin::__private::MetadataFields = ::dynamic_plugin::__private::MetadataFields {
+                    name: #name,
+                    version: #version,
+                    authors: &AUTHORS,
+                    description: #description,
+                    homepage: #homepage,
+                    license: #license,
+                    dependencies: &[#(#dependencies),*],
+                };
+                #section
+                #[used]
+                static METADATA: [u8; FIELDS.encoded_len()] = FIELDS.encode();
+                METADATA.as_ptr()
+            }
+        }
+    }
+}
+
+/// A metadata field, or the value Cargo sets for `key` if it wasn't
+/// given.
+fn field(value: Option<&String>, key: &str) -> TokenStream2 {
+    value.map_or_else(|| quote!(::std::env!(#key)), |value| quote!(#value))
+}
@@ -0,0 +1,134 @@
+//! The thread-safety contract between a host and its plugins.
+//!
+//! An interface declares how its plugins' functions may be called, with
+//! `#[thread_safe]`, `#[single_threaded]` or `#[main_thread_only]`, and
+//! plugins agree to it with `threading: ...,`. The contract is part of
+//! the signature, and decides whether the generated plugin struct is
+//! `Send` and `Sync`, or wraps each call in a mutex.
+//!
+//! Separately, an interface can be `#[non_reentrant]`, for plugins which
+//! can't run more than one call at once. Each loaded plugin then holds
+//! every call, from any path, to one at a time, including creating and
+//! destroying instances.
+//!
+//! Both hold calls with a lock, so a plugin which calls back into the
+//! host, which calls the plugin again on the same thread, would wait for
+//! itself. The inner call fails with `Error::Reentrant` instead.
+
+use std::hash::{Hash, Hasher};
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::Ident;
+
+/// How a plugin's functions may be called from the host's threads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Threading {
+    /// From any thread, at the same time.
+    #[default]
+    ThreadSafe,
+    /// From any thread, one call at a time.
+    SingleThreaded,
+    /// Only from the thread which loaded the plugin.
+    MainThreadOnly,
+}
+
+impl Threading {
+    pub const NAMES: &'static str = "`thread_safe`, `single_threaded` or `main_thread_only`";
+
+    /// The contract named `ident`, if there is one.
+    pub fn from_ident(ident: &Ident) -> Option<Self> {
+        match ident.to_string().as_str() {
+            "thread_safe" => Some(Self::ThreadSafe),
+            "single_threaded" => Some(Self::SingleThreaded),
+            "main_thread_only" => Some(Self::MainThreadOnly),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ThreadSafe => "thread_safe",
+            Self::SingleThreaded => "single_threaded",
+            Self::MainThreadOnly => "main_thread_only",
+        }
+    }
+
+    /// The field of the plugin struct which enforces the contract, if
+    /// it needs one.
+    pub fn field(self) -> Option<TokenStream2> {
+        match self {
+            Self::ThreadSafe => None,
+            Self::SingleThreaded => Some(quote!(exclusive: ::dynamic_plugin::__private::CallLock,)),
+            // Raw pointers are neither `Send` nor `Sync`
+            Self::MainThreadOnly => Some(quote!(_thread: ::std::marker::PhantomData<*const ()>,)),
+        }
+    }
+
+    /// The value of the field from [`Threading::field`], if there is one.
+    pub fn init(self) -> Option<TokenStream2> {
+        match self {
+            Self::ThreadSafe => None,
+            Self::SingleThreaded => Some(quote!(exclusive: ::dynamic_plugin::__private::CallLock::new(),)),
+            Self::MainThreadOnly => Some(quote!(_thread: ::std::marker::PhantomData,)),
+        }
+    }
+
+    /// The statement which stops other threads calling the plugin
+    /// `plugin` until the end of the current scope, if the contract
+    /// needs one. It returns early with `Error::Reentrant` if the
+    /// current thread is already calling the plugin, for `function`.
+    pub fn guard(self, plugin: &TokenStream2, function: &str) -> Option<TokenStream2> {
+        (self == Self::SingleThreaded).then(|| {
+            quote! {
+                let _exclusive = #plugin.exclusive.lock(#function)?;
+            }
+        })
+    }
+}
+
+/// The field of the plugin which holds its calls to one at a time, if
+/// its interface is `#[non_reentrant]`.
+pub fn call_limit_field(non_reentrant: bool) -> Option<TokenStream2> {
+    non_reentrant.then(|| quote!(calls: ::dynamic_plugin::__private::CallLock,))
+}
+
+/// The value of the plugin's call limit field, if it has one.
+pub fn call_limit_init(non_reentrant: bool) -> Option<TokenStream2> {
+    non_reentrant.then(|| quote!(calls: ::dynamic_plugin::__private::CallLock::new(),))
+}
+
+/// The statement which holds other calls to the plugin `plugin` until
+/// the end of the current scope, if its interface is `#[non_reentrant]`.
+/// Like [`Threading::guard`], it returns early for a re-entrant call.
+pub fn call_limit_guard(
+    non_reentrant: bool,
+    plugin: &TokenStream2,
+    function: &str,
+) -> Option<TokenStream2> {
+    non_reentrant.then(|| {
+        quote! {
+            let _call = #plugin.calls.lock(#function)?;
+        }
+    })
+}
+
+/// Hash whether an interface is `#[non_reentrant]` into its signature.
+/// Plugins agree to it with `reentrant: false,`.
+pub fn hash_non_reentrant<H: Hasher>(non_reentrant: bool, state: &mut H) {
+    // Interfaces written before this was hashed keep their signatures
+    if non_reentrant {
+        "non_reentrant".hash(state);
+    }
+}
+
+impl Hash for Threading {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Interfaces written before the contract existed are thread-safe,
+        // and keep their signatures
+        if *self != Self::ThreadSafe {
+            "threading".hash(state);
+            self.name().hash(state);
+        }
+    }
+}
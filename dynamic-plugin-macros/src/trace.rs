@@ -0,0 +1,62 @@
+//! Reporting plugin calls and loading through `tracing`.
+//!
+//! With the `tracing` feature, host methods run their calls through
+//! `traced_call`, or `traced_async_call` for `async` functions, and the loader functions report each file they load
+//! or reject. Without it, the generated code is unchanged.
+
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::Ident;
+
+fn enabled() -> bool {
+    cfg!(feature = "tracing") && cfg!(feature = "host")
+}
+
+/// Wrap `body`, the body of the host method for `function` of the
+/// interface `interface`, in a span for the call to `library`. For an
+/// `async` function, the span lasts until the returned future completes.
+pub fn call(
+    interface: &Ident,
+    function: &Ident,
+    is_async: bool,
+    library: &TokenStream2,
+    body: TokenStream2,
+) -> TokenStream2 {
+    if !enabled() {
+        return body;
+    }
+    let interface = interface.to_string();
+    let function = function.to_string();
+    let traced = if is_async {
+        quote!(traced_async_call)
+    } else {
+        quote!(traced_call)
+    };
+    quote! {
+        ::dynamic_plugin::__private::#traced(#interface, #function, &#library, || { #body })
+    }
+}
+
+/// Wrap `body`, which loads the plugin at `path`, to report whether it
+/// was loaded.
+pub fn load(interface: &Ident, body: TokenStream2) -> TokenStream2 {
+    if !enabled() {
+        return body;
+    }
+    let interface = interface.to_string();
+    quote! {
+        let plugin_path = ::std::path::PathBuf::from(path.as_ref());
+        ::dynamic_plugin::__private::traced_load(#interface, &plugin_path, move || { #body })
+    }
+}
+
+/// The statement which reports the plugins loaded and files rejected in
+/// `report`, if tracing is enabled.
+pub fn report(interface: &Ident) -> Option<TokenStream2> {
+    enabled().then(|| {
+        let interface = interface.to_string();
+        quote! {
+            ::dynamic_plugin::__private::trace_report(#interface, &report, |plugin| plugin.library.path());
+        }
+    })
+}
@@ -1,12 +1,43 @@
-use dynamic_plugin::{libc::c_char, plugin_interface};
+use dynamic_plugin::{
+    host_interface, plugin_interface, PluginBuffer, PluginError, PluginStr, PluginString,
+};
 
 plugin_interface! {
     extern trait ExamplePlugin {
         /// Ask the plugin to do a thing
         fn do_a_thing();
         /// Say hello to a person
-        fn say_hello(to: *const c_char) -> bool;
+        fn say_hello(to: PluginStr) -> bool;
         /// Here's a function
         fn trigger_function(a_func: extern "C" fn(u32, u32));
+        /// Add up some numbers
+        fn sum(values: &[u32]) -> u64;
+        /// Fill a buffer with a greeting
+        fn fill(buffer: &mut [u8]);
+        /// Write a greeting for a person
+        fn greeting(to: PluginStr) -> PluginString;
+        /// Get some bytes, copied into a `Vec<u8>` for the host
+        fn bytes() -> Vec<u8>;
+        /// Get some bytes, freed by the plugin when dropped
+        fn bytes_guard() -> PluginBuffer;
+        /// Parse a number, failing if it isn't valid
+        fn parse_number(text: PluginStr) -> Result<u32, PluginError>;
+        /// Describe a number, failing if it is zero
+        fn describe(number: u32) -> Result<String, PluginError>;
+        /// Always panics
+        fn do_a_panic() -> u32;
+        /// Add to this instance's count, returning the new count
+        fn increment(&mut self, by: u32) -> u32;
+        /// Get this instance's count
+        fn count(&self) -> u32;
+    }
+}
+
+host_interface! {
+    extern trait ExampleHost {
+        /// Write a message to the host's log
+        fn log(message: PluginStr);
+        /// Look up a configuration value
+        fn config(key: PluginStr) -> Result<String, PluginError>;
     }
 }
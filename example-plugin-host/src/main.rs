@@ -1,7 +1,22 @@
-use std::ffi::CString;
+use dynamic_plugin::{PluginDigest, PluginError, PluginLoader, Result};
+use example_plugin_host::{
+    ExampleHost, ExampleHostImpl, ExamplePlugin, ExamplePluginApi, ExamplePluginInstanceApi,
+};
 
-use dynamic_plugin::Result;
-use example_plugin_host::ExamplePlugin;
+struct Host;
+
+impl ExampleHostImpl for Host {
+    fn log(&self, message: &str) {
+        println!("[plugin] {message}");
+    }
+
+    fn config(&self, key: &str) -> std::result::Result<String, PluginError> {
+        match key {
+            "greeting" => Ok("Hello from the host's configuration!".to_string()),
+            _ => Err(PluginError::new(1, format!("no configuration for {key}"))),
+        }
+    }
+}
 
 extern "C" fn a_func(a: u32, b: u32) {
     println!("a = {a}, b = {b}");
@@ -9,13 +24,69 @@ extern "C" fn a_func(a: u32, b: u32) {
     assert_eq!(b, 3);
 }
 
+/// Works with any implementation of the interface, not just a loaded plugin
+fn count_to(counter: &mut dyn ExamplePluginInstanceApi, to: u32) -> Result<u32> {
+    while counter.count()? < to {
+        counter.increment(1)?;
+    }
+    counter.count()
+}
+
 fn main() -> Result<()> {
-    let plugin = ExamplePlugin::load_plugin_and_check("target/debug/libexample_plugin.so")?;
+    let path = "target/debug/libexample_plugin.so";
+    // Only load this exact build of the plugin
+    let digest = PluginDigest::of_file(path)?;
+    let loader = PluginLoader::new()
+        .host_api(ExampleHost::host_api(Host))
+        .allow_digests([digest]);
+    let plugin = ExamplePlugin::load_plugin_with(path, &loader)?;
+    assert_eq!(plugin.digest(), Some(&digest));
+    println!("Loaded plugin with digest {digest}");
+
+    let metadata = plugin.metadata().expect("the plugin has metadata");
+    println!("Loaded {} {}", metadata.name, metadata.version);
+    assert_eq!(metadata.name, "example-plugin");
+    assert_eq!(metadata.license.as_deref(), Some("MIT"));
 
-    plugin.do_a_thing()?;
-    let s = CString::new("Jens").unwrap();
-    plugin.say_hello(s.as_ptr())?;
+    let report = ExamplePlugin::discover_plugins_with(
+        "target/debug",
+        &loader.clone().filter(|metadata| metadata.name == "example-plugin"),
+    );
+    assert_eq!(report.loaded.len(), 1);
+    println!("Rejected {} other files", report.rejected.len());
+    drop(report);
+
+    let api: &dyn ExamplePluginApi = &plugin;
+    api.do_a_thing()?;
+    plugin.say_hello("Jens")?;
     plugin.trigger_function(a_func)?;
+    assert_eq!(plugin.sum(&[1, 2, 3, 4])?, 10);
+    let mut buffer = [0u8; 6];
+    plugin.fill(&mut buffer)?;
+    assert_eq!(&buffer, b"Hello!");
+    let greeting = plugin.greeting("Jens")?;
+    println!("{greeting}");
+    assert_eq!(plugin.bytes()?, vec![1, 2, 3]);
+    assert_eq!(&*plugin.bytes_guard()?, &[4, 5, 6]);
+    assert_eq!(plugin.parse_number("42")?.unwrap(), 42);
+    let err = plugin.parse_number("forty-two")?.unwrap_err();
+    println!("Failed to parse: {err}");
+    assert_eq!(err.code(), 1);
+    println!("{}", plugin.describe(7)?.unwrap());
+    assert_eq!(plugin.describe(0)?.unwrap_err().code(), 2);
+    match plugin.do_a_panic() {
+        Err(e) => println!("{e}"),
+        Ok(_) => panic!("the plugin should have panicked"),
+    }
+
+    let mut first = plugin.create_instance()?;
+    let mut second = plugin.create_instance()?;
+    assert_eq!(first.increment(2)?, 2);
+    assert_eq!(first.increment(3)?, 5);
+    assert_eq!(second.increment(1)?, 1);
+    assert_eq!(first.count()?, 5);
+    assert_eq!(second.count()?, 1);
+    assert_eq!(count_to(&mut second, 4)?, 4);
 
     Ok(())
 }
@@ -0,0 +1,86 @@
+//! Plugins whose interfaces say how they may be called from the host's
+//! threads, linked statically into this example.
+
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use dynamic_plugin::{plugin_interface, Result};
+
+plugin_interface! {
+    // A plugin which keeps state it doesn't lock, so can only run one
+    // call at a time.
+    #[single_threaded]
+    extern trait Tally {
+        fn add(amount: u64) -> u64;
+    }
+}
+
+plugin_interface! {
+    // A plugin which uses a library that must stay on one thread.
+    #[main_thread_only]
+    extern trait Window {
+        fn title() -> u64;
+    }
+}
+
+mod tally {
+    use std::{cell::Cell, thread, time::Duration};
+
+    /// A total which isn't safe to update from two calls at once.
+    struct Total(Cell<u64>);
+
+    // Safety: the interface is `single_threaded`, so calls never overlap
+    unsafe impl Sync for Total {}
+
+    static TOTAL: Total = Total(Cell::new(0));
+
+    dynamic_plugin::plugin_impl! {
+        super::Tally,
+        threading: single_threaded,
+        link: static,
+
+        fn add(amount: u64) -> u64 {
+            let total = TOTAL.0.get();
+            thread::sleep(Duration::from_millis(10));
+            TOTAL.0.set(total + amount);
+            total + amount
+        }
+    }
+}
+
+mod window {
+    dynamic_plugin::plugin_impl! {
+        super::Window,
+        threading: main_thread_only,
+        link: static,
+
+        fn title() -> u64 {
+            42
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    // Calls from many threads are run one at a time, so none are lost
+    let tally = Arc::new(Tally::from_static(&tally::STATIC_PLUGIN)?);
+    let start = Instant::now();
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let tally = Arc::clone(&tally);
+            thread::spawn(move || tally.add(1))
+        })
+        .collect();
+    for thread in threads {
+        thread.join().expect("the thread panicked")?;
+    }
+    assert!(start.elapsed() >= Duration::from_millis(40));
+    println!("The total is {}", tally.add(0)?);
+
+    // `Window` can't be sent to another thread, so it is only called here
+    let window = Window::from_static(&window::STATIC_PLUGIN)?;
+    println!("The window's title is {}", window.title()?);
+    Ok(())
+}
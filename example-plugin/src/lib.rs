@@ -1,21 +1,101 @@
-use std::ffi::CStr;
+use dynamic_plugin::{plugin_impl, PluginBuffer, PluginError};
+use example_plugin_host::ExampleHost;
 
-use dynamic_plugin::{libc, plugin_impl};
+#[derive(Default)]
+struct Counter {
+    count: u32,
+}
 
 plugin_impl! {
     example_plugin_host::ExamplePlugin,
+    host: ExampleHost,
+    state: Counter,
+    metadata: {
+        description: "An example plugin",
+        authors: ["Lily Hopkins <lily@hpkns.uk>"],
+        license: "MIT",
+    },
+
+    #[on_load]
+    fn on_load() -> Result<(), PluginError> {
+        if let Some(host) = ExampleHost::get() {
+            host.log("The plugin has been loaded").unwrap();
+        }
+        Ok(())
+    }
+
+    #[on_unload]
+    fn on_unload() {
+        if let Some(host) = ExampleHost::get() {
+            host.log("The plugin is being unloaded").unwrap();
+        }
+    }
 
     fn do_a_thing() {
         println!("A thing has been done!");
+        if let Some(host) = ExampleHost::get() {
+            host.log("The plugin did a thing").unwrap();
+            match host.config("greeting").unwrap() {
+                Ok(greeting) => host.log(greeting.as_str()).unwrap(),
+                Err(e) => host.log(e.message()).unwrap(),
+            }
+        }
     }
 
-    unsafe fn say_hello(name: *const libc::c_char) -> bool {
-        let name = CStr::from_ptr(name);
-        println!("Hello, {}!", name.to_string_lossy());
+    fn say_hello(name: &str) -> bool {
+        println!("Hello, {name}!");
         true
     }
 
     fn trigger_function(a_func: extern "C" fn(u32, u32)) {
         a_func(5, 3);
     }
+
+    fn sum(values: &[u32]) -> u64 {
+        values.iter().map(|v| u64::from(*v)).sum()
+    }
+
+    fn fill(buffer: &mut [u8]) {
+        for (b, c) in buffer.iter_mut().zip(b"Hello!".iter().cycle()) {
+            *b = *c;
+        }
+    }
+
+    fn greeting(name: &str) -> String {
+        format!("Hello, {name}!")
+    }
+
+    fn bytes() -> Vec<u8> {
+        vec![1, 2, 3]
+    }
+
+    fn bytes_guard() -> PluginBuffer {
+        vec![4, 5, 6].into()
+    }
+
+    fn parse_number(text: &str) -> Result<u32, PluginError> {
+        text.parse()
+            .map_err(|e: std::num::ParseIntError| PluginError::new(1, e.to_string()))
+    }
+
+    fn describe(number: u32) -> Result<String, PluginError> {
+        if number == 0 {
+            Err(PluginError::new(2, "zero is not interesting"))
+        } else {
+            Ok(format!("{number} is a fine number"))
+        }
+    }
+
+    fn do_a_panic() -> u32 {
+        panic!("this plugin always panics");
+    }
+
+    fn increment(&mut self, by: u32) -> u32 {
+        self.count += by;
+        self.count
+    }
+
+    fn count(&self) -> u32 {
+        self.count
+    }
 }
@@ -0,0 +1,80 @@
+//! Check plugins' dependencies however they are loaded.
+
+use dynamic_plugin::{plugin_impl, plugin_interface, Error};
+
+plugin_interface! {
+    extern trait Part {
+        fn id() -> u32;
+    }
+}
+
+mod core_part {
+    dynamic_plugin::plugin_impl! {
+        super::Part,
+        link: static,
+        metadata: {
+            name: "core",
+            version: "1.2.0",
+        },
+
+        fn id() -> u32 {
+            1
+        }
+    }
+}
+
+mod ui_part {
+    dynamic_plugin::plugin_impl! {
+        super::Part,
+        link: static,
+        metadata: {
+            name: "ui",
+            version: "1.0.0",
+            dependencies: { "core": "^1.1" },
+        },
+
+        fn id() -> u32 {
+            2
+        }
+    }
+}
+
+plugin_impl! {
+    Part,
+    link: static,
+    metadata: {
+        name: "app",
+        version: "1.0.0",
+        dependencies: { "core": "^2" },
+    },
+
+    fn id() -> u32 {
+        3
+    }
+}
+
+fn is_missing(result: dynamic_plugin::Result<Part>, name: &str) -> bool {
+    matches!(result, Err(Error::MissingDependency { dependency, .. }) if dependency == name)
+}
+
+#[test]
+fn checks_dependencies_are_loaded() {
+    assert!(is_missing(
+        Part::from_static(&ui_part::STATIC_PLUGIN),
+        "core"
+    ));
+
+    let core = Part::from_static(&core_part::STATIC_PLUGIN).unwrap();
+    let ui = Part::from_static(&ui_part::STATIC_PLUGIN).unwrap();
+    assert_eq!(ui.id().unwrap(), 2);
+    // The version loaded doesn't meet the requirement
+    assert!(is_missing(Part::from_static(&STATIC_PLUGIN), "core"));
+
+    // Dependencies must stay loaded
+    drop(ui);
+    drop(core);
+    assert!(is_missing(
+        Part::from_static(&ui_part::STATIC_PLUGIN),
+        "core"
+    ));
+}
@@ -0,0 +1,97 @@
+//! Load the example plugin more than once, checking that the library is
+//! shared between the plugins using it.
+//!
+//! These checks share one library, so run in their own process, one
+//! after another.
+
+use std::{path::PathBuf, sync::Mutex};
+
+use dynamic_plugin::{PluginError, PluginLoader};
+use example_plugin_host::{ExampleHost, ExampleHostImpl, ExamplePlugin};
+
+/// The messages logged by the plugin, and which host they were sent to.
+static LOG: Mutex<Vec<(&str, String)>> = Mutex::new(Vec::new());
+
+struct Host(&'static str);
+
+impl ExampleHostImpl for Host {
+    fn log(&self, message: &str) {
+        LOG.lock().unwrap().push((self.0, message.to_string()));
+    }
+
+    fn config(&self, key: &str) -> Result<String, PluginError> {
+        Err(PluginError::new(1, format!("no configuration for {key}")))
+    }
+}
+
+fn plugin_path() -> PathBuf {
+    std::env::current_exe().unwrap().with_file_name(format!(
+        "{}example_plugin{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ))
+}
+
+fn load(host: &'static str) -> ExamplePlugin {
+    let loader = PluginLoader::new().host_api(ExampleHost::host_api(Host(host)));
+    ExamplePlugin::load_plugin_with(plugin_path(), &loader).unwrap()
+}
+
+/// Take the messages logged so far.
+fn take_log() -> Vec<(&'static str, String)> {
+    std::mem::take(&mut *LOG.lock().unwrap())
+}
+
+/// Whether `host` was sent `message`.
+fn logged(log: &[(&str, String)], host: &str, message: &str) -> bool {
+    log.iter()
+        .any(|(to, logged)| *to == host && logged == message)
+}
+
+fn keeps_the_first_host_api_until_the_last_plugin_is_dropped() {
+    let first = load("first");
+    let second = load("second");
+    drop(first);
+    second.do_a_thing().unwrap();
+    let log = take_log();
+    assert!(logged(&log, "first", "The plugin did a thing"), "{log:?}");
+    assert!(!log.iter().any(|(to, _)| *to == "second"), "{log:?}");
+    drop(second);
+    take_log();
+
+    // Once every plugin is dropped, the next is given its own host API
+    let third = load("third");
+    third.do_a_thing().unwrap();
+    assert!(logged(&take_log(), "third", "The plugin did a thing"));
+    drop(third);
+    take_log();
+}
+
+/// How many times the plugin has been sent `message`.
+fn count(log: &[(&str, String)], message: &str) -> usize {
+    log.iter().filter(|(_, logged)| logged == message).count()
+}
+
+fn runs_hooks_on_first_load_and_last_unload() {
+    let first = load("first");
+    let second = load("second");
+    let log = take_log();
+    assert_eq!(count(&log, "The plugin has been loaded"), 1, "{log:?}");
+
+    drop(first);
+    assert_eq!(count(&take_log(), "The plugin is being unloaded"), 0);
+    // The library is still usable by the second plugin
+    assert_eq!(second.sum(&[1, 2]).unwrap(), 3);
+
+    drop(second);
+    let log = take_log();
+    assert_eq!(count(&log, "The plugin is being unloaded"), 1, "{log:?}");
+    // The hook is run before the host API is taken back
+    assert!(logged(&log, "first", "The plugin is being unloaded"));
+}
+
+#[test]
+fn lifecycle() {
+    keeps_the_first_host_api_until_the_last_plugin_is_dropped();
+    runs_hooks_on_first_load_and_last_unload();
+}
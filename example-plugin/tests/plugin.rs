@@ -0,0 +1,202 @@
+//! Load the example plugin and call into it as a host would.
+
+use std::path::PathBuf;
+
+use dynamic_plugin::{Error, PluginDigest, PluginError, PluginLoader};
+use example_plugin_host::{ExampleHost, ExampleHostImpl, ExamplePlugin};
+
+struct Host;
+
+impl ExampleHostImpl for Host {
+    fn log(&self, _message: &str) {}
+
+    fn config(&self, key: &str) -> Result<String, PluginError> {
+        Err(PluginError::new(1, format!("no configuration for {key}")))
+    }
+}
+
+/// The example plugin, built alongside these tests.
+fn plugin_path() -> PathBuf {
+    let deps = std::env::current_exe().unwrap();
+    deps.with_file_name(format!(
+        "{}example_plugin{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ))
+}
+
+fn load() -> ExamplePlugin {
+    let loader = PluginLoader::new().host_api(ExampleHost::host_api(Host));
+    ExamplePlugin::load_plugin_with(plugin_path(), &loader).unwrap()
+}
+
+#[test]
+fn passes_strings() {
+    let plugin = load();
+    assert!(plugin.say_hello("Jens").unwrap());
+    assert_eq!(
+        plugin.greeting("Jens").unwrap().as_str(),
+        "Hello, Jens!"
+    );
+}
+
+#[test]
+fn rejects_invalid_utf8_in_strings() {
+    let plugin = load();
+    let name = c"J\xffns";
+    assert!(matches!(
+        plugin.greeting(name),
+        Err(Error::InvalidUtf8)
+    ));
+}
+
+#[test]
+fn returns_owned_strings_and_buffers() {
+    let plugin = load();
+    assert_eq!(plugin.bytes().unwrap(), vec![1, 2, 3]);
+    assert_eq!(&*plugin.bytes_guard().unwrap(), &[4, 5, 6]);
+    let greeting = plugin.greeting("Jens").unwrap();
+    assert_eq!(greeting.to_string(), "Hello, Jens!");
+    assert_eq!(greeting.into_string(), "Hello, Jens!");
+}
+
+#[test]
+fn returns_results() {
+    let plugin = load();
+    assert_eq!(plugin.parse_number("42").unwrap().unwrap(), 42);
+    let error = plugin
+        .parse_number("forty-two")
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(error.code(), 1);
+    assert_eq!(error.message(), "invalid digit found in string");
+    assert_eq!(plugin.describe(7).unwrap().unwrap(), "7 is a fine number");
+    let error = plugin.describe(0).unwrap().unwrap_err();
+    assert_eq!(error.code(), 2);
+    assert_eq!(error.message(), "zero is not interesting");
+}
+
+#[test]
+fn reports_panics() {
+    let plugin = load();
+    match plugin.do_a_panic() {
+        Err(Error::PluginPanicked { function, message }) => {
+            assert_eq!(function, "do_a_panic");
+            assert!(
+                message.starts_with("this plugin always panics"),
+                "{message}"
+            );
+        }
+        other => panic!("expected a panic, got {other:?}"),
+    }
+    // The plugin can still be called after it panics
+    assert_eq!(plugin.sum(&[1, 2, 3]).unwrap(), 6);
+}
+
+#[test]
+fn exports_metadata() {
+    let plugin = load();
+    let metadata = plugin.metadata().unwrap();
+    // Fields not given in `plugin_impl!` come from the plugin's manifest
+    assert_eq!(metadata.name, "example-plugin");
+    assert_eq!(metadata.version.to_string(), "0.0.0");
+    assert_eq!(metadata.authors, ["Lily Hopkins <lily@hpkns.uk>"]);
+    assert_eq!(metadata.description.as_deref(), Some("An example plugin"));
+    assert_eq!(metadata.homepage, None);
+    assert_eq!(metadata.license.as_deref(), Some("MIT"));
+}
+
+#[test]
+fn reads_metadata_without_loading() {
+    let metadata = PluginLoader::read_metadata(plugin_path()).unwrap();
+    assert_eq!(metadata.as_ref(), load().metadata());
+}
+
+#[test]
+fn discovers_plugins() {
+    let dir = std::env::temp_dir().join(format!("dynamic-plugin-discovery-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy(plugin_path(), dir.join(plugin_path().file_name().unwrap())).unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a plugin").unwrap();
+
+    let loader = PluginLoader::new().host_api(ExampleHost::host_api(Host));
+    let report = ExamplePlugin::discover_plugins_with(&dir, &loader);
+    assert_eq!(report.loaded.len(), 1);
+    assert_eq!(report.rejected.len(), 1);
+    assert!(matches!(report.rejected[0].error, Error::NotASharedLibrary));
+
+    // Plugins are filtered by the metadata in their files
+    let loader = loader.filter(|metadata| metadata.name != "example-plugin");
+    let report = ExamplePlugin::discover_plugins_with(&dir, &loader);
+    assert!(report.loaded.is_empty());
+    assert_eq!(report.rejected.len(), 2);
+    assert!(report
+        .rejected
+        .iter()
+        .any(|rejected| matches!(rejected.error, Error::Filtered)));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn checks_digests() {
+    let digest = PluginDigest::of_file(plugin_path()).unwrap();
+    let loader = PluginLoader::new().host_api(ExampleHost::host_api(Host));
+    let plugin =
+        ExamplePlugin::load_plugin_with(plugin_path(), &loader.clone().allow_digests([digest]))
+            .unwrap();
+    assert_eq!(plugin.digest(), Some(&digest));
+
+    let other = PluginDigest([0; 32]);
+    let result =
+        ExamplePlugin::load_plugin_with(plugin_path(), &loader.clone().allow_digests([other]));
+    assert!(matches!(result, Err(Error::NotAllowed { digest: found }) if found == digest));
+
+    let allowlist =
+        std::env::temp_dir().join(format!("dynamic-plugin-allowlist-{}", std::process::id()));
+    std::fs::write(
+        &allowlist,
+        format!("# Trusted builds\n{digest}  example_plugin\n"),
+    )
+    .unwrap();
+    let plugin =
+        ExamplePlugin::load_plugin_with(plugin_path(), &loader.allowlist_file(&allowlist).unwrap())
+            .unwrap();
+    assert_eq!(plugin.digest(), Some(&digest));
+    std::fs::remove_file(allowlist).unwrap();
+}
+
+/// Compile `source` into a dynamic library in a directory of its own.
+fn compile_library(name: &str, source: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dynamic-plugin-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("lib.rs"), source).unwrap();
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let status = std::process::Command::new(rustc)
+        .args(["--crate-type", "cdylib", "--crate-name", name, "-o"])
+        .arg(plugin_path_in(&dir, name))
+        .arg(dir.join("lib.rs"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    plugin_path_in(&dir, name)
+}
+
+fn plugin_path_in(dir: &std::path::Path, name: &str) -> PathBuf {
+    dir.join(format!(
+        "{}{name}{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ))
+}
+
+#[test]
+fn rejects_libraries_without_an_abi() {
+    let path = compile_library(
+        "no_abi",
+        "#[no_mangle] pub extern \"C\" fn _dynamic_plugin_signature() -> u64 { 0 }",
+    );
+    let result = ExamplePlugin::load_plugin_and_check(&path);
+    assert!(matches!(result, Err(Error::IncompatibleAbi)));
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
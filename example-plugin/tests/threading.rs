@@ -0,0 +1,193 @@
+//! Check plugins are called as their interfaces' threading contracts say.
+
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::Duration,
+};
+
+use dynamic_plugin::{plugin_interface, Error, Result};
+
+plugin_interface! {
+    extern trait SharedPlugin {
+        fn work(millis: u64) -> u32;
+    }
+}
+
+plugin_interface! {
+    #[single_threaded]
+    extern trait ExclusivePlugin {
+        fn work(millis: u64) -> u32;
+    }
+}
+
+plugin_interface! {
+    #[main_thread_only]
+    extern trait LocalPlugin {
+        fn work(millis: u64) -> u32;
+    }
+}
+
+plugin_interface! {
+    #[single_threaded]
+    extern trait ExclusiveCallback {
+        fn call_back() -> bool;
+    }
+}
+
+plugin_interface! {
+    #[non_reentrant]
+    extern trait NonReentrantCallback {
+        fn call_back() -> bool;
+    }
+}
+
+/// Work for `millis`, returning how many calls to a plugin whose calls
+/// are counted by `running` were running at once.
+fn work(running: &AtomicU32, millis: u64) -> u32 {
+    let at_once = running.fetch_add(1, Ordering::SeqCst) + 1;
+    thread::sleep(Duration::from_millis(millis));
+    running.fetch_sub(1, Ordering::SeqCst);
+    at_once
+}
+
+mod shared {
+    use std::sync::atomic::AtomicU32;
+
+    static RUNNING: AtomicU32 = AtomicU32::new(0);
+
+    dynamic_plugin::plugin_impl! {
+        super::SharedPlugin,
+        link: static,
+
+        fn work(millis: u64) -> u32 {
+            super::work(&RUNNING, millis)
+        }
+    }
+}
+
+mod exclusive {
+    use std::sync::atomic::AtomicU32;
+
+    static RUNNING: AtomicU32 = AtomicU32::new(0);
+
+    dynamic_plugin::plugin_impl! {
+        super::ExclusivePlugin,
+        threading: single_threaded,
+        link: static,
+
+        fn work(millis: u64) -> u32 {
+            super::work(&RUNNING, millis)
+        }
+    }
+}
+
+mod local {
+    use std::sync::atomic::AtomicU32;
+
+    static RUNNING: AtomicU32 = AtomicU32::new(0);
+
+    dynamic_plugin::plugin_impl! {
+        super::LocalPlugin,
+        threading: main_thread_only,
+        link: static,
+
+        fn work(millis: u64) -> u32 {
+            super::work(&RUNNING, millis)
+        }
+    }
+}
+
+/// Whether a call made back into the plugin, as by a host function the
+/// plugin called, failed as re-entrant.
+fn called_back<T>(call: Result<T>) -> bool {
+    matches!(call, Err(Error::Reentrant { function }) if function == "call_back")
+}
+
+mod exclusive_callback {
+    use std::sync::OnceLock;
+
+    pub static PLUGIN: OnceLock<super::ExclusiveCallback> = OnceLock::new();
+
+    dynamic_plugin::plugin_impl! {
+        super::ExclusiveCallback,
+        threading: single_threaded,
+        link: static,
+
+        fn call_back() -> bool {
+            super::called_back(PLUGIN.get().unwrap().call_back())
+        }
+    }
+}
+
+mod non_reentrant_callback {
+    use std::sync::OnceLock;
+
+    pub static PLUGIN: OnceLock<super::NonReentrantCallback> = OnceLock::new();
+
+    dynamic_plugin::plugin_impl! {
+        super::NonReentrantCallback,
+        reentrant: false,
+        link: static,
+
+        fn call_back() -> bool {
+            super::called_back(PLUGIN.get().unwrap().call_back())
+        }
+    }
+}
+
+/// The most calls which ran at once, when `call` is made from four
+/// threads at the same time.
+fn most_at_once(call: impl Fn() -> u32 + Sync) -> u32 {
+    thread::scope(|scope| {
+        let calls: Vec<_> = (0..4).map(|_| scope.spawn(&call)).collect();
+        calls
+            .into_iter()
+            .map(|call| call.join().unwrap())
+            .max()
+            .unwrap()
+    })
+}
+
+fn assert_send_and_sync<T: Send + Sync>() {}
+
+#[test]
+fn runs_calls_to_thread_safe_plugins_at_once() {
+    assert_send_and_sync::<SharedPlugin>();
+    let plugin = SharedPlugin::from_static(&shared::STATIC_PLUGIN).unwrap();
+    assert!(most_at_once(|| plugin.work(100).unwrap()) > 1);
+}
+
+#[test]
+fn runs_calls_to_single_threaded_plugins_one_at_a_time() {
+    assert_send_and_sync::<ExclusivePlugin>();
+    let plugin = ExclusivePlugin::from_static(&exclusive::STATIC_PLUGIN).unwrap();
+    assert_eq!(most_at_once(|| plugin.work(20).unwrap()), 1);
+}
+
+#[test]
+fn calls_main_thread_only_plugins_from_their_own_thread() {
+    // `LocalPlugin` is neither `Send` nor `Sync`, so the compiler stops
+    // it being used from other threads
+    let plugin = LocalPlugin::from_static(&local::STATIC_PLUGIN).unwrap();
+    assert_eq!(plugin.work(1).unwrap(), 1);
+}
+
+#[test]
+fn rejects_calls_back_into_single_threaded_plugins() {
+    let plugin = exclusive_callback::PLUGIN.get_or_init(|| {
+        ExclusiveCallback::from_static(&exclusive_callback::STATIC_PLUGIN).unwrap()
+    });
+    assert!(plugin.call_back().unwrap());
+    // The outer call released the lock when it returned
+    assert!(plugin.call_back().unwrap());
+}
+
+#[test]
+fn rejects_calls_back_into_non_reentrant_plugins() {
+    let plugin = non_reentrant_callback::PLUGIN.get_or_init(|| {
+        NonReentrantCallback::from_static(&non_reentrant_callback::STATIC_PLUGIN).unwrap()
+    });
+    assert!(plugin.call_back().unwrap());
+    assert!(plugin.call_back().unwrap());
+}
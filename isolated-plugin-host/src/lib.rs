@@ -0,0 +1,31 @@
+use dynamic_plugin::{plugin_interface, PluginError, PluginStr};
+
+plugin_interface! {
+    extern trait IsolatedPlugin {
+        /// Add two numbers
+        fn add(a: u32, b: u32) -> u32;
+        /// Scale some points
+        fn scale(points: &mut [[f32; 2]], by: f32);
+        /// Sleep for a number of milliseconds
+        fn sleep(millis: u64);
+        /// Sleep for a number of milliseconds, for at most a tenth of a second
+        #[timeout(millis = 100)]
+        fn nap(millis: u64);
+        /// Abort the process the plugin is running in
+        fn crash();
+        /// Make the plugin hang when it is unloaded
+        fn hang_on_unload();
+        /// Get the ID of the process the plugin is running in
+        fn process_id() -> u32;
+        /// Read a file
+        fn read_file(path: PluginStr) -> Result<Vec<u8>, PluginError>;
+        /// Open a network socket, returning whether it was opened
+        fn open_socket() -> bool;
+        /// Open a network socket on another thread, after a number of milliseconds
+        fn open_socket_later(millis: u64);
+        /// Allocate a number of bytes, returning how many were allocated
+        fn allocate(bytes: u64) -> u64;
+        /// Use the CPU for a number of milliseconds
+        fn spin(millis: u64);
+    }
+}
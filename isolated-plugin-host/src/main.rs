@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use dynamic_plugin::{Error, PluginLoader, ResourceLimits, Result, SandboxPolicy};
+use isolated_plugin_host::IsolatedPlugin;
+
+fn main() -> Result<()> {
+    // Workers run this executable again, and serve the plugin from here
+    IsolatedPlugin::run_isolated_worker();
+
+    let path = std::env::current_exe()?.with_file_name(format!(
+        "{}isolated_plugin{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ));
+    let loader = PluginLoader::new()
+        .isolate(true)
+        .call_timeout(Duration::from_secs(1));
+    let plugin = IsolatedPlugin::load_plugin_with(&path, &loader)?;
+    println!("The plugin is running in process {}", plugin.process_id()?);
+    assert_ne!(plugin.process_id()?, std::process::id());
+
+    // Arguments are copied to the worker, and slices copied back
+    assert_eq!(plugin.add(2, 3)?, 5);
+    let mut points = [[1.0, 2.0], [3.0, 4.0]];
+    plugin.scale(&mut points, 2.0)?;
+    assert_eq!(points, [[2.0, 4.0], [6.0, 8.0]]);
+
+    // A plugin which hangs is killed, rather than hanging the host
+    match plugin.sleep(60_000) {
+        Err(Error::Timeout { function, timeout }) => {
+            println!("`{function}` took longer than {timeout:?}");
+        }
+        result => panic!("the call should have timed out, but returned {result:?}"),
+    }
+    assert!(!plugin.is_healthy());
+
+    // Functions can have timeouts of their own, instead of the loader's
+    let plugin = IsolatedPlugin::load_plugin_with(&path, &loader)?;
+    plugin.nap(10)?;
+    assert!(matches!(plugin.nap(60_000), Err(Error::Timeout { .. })));
+
+    // A plugin which crashes doesn't take the host with it
+    let plugin = IsolatedPlugin::load_plugin_with(&path, &loader)?;
+    match plugin.crash() {
+        Err(Error::PluginCrashed(status)) => println!("The plugin crashed: {status}"),
+        result => panic!("the plugin should have crashed, but returned {result:?}"),
+    }
+    assert!(!plugin.is_healthy());
+
+    // A plugin which uses more than its limits is stopped
+    let limits = ResourceLimits::new().memory(512 * 1024 * 1024);
+    let plugin = IsolatedPlugin::load_plugin_with(&path, &loader.clone().resource_limits(limits))?;
+    match plugin.allocate(1024 * 1024 * 1024) {
+        Err(Error::ResourceLimitExceeded { resource }) => {
+            println!("The plugin used too much {resource}");
+        }
+        result => panic!("the plugin should have been stopped, but returned {result:?}"),
+    }
+
+    // Plugins can be given limits of their own
+    let loader_with_limits = loader
+        .clone()
+        .resource_limits(limits)
+        .plugin_resource_limits("isolated-plugin", limits.memory(2 * 1024 * 1024 * 1024));
+    let plugin = IsolatedPlugin::load_plugin_with(&path, &loader_with_limits)?;
+    plugin.allocate(1024 * 1024 * 1024)?;
+
+    // A sandboxed plugin can only read the files it is allowed to, and
+    // is stopped if it opens a socket
+    let policy = SandboxPolicy::new().allow_read(path.parent().unwrap_or(&path));
+    let plugin = IsolatedPlugin::load_plugin_with(&path, &loader.clone().sandbox(policy))?;
+    assert!(plugin
+        .read_file(path.to_string_lossy().as_ref())?
+        .is_ok());
+    assert!(plugin.read_file("/etc/hostname")?.is_err());
+    match plugin.open_socket() {
+        Err(Error::SandboxViolation { syscall }) => {
+            println!("The plugin was stopped calling {syscall:?}");
+        }
+        result => panic!("the plugin should have been stopped, but returned {result:?}"),
+    }
+    assert!(!plugin.is_healthy());
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use dynamic_plugin::{plugin_impl, PluginError};
+
+/// Whether to hang when unloaded.
+static HANG_ON_UNLOAD: AtomicBool = AtomicBool::new(false);
+
+plugin_impl! {
+    isolated_plugin_host::IsolatedPlugin,
+
+    #[on_unload]
+    fn on_unload() {
+        while HANG_ON_UNLOAD.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    fn add(a: u32, b: u32) -> u32 {
+        a + b
+    }
+
+    fn scale(points: &mut [[f32; 2]], by: f32) {
+        for point in points.iter_mut().flatten() {
+            *point *= by;
+        }
+    }
+
+    fn sleep(millis: u64) {
+        std::thread::sleep(Duration::from_millis(millis));
+    }
+
+    fn nap(millis: u64) {
+        std::thread::sleep(Duration::from_millis(millis));
+    }
+
+    fn crash() {
+        std::process::abort();
+    }
+
+    fn hang_on_unload() {
+        HANG_ON_UNLOAD.store(true, Ordering::Relaxed);
+    }
+
+    fn process_id() -> u32 {
+        std::process::id()
+    }
+
+    fn read_file(path: &str) -> Result<Vec<u8>, PluginError> {
+        std::fs::read(path)
+            .map_err(|e| PluginError::new(e.raw_os_error().unwrap_or(-1), e.to_string()))
+    }
+
+    fn open_socket() -> bool {
+        std::net::UdpSocket::bind("127.0.0.1:0").is_ok()
+    }
+
+    fn open_socket_later(millis: u64) {
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(millis));
+            std::net::UdpSocket::bind("127.0.0.1:0").is_ok()
+        });
+    }
+
+    fn allocate(bytes: u64) -> u64 {
+        let bytes = usize::try_from(bytes).unwrap_or(usize::MAX);
+        std::hint::black_box(vec![0_u8; bytes]).len() as u64
+    }
+
+    fn spin(millis: u64) {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(millis) {
+            std::hint::spin_loop();
+        }
+    }
+}
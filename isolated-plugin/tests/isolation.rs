@@ -0,0 +1,270 @@
+//! Tests for isolated plugins. Their workers run this test's executable
+//! again, which must serve the plugin before doing anything else, so
+//! these tests run without a test harness.
+
+use std::{
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use dynamic_plugin::{libc, Error, PluginLoader, ResourceLimits, SandboxPolicy};
+use isolated_plugin_host::IsolatedPlugin;
+
+fn main() {
+    IsolatedPlugin::run_isolated_worker();
+
+    let tests: &[(&str, fn())] = &[
+        ("runs_plugins_in_a_worker", runs_plugins_in_a_worker),
+        (
+            "copies_values_to_and_from_workers",
+            copies_values_to_and_from_workers,
+        ),
+        ("reports_crashes", reports_crashes),
+        ("times_out_calls", times_out_calls),
+        (
+            "times_out_functions_by_their_own_timeouts",
+            times_out_functions_by_their_own_timeouts,
+        ),
+        ("stops_workers_when_dropped", stops_workers_when_dropped),
+        ("kills_hung_workers", kills_hung_workers),
+        ("sandboxes_plugins", sandboxes_plugins),
+        ("limits_resources", limits_resources),
+        ("limits_resources_per_plugin", limits_resources_per_plugin),
+        (
+            "reports_violations_between_calls_as_crashes",
+            reports_violations_between_calls_as_crashes,
+        ),
+    ];
+    for (name, test) in tests {
+        test();
+        println!("test {name} ... ok");
+    }
+}
+
+fn plugin_path() -> PathBuf {
+    let deps = std::env::current_exe().unwrap();
+    deps.with_file_name(format!(
+        "{}isolated_plugin{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ))
+}
+
+fn load_with(loader: &PluginLoader) -> IsolatedPlugin {
+    IsolatedPlugin::load_plugin_with(plugin_path(), loader).unwrap()
+}
+
+fn load() -> IsolatedPlugin {
+    load_with(&PluginLoader::new().isolate(true))
+}
+
+/// Whether the process with `id` is still running.
+fn is_running(id: u32) -> bool {
+    // Safety: signal 0 only checks the process exists
+    unsafe { libc::kill(id.try_into().unwrap(), 0) == 0 }
+}
+
+fn runs_plugins_in_a_worker() {
+    let plugin = load();
+    let worker = plugin.process_id().unwrap();
+    assert_ne!(worker, std::process::id());
+    assert!(is_running(worker));
+    assert!(plugin.is_healthy());
+}
+
+fn copies_values_to_and_from_workers() {
+    let plugin = load();
+    assert_eq!(plugin.add(2, 3).unwrap(), 5);
+    let mut points = [[1.0, -2.0], [0.5, 4.0]];
+    plugin.scale(&mut points, 2.0).unwrap();
+    assert_eq!(points, [[2.0, -4.0], [1.0, 8.0]]);
+}
+
+fn reports_crashes() {
+    let plugin = load();
+    let Err(Error::PluginCrashed(status)) = plugin.crash() else {
+        panic!("the worker should have crashed");
+    };
+    assert_eq!(status.signal(), Some(libc::SIGABRT));
+    assert!(!plugin.is_healthy());
+    assert!(matches!(plugin.add(1, 1), Err(Error::PluginCrashed(_))));
+}
+
+fn times_out_calls() {
+    let timeout = Duration::from_millis(200);
+    let plugin = load_with(&PluginLoader::new().isolate(true).call_timeout(timeout));
+    let worker = plugin.process_id().unwrap();
+    plugin.sleep(10).unwrap();
+
+    let start = Instant::now();
+    let result = plugin.sleep(60_000);
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(matches!(
+        result,
+        Err(Error::Timeout { function, timeout: found }) if function == "sleep" && found == timeout
+    ));
+    assert!(!is_running(worker));
+    assert!(!plugin.is_healthy());
+    assert!(matches!(plugin.add(1, 1), Err(Error::Timeout { .. })));
+}
+
+fn times_out_functions_by_their_own_timeouts() {
+    let loader = PluginLoader::new()
+        .isolate(true)
+        .call_timeout(Duration::from_secs(60));
+    let plugin = load_with(&loader);
+    plugin.nap(10).unwrap();
+    assert!(matches!(
+        plugin.nap(60_000),
+        Err(Error::Timeout { function, timeout }) if function == "nap" && timeout == Duration::from_millis(100)
+    ));
+}
+
+fn stops_workers_when_dropped() {
+    let plugin = load();
+    let worker = plugin.process_id().unwrap();
+    drop(plugin);
+    assert!(!is_running(worker));
+
+    // Even if the plugin hangs while being unloaded
+    let plugin = load();
+    let worker = plugin.process_id().unwrap();
+    plugin.hang_on_unload().unwrap();
+    let start = Instant::now();
+    drop(plugin);
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(!is_running(worker));
+}
+
+fn kills_hung_workers() {
+    let plugin = Arc::new(load());
+    let worker = plugin.process_id().unwrap();
+    let call = thread::spawn({
+        let plugin = Arc::clone(&plugin);
+        move || plugin.sleep(60_000)
+    });
+    thread::sleep(Duration::from_millis(200));
+
+    // Checking the worker doesn't wait for the call
+    let start = Instant::now();
+    assert!(plugin.is_healthy());
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    plugin.kill_worker();
+    assert!(!plugin.is_healthy());
+    assert!(matches!(call.join().unwrap(), Err(Error::WorkerKilled)));
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(!is_running(worker));
+    assert!(matches!(plugin.add(1, 1), Err(Error::WorkerKilled)));
+
+    // Workers can be killed between calls too
+    let plugin = load();
+    let worker = plugin.process_id().unwrap();
+    plugin.kill_worker();
+    assert!(matches!(plugin.add(1, 1), Err(Error::WorkerKilled)));
+    assert!(!is_running(worker));
+}
+
+fn sandboxes_plugins() {
+    let dir = std::env::temp_dir().join(format!("isolated-plugin-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("allowed.txt"), "allowed").unwrap();
+    std::fs::write(
+        std::env::temp_dir().join("isolated-plugin-denied.txt"),
+        "denied",
+    )
+    .unwrap();
+    let policy = SandboxPolicy::new().allow_read(&dir);
+    let plugin = load_with(&PluginLoader::new().isolate(true).sandbox(policy));
+
+    let read = |path: PathBuf| plugin.read_file(path.to_str().unwrap()).unwrap();
+    assert_eq!(read(dir.join("allowed.txt")).unwrap(), b"allowed");
+    let denied = read(std::env::temp_dir().join("isolated-plugin-denied.txt")).unwrap_err();
+    assert_eq!(denied.code(), libc::EACCES);
+    assert!(plugin.is_healthy());
+
+    assert!(matches!(
+        plugin.open_socket(),
+        Err(Error::SandboxViolation { syscall: Some(syscall) }) if syscall == "socket"
+    ));
+    assert!(!plugin.is_healthy());
+    assert!(matches!(
+        plugin.add(1, 1),
+        Err(Error::SandboxViolation { .. })
+    ));
+
+    // Without a sandbox, the same plugin can do both
+    let plugin = load();
+    assert!(plugin.open_socket().unwrap());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn reports_violations_between_calls_as_crashes() {
+    let plugin = load_with(
+        &PluginLoader::new()
+            .isolate(true)
+            .sandbox(SandboxPolicy::new()),
+    );
+    plugin.open_socket_later(10).unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(matches!(plugin.add(1, 1), Err(Error::PluginCrashed(_))));
+    assert!(!plugin.is_healthy());
+}
+
+fn limits_resources() {
+    let memory = ResourceLimits::new().memory(512 * 1024 * 1024);
+    let plugin = load_with(&PluginLoader::new().isolate(true).resource_limits(memory));
+    assert_eq!(plugin.allocate(1024).unwrap(), 1024);
+    assert!(matches!(
+        plugin.allocate(1024 * 1024 * 1024),
+        Err(Error::ResourceLimitExceeded { resource }) if resource == "memory"
+    ));
+    assert!(!plugin.is_healthy());
+
+    // Crashes are still crashes when resources are limited
+    let plugin = load_with(&PluginLoader::new().isolate(true).resource_limits(memory));
+    assert!(matches!(
+        plugin.crash(),
+        Err(Error::PluginCrashed(status)) if status.signal() == Some(libc::SIGABRT)
+    ));
+
+    let cpu_time = ResourceLimits::new().cpu_time(Duration::from_secs(1));
+    let plugin = load_with(&PluginLoader::new().isolate(true).resource_limits(cpu_time));
+    plugin.spin(10).unwrap();
+    assert!(matches!(
+        plugin.spin(10_000),
+        Err(Error::ResourceLimitExceeded { resource }) if resource == "CPU time"
+    ));
+    let plugin = load_with(&PluginLoader::new().isolate(true).resource_limits(cpu_time));
+    assert!(matches!(plugin.crash(), Err(Error::PluginCrashed(_))));
+}
+
+fn limits_resources_per_plugin() {
+    let small = ResourceLimits::new().memory(512 * 1024 * 1024);
+    let large = ResourceLimits::new().memory(4 * 1024 * 1024 * 1024);
+    let loader = PluginLoader::new()
+        .isolate(true)
+        .resource_limits(small)
+        .plugin_resource_limits("isolated-plugin", large);
+    let plugin = load_with(&loader);
+    assert_eq!(
+        plugin.allocate(1024 * 1024 * 1024).unwrap(),
+        1024 * 1024 * 1024
+    );
+
+    let loader = PluginLoader::new()
+        .isolate(true)
+        .resource_limits(large)
+        .plugin_resource_limits("another-plugin", small);
+    let plugin = load_with(&loader);
+    assert!(plugin.allocate(1024 * 1024 * 1024).is_ok());
+    let loader = loader.plugin_resource_limits("isolated-plugin", small);
+    let plugin = load_with(&loader);
+    assert!(matches!(
+        plugin.allocate(1024 * 1024 * 1024),
+        Err(Error::ResourceLimitExceeded { .. })
+    ));
+}
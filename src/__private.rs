@@ -0,0 +1,532 @@
+//! Support functions for code generated by the dynamic-plugin macros.
+//! These are not part of the public API.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_void, CStr, OsStr},
+    fs::File,
+    mem::MaybeUninit,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::Once,
+};
+
+use libc::c_char;
+
+use crate::{Error, HostApi, PluginError, PluginLibrary, PluginResult, PluginString, Result};
+
+pub use crate::{
+    abi::{AbiDescriptor, ABI},
+    calls::{CallGuard, CallLock},
+    future::{async_call, async_result, complete_async},
+    library::StaticSymbol,
+    metadata::{cargo_list, cargo_list_len, MetadataFields},
+    tasks::{spawn_async, AsyncContext, RunningTasks},
+};
+#[cfg(feature = "tokio")]
+pub use crate::{blocking::run_blocking, calls::CallPermit};
+#[cfg(feature = "tracing")]
+pub use crate::trace::{trace_report, traced_async_call, traced_call, traced_load};
+#[cfg(unix)]
+pub use crate::{
+    isolation::CallFn,
+    wire::{Reader, WireValue, Writer},
+};
+
+thread_local! {
+    /// The message of the last panic on this thread, if not yet taken.
+    static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// The message recorded in place of a panic's when a string argument
+/// isn't valid UTF-8, so that the caller gets [`Error::InvalidUtf8`].
+const INVALID_UTF8_ARGUMENT: &str = "\u{18}invalid UTF-8 in a string argument";
+
+/// The payload a call unwinds with when a string argument isn't valid
+/// UTF-8.
+struct InvalidUtf8Argument;
+
+/// Rebuild a string argument received from the host. The host can pass
+/// any C string, so one which isn't valid UTF-8 stops the call, which
+/// returns [`Error::InvalidUtf8`].
+///
+/// # Safety
+///
+/// `ptr` must point to a nul-terminated string that outlives `'a`, and
+/// this must be called inside [`catch_panic`].
+#[must_use]
+pub unsafe fn str_arg<'a>(ptr: *const c_char) -> &'a str {
+    CStr::from_ptr(ptr).to_str().unwrap_or_else(|_| {
+        // Unwinding without panicking skips the panic hook, so nothing
+        // is printed
+        panic::resume_unwind(Box::new(InvalidUtf8Argument))
+    })
+}
+
+/// Rebuild a C string argument received from the host.
+///
+/// # Safety
+///
+/// `ptr` must point to a nul-terminated string that outlives `'a`.
+#[must_use]
+pub unsafe fn c_str_arg<'a>(ptr: *const c_char) -> &'a CStr {
+    CStr::from_ptr(ptr)
+}
+
+/// Rebuild a slice argument received from the host.
+///
+/// # Safety
+///
+/// `ptr` and `len` must describe a valid slice that outlives `'a`.
+#[must_use]
+pub unsafe fn slice_arg<'a, T>(ptr: *const T, len: usize) -> &'a [T] {
+    if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// Rebuild a mutable slice argument received from the host.
+///
+/// # Safety
+///
+/// `ptr` and `len` must describe a valid slice that outlives `'a` and
+/// is not aliased.
+#[must_use]
+pub unsafe fn slice_arg_mut<'a, T>(ptr: *mut T, len: usize) -> &'a mut [T] {
+    if len == 0 {
+        &mut []
+    } else {
+        std::slice::from_raw_parts_mut(ptr, len)
+    }
+}
+
+/// Run a plugin function, catching any panic so that it does not
+/// unwind into the host. If a panic occurs, its message is recorded
+/// for [`take_panic`] and an uninitialised value is returned.
+pub fn catch_panic<T, F: FnOnce() -> T>(f: F) -> MaybeUninit<T> {
+    catch(f).map_or(MaybeUninit::uninit(), MaybeUninit::new)
+}
+
+/// Run `f`, catching and recording any panic as [`catch_panic`] does.
+pub(crate) fn catch<T, F: FnOnce() -> T>(f: F) -> Option<T> {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        // Record where panics occur, then continue as normal
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let message = panic_message(info.payload());
+            let message = match info.location() {
+                Some(location) => format!("{message} (at {location})"),
+                None => message,
+            };
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some(message));
+            previous(info);
+        }));
+    });
+
+    LAST_PANIC.with(|last| last.borrow_mut().take());
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => {
+            // `f` may have caught a panic of its own, which the hook
+            // still recorded
+            LAST_PANIC.with(|last| last.borrow_mut().take());
+            Some(value)
+        }
+        Err(payload) => {
+            LAST_PANIC.with(|last| {
+                let mut last = last.borrow_mut();
+                if payload.is::<InvalidUtf8Argument>() {
+                    *last = Some(INVALID_UTF8_ARGUMENT.to_string());
+                } else if last.is_none() {
+                    // Another panic hook has replaced ours
+                    *last = Some(panic_message(&*payload));
+                }
+            });
+            None
+        }
+    }
+}
+
+/// Take the message of the last panic caught on this thread by
+/// [`catch`].
+pub(crate) fn take_last_panic() -> String {
+    LAST_PANIC
+        .with(|last| last.borrow_mut().take())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string())
+}
+
+/// The error for a call which was stopped by a caught panic with
+/// `message`, built by `panicked` unless the call was stopped by a
+/// string argument which isn't valid UTF-8.
+pub(crate) fn caught_panic(message: String, panicked: impl FnOnce(String) -> Error) -> Error {
+    if message == INVALID_UTF8_ARGUMENT {
+        Error::InvalidUtf8
+    } else {
+        panicked(message)
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+/// Take the message of the last panic caught on this thread by
+/// [`catch_panic`], writing it to `message` and returning true if there
+/// was one.
+///
+/// # Safety
+///
+/// `message` must be valid to write a [`PluginString`] to.
+pub unsafe extern "C" fn take_panic(message: *mut PluginString) -> bool {
+    match LAST_PANIC.with(|last| last.borrow_mut().take()) {
+        Some(last) => {
+            message.write(last.into());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Check whether the last call to `function` in `library` panicked.
+///
+/// # Errors
+///
+/// - [`Error::PluginPanicked`] if the function panicked.
+/// - [`Error::InvalidUtf8`] if a string argument wasn't valid UTF-8.
+///
+/// # Safety
+///
+/// `library` must be a plugin, exposing `_dynamic_plugin_take_panic` if
+/// it exposes anything by that name.
+pub unsafe fn check_panic(library: &PluginLibrary, function: &str) -> Result<()> {
+    let Ok(take_panic) = library
+        .get::<unsafe extern "C" fn(*mut PluginString) -> bool>(b"_dynamic_plugin_take_panic")
+    else {
+        // Plugins not written with these macros cannot report panics
+        return Ok(());
+    };
+    match take_panic_from(take_panic) {
+        Some(message) => Err(caught_panic(message, |message| Error::PluginPanicked {
+            function: function.to_string(),
+            message,
+        })),
+        None => Ok(()),
+    }
+}
+
+/// Take the message of the last panic caught by the other side of the
+/// plugin boundary, using its `take_panic` function.
+///
+/// # Safety
+///
+/// `take_panic` must behave as [`take_panic`].
+pub unsafe fn take_panic_from(
+    take_panic: unsafe extern "C" fn(*mut PluginString) -> bool,
+) -> Option<String> {
+    let mut message = MaybeUninit::uninit();
+    if take_panic(message.as_mut_ptr()) {
+        Some(message.assume_init().into_string())
+    } else {
+        None
+    }
+}
+
+/// Check `value`, received from the other side of the plugin boundary,
+/// with `check` before it is used.
+///
+/// # Errors
+///
+/// - Any error returned by `check`.
+pub fn received<T>(value: T, check: impl FnOnce(&T) -> Result<()>) -> Result<T> {
+    check(&value).map(|()| value)
+}
+
+/// Check that a string received from the other side of the plugin
+/// boundary is valid UTF-8.
+///
+/// # Errors
+///
+/// - [`Error::InvalidUtf8`] if it is not.
+pub fn check_string(value: &PluginString) -> Result<()> {
+    value.check()
+}
+
+/// Check that an error received from the other side of the plugin
+/// boundary has a valid UTF-8 message.
+///
+/// # Errors
+///
+/// - [`Error::InvalidUtf8`] if it does not.
+pub fn check_error(value: &PluginError) -> Result<()> {
+    value.check()
+}
+
+/// Check the value held by a result received from the other side of the
+/// plugin boundary, with `ok` or `err`.
+///
+/// # Errors
+///
+/// - Any error returned by `ok` or `err`.
+pub fn check_result<T, E>(
+    value: &PluginResult<T, E>,
+    ok: impl FnOnce(&T) -> Result<()>,
+    err: impl FnOnce(&E) -> Result<()>,
+) -> Result<()> {
+    match value.as_result() {
+        Ok(value) => ok(value),
+        Err(value) => err(value),
+    }
+}
+
+/// Accept a value received from the other side of the plugin boundary
+/// without checking it.
+///
+/// # Errors
+///
+/// Never.
+pub fn unchecked<T>(_value: &T) -> Result<()> {
+    Ok(())
+}
+
+/// Create a [`HostApi`] for the host interface with `signature`, from
+/// an `implementation` and a function which builds the vtable given a
+/// pointer to it.
+pub fn host_api<T, V, F>(signature: u64, implementation: T, vtable: F) -> HostApi
+where
+    T: Send + Sync + 'static,
+    V: 'static,
+    F: FnOnce(*const c_void) -> V,
+{
+    HostApi::new(signature, implementation, vtable)
+}
+
+/// Check whether the last call to `function` in the host panicked.
+///
+/// # Errors
+///
+/// - [`Error::HostPanicked`] if the function panicked.
+/// - [`Error::InvalidUtf8`] if a string argument wasn't valid UTF-8.
+///
+/// # Safety
+///
+/// `take_panic` must behave as [`take_panic`].
+pub unsafe fn check_host_panic(
+    take_panic: unsafe extern "C" fn(*mut PluginString) -> bool,
+    function: &str,
+) -> Result<()> {
+    match take_panic_from(take_panic) {
+        Some(message) => Err(caught_panic(message, |message| Error::HostPanicked {
+            function: function.to_string(),
+            message,
+        })),
+        None => Ok(()),
+    }
+}
+
+/// The result of a plugin's `on_load` hook.
+pub trait OnLoadResult {
+    /// Convert into a `Result`.
+    ///
+    /// # Errors
+    ///
+    /// If the hook failed.
+    fn into_result(self) -> std::result::Result<(), PluginError>;
+}
+
+impl OnLoadResult for () {
+    fn into_result(self) -> std::result::Result<(), PluginError> {
+        Ok(())
+    }
+}
+
+impl OnLoadResult for std::result::Result<(), PluginError> {
+    fn into_result(self) -> std::result::Result<(), PluginError> {
+        self
+    }
+}
+
+/// Run a plugin's `on_load` hook, writing any error to `error` and
+/// returning true if it succeeded.
+///
+/// # Safety
+///
+/// `error` must be valid to write a [`PluginError`] to.
+pub unsafe fn on_load<R: OnLoadResult, F: FnOnce() -> R>(error: *mut PluginError, f: F) -> bool {
+    let Some(result) = catch(|| f().into_result()) else {
+        // The panic is reported by `take_panic`
+        return false;
+    };
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            error.write(e);
+            false
+        }
+    }
+}
+
+/// Stop using the plugin `library`. If no other plugin is using it,
+/// wait for any `async` calls still running in it, then call its
+/// `on_unload` hook, if it has one, and take back its host API.
+///
+/// Returns `false` if calls are still running after the plugin's time
+/// limit, in which case the library must not be unloaded. The hook is
+/// still called, as it may be what stops them.
+///
+/// # Safety
+///
+/// `library` must be a plugin, exposing `_dynamic_plugin_wait_tasks`,
+/// `_dynamic_plugin_on_unload` and `_dynamic_plugin_set_host_api` if it
+/// exposes anything by those names.
+#[must_use]
+pub unsafe fn on_unload(library: &PluginLibrary) -> bool {
+    let mut completed = true;
+    crate::registry::release(library, || {
+        if let Ok(wait_tasks) =
+            library.get::<unsafe extern "C" fn() -> bool>(b"_dynamic_plugin_wait_tasks")
+        {
+            completed = wait_tasks();
+        }
+        if let Ok(on_unload) = library.get::<unsafe extern "C" fn()>(b"_dynamic_plugin_on_unload")
+        {
+            on_unload();
+            // Nothing can be done about a panic now, but clear it so that
+            // it isn't reported by a later call
+            let _ = check_panic(library, "on_unload");
+        }
+        clear_host_api(library);
+    });
+    completed
+}
+
+/// Take the host API back from the plugin `library`, if it uses one,
+/// before it is dropped.
+///
+/// # Safety
+///
+/// `library` must be a plugin, exposing `_dynamic_plugin_set_host_api`
+/// if it exposes anything by that name.
+pub(crate) unsafe fn clear_host_api(library: &PluginLibrary) {
+    if let Ok(set_host_api) =
+        library.get::<unsafe extern "C" fn(*const c_void) -> bool>(b"_dynamic_plugin_set_host_api")
+    {
+        set_host_api(std::ptr::null());
+    }
+}
+
+/// Check that the file at `path` is a shared library for the host's
+/// platform and architecture.
+///
+/// # Errors
+///
+/// - [`Error::Io`] if the file cannot be read.
+/// - [`Error::NotASharedLibrary`] if the file is not a shared library for the host's platform.
+/// - [`Error::WrongArchitecture`] if the library is for a different architecture.
+pub fn check_header<P: AsRef<OsStr>>(path: P) -> Result<()> {
+    crate::header::check(&File::open(Path::new(path.as_ref()))?)
+}
+
+/// Create a new instance of the plugin `library`'s state, returning its
+/// handle.
+///
+/// # Errors
+///
+/// - [`Error::PluginPanicked`] if the plugin panicked while creating its state.
+/// - [`Error::PluginCrashed`] if the plugin is isolated and its worker has crashed.
+///
+/// # Safety
+///
+/// `library` must be a plugin with instance methods.
+pub unsafe fn create_instance(library: &PluginLibrary) -> Result<*mut c_void> {
+    #[cfg(unix)]
+    if let PluginLibrary::Isolated { worker, .. } = library {
+        return worker.create_instance();
+    }
+    let create: unsafe extern "C" fn() -> MaybeUninit<*mut c_void> =
+        library.get(b"_dynamic_plugin_create")?;
+    let handle = create();
+    check_panic(library, "create")?;
+    Ok(handle.assume_init())
+}
+
+/// Destroy an instance of the plugin `library`'s state.
+///
+/// # Safety
+///
+/// `handle` must have been returned by [`create_instance`] for
+/// `library`, and not already destroyed.
+pub unsafe fn destroy_instance(library: &PluginLibrary, handle: *mut c_void) {
+    #[cfg(unix)]
+    if let PluginLibrary::Isolated { worker, .. } = library {
+        worker.destroy_instance(handle);
+        return;
+    }
+    if let Ok(destroy) =
+        library.get::<unsafe extern "C" fn(*mut c_void)>(b"_dynamic_plugin_destroy")
+    {
+        destroy(handle);
+        // Nothing can be done about a panic now, but clear it so that it
+        // isn't reported by a later call
+        let _ = check_panic(library, "destroy");
+    }
+}
+
+/// Serve the plugin interface with `signature` if this process was
+/// started as a worker for an isolated plugin, calling its functions
+/// with `call`, then exit. Otherwise, return immediately.
+#[cfg(unix)]
+pub fn run_isolated_worker(signature: u64, call: CallFn) {
+    crate::isolation::run_worker(signature, call);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take() -> Option<String> {
+        let mut message = MaybeUninit::uninit();
+        // SAFETY: `message` can hold a `PluginString`.
+        unsafe { take_panic(message.as_mut_ptr()) }
+            // SAFETY: `take_panic` wrote the message.
+            .then(|| unsafe { message.assume_init() }.into_string())
+    }
+
+    #[test]
+    fn records_panics() {
+        assert!(catch(|| -> u32 { panic!("boom") }).is_none());
+        let message = take().unwrap();
+        assert!(message.starts_with("boom (at "), "{message}");
+        assert_eq!(take(), None);
+    }
+
+    #[test]
+    fn ignores_panics_caught_inside_the_function() {
+        let value = catch(|| {
+            let _ = panic::catch_unwind(|| panic!("caught"));
+            5
+        });
+        assert_eq!(value, Some(5));
+        assert_eq!(take(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_string_arguments() {
+        // SAFETY: the pointers are to nul-terminated strings.
+        let valid = catch(|| unsafe { str_arg(c"Jens".as_ptr()) });
+        assert_eq!(valid, Some("Jens"));
+        let invalid = catch(|| unsafe { str_arg(c"J\xffns".as_ptr()) });
+        assert_eq!(invalid, None);
+        assert!(matches!(
+            caught_panic(take_last_panic(), |_| unreachable!()),
+            Error::InvalidUtf8
+        ));
+    }
+}
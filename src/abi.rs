@@ -0,0 +1,103 @@
+//! The toolchain and ABI a plugin was built with.
+//!
+//! Two libraries with the same signature can still be incompatible, for
+//! example if they were built for different targets or with different
+//! versions of this crate. Each plugin exports an [`AbiDescriptor`],
+//! which the host checks against its own when the plugin is loaded.
+
+use crate::{metadata::RawStr, Error, Result};
+
+/// The version of the scheme used to calculate plugin signatures. This
+/// changes whenever the same interface would give a different signature.
+const SIGNATURE_SCHEME: u32 = 1;
+
+/// The toolchain and ABI a plugin was built with, in the C ABI.
+#[doc(hidden)]
+#[repr(C)]
+pub struct AbiDescriptor {
+    signature_scheme: u32,
+    crate_version: RawStr,
+    target: RawStr,
+    pointer_width: u32,
+    big_endian: bool,
+}
+
+/// The descriptor for this build, exported by plugins and compared
+/// against by hosts.
+#[doc(hidden)]
+pub static ABI: AbiDescriptor = AbiDescriptor {
+    signature_scheme: SIGNATURE_SCHEME,
+    crate_version: RawStr::new(env!("CARGO_PKG_VERSION")),
+    target: RawStr::new(env!("DYNAMIC_PLUGIN_TARGET")),
+    pointer_width: usize::BITS,
+    big_endian: cfg!(target_endian = "big"),
+};
+
+impl AbiDescriptor {
+    /// Check that a plugin's descriptor matches this build's.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::SignatureSchemeMismatch`] if the signatures were calculated differently.
+    /// - [`Error::CrateVersionMismatch`] if the plugin was built with an incompatible version of this crate.
+    /// - [`Error::TargetMismatch`] if the plugin was built for a different target.
+    /// - [`Error::PointerWidthMismatch`] if the plugin has a different pointer width.
+    /// - [`Error::EndiannessMismatch`] if the plugin has a different endianness.
+    ///
+    /// # Safety
+    ///
+    /// `self` must have been exported by a plugin built with these macros.
+    pub(crate) unsafe fn check(&self) -> Result<()> {
+        if self.signature_scheme != ABI.signature_scheme {
+            return Err(Error::SignatureSchemeMismatch {
+                expected: ABI.signature_scheme,
+                found: self.signature_scheme,
+            });
+        }
+
+        let expected = ABI.crate_version.to_string().unwrap_or_default();
+        let found = self.crate_version.to_string().unwrap_or_default();
+        if !versions_compatible(&expected, &found) {
+            return Err(Error::CrateVersionMismatch { expected, found });
+        }
+
+        let expected = ABI.target.to_string().unwrap_or_default();
+        let found = self.target.to_string().unwrap_or_default();
+        if expected != found {
+            return Err(Error::TargetMismatch { expected, found });
+        }
+
+        if self.pointer_width != ABI.pointer_width {
+            return Err(Error::PointerWidthMismatch {
+                expected: ABI.pointer_width,
+                found: self.pointer_width,
+            });
+        }
+
+        if self.big_endian != ABI.big_endian {
+            return Err(Error::EndiannessMismatch {
+                expected: endianness(ABI.big_endian).to_string(),
+                found: endianness(self.big_endian).to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether two versions of this crate share the same ABI, which is true
+/// if they are semver compatible.
+fn versions_compatible(a: &str, b: &str) -> bool {
+    match (semver::Version::parse(a), semver::Version::parse(b)) {
+        (Ok(a), Ok(b)) => a.major == b.major && (a.major > 0 || a.minor == b.minor),
+        _ => false,
+    }
+}
+
+fn endianness(big_endian: bool) -> &'static str {
+    if big_endian {
+        "big"
+    } else {
+        "little"
+    }
+}
@@ -0,0 +1,31 @@
+//! Running plugin calls on tokio's blocking thread pool.
+//!
+//! With the `tokio` feature, each host method for a plugin function has
+//! an `_async` variant, which runs the call here so that it doesn't
+//! block the runtime. Calls to plugins whose interface is
+//! `#[non_reentrant]` wait their turn on the runtime first, with a
+//! [`CallPermit`](crate::__private::CallPermit), so that they don't
+//! hold a thread of the pool while they wait.
+
+use crate::{Error, Result};
+
+/// Run `call`, a call to the plugin function `function`, on the blocking
+/// pool.
+///
+/// # Errors
+///
+/// - [`Error::Cancelled`] if the runtime shut down before the call ran.
+/// - Any error returned by `call`.
+pub async fn run_blocking<T, F>(function: &str, call: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(call).await {
+        Ok(result) => result,
+        Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+        Err(_) => Err(Error::Cancelled {
+            function: function.to_string(),
+        }),
+    }
+}
@@ -0,0 +1,237 @@
+//! Holding a plugin's calls to one at a time.
+//!
+//! Each plugin whose interface is `#[non_reentrant]` or
+//! `#[single_threaded]` has a [`CallLock`], which every call to it holds
+//! until it returns. A call made again from the thread holding the lock,
+//! such as by a host function the plugin called, fails with
+//! [`Error::Reentrant`] rather than waiting for itself. With the `tokio`
+//! feature the lock is a semaphore with a single permit, so that an
+//! `_async` call waits for the permit on the runtime, and only then
+//! takes it to the blocking pool. Otherwise every queued call would
+//! hold a thread of the pool while it waited.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "tokio")]
+use std::{cell::RefCell, sync::Arc};
+#[cfg(not(feature = "tokio"))]
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+#[cfg(feature = "tokio")]
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{Error, Result};
+
+thread_local! {
+    /// Identifies the current thread by its address, which no other
+    /// running thread shares.
+    static THREAD: u8 = const { 0 };
+}
+
+#[cfg(feature = "tokio")]
+thread_local! {
+    /// The permit taken by an `_async` call for the lock at the given
+    /// address, for the host method it runs to use.
+    static HANDED_OVER: RefCell<Option<(usize, OwnedSemaphorePermit)>> = const { RefCell::new(None) };
+}
+
+/// Holds the calls to a plugin to one at a time.
+#[derive(Debug)]
+pub struct CallLock {
+    /// The thread holding the lock, or 0 if none is.
+    owner: AtomicUsize,
+    #[cfg(feature = "tokio")]
+    permits: Arc<Semaphore>,
+    #[cfg(not(feature = "tokio"))]
+    lock: Mutex<()>,
+}
+
+/// Held by a call to a plugin, until it returns.
+#[must_use]
+pub struct CallGuard<'a> {
+    lock: &'a CallLock,
+    #[cfg(feature = "tokio")]
+    _permit: OwnedSemaphorePermit,
+    #[cfg(not(feature = "tokio"))]
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl CallLock {
+    /// A lock which no call holds.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            owner: AtomicUsize::new(0),
+            #[cfg(feature = "tokio")]
+            permits: Arc::new(Semaphore::new(1)),
+            #[cfg(not(feature = "tokio"))]
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Wait for any other call to return, then hold the lock for a call
+    /// to `function` until the guard is dropped. A call run by
+    /// [`CallPermit::run`] uses the permit it was given instead of
+    /// waiting.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Reentrant`] if the current thread already holds the lock.
+    ///
+    /// # Panics
+    ///
+    /// Never: the semaphore is never closed.
+    pub fn lock(&self, function: &str) -> Result<CallGuard<'_>> {
+        let thread = current_thread();
+        // Only this thread stores its own address, so a stale value can't
+        // match it
+        if self.owner.load(Ordering::Relaxed) == thread {
+            return Err(Error::Reentrant {
+                function: function.to_string(),
+            });
+        }
+        let guard = self.wait();
+        self.owner.store(thread, Ordering::Relaxed);
+        Ok(guard)
+    }
+
+    /// Wait for any other call to return.
+    fn wait(&self) -> CallGuard<'_> {
+        #[cfg(feature = "tokio")]
+        {
+            let handed_over = HANDED_OVER.with(|slot| {
+                let mut slot = slot.borrow_mut();
+                match slot.take() {
+                    Some((lock, permit)) if lock == self.address() => Some(permit),
+                    other => {
+                        *slot = other;
+                        None
+                    }
+                }
+            });
+            let permit = handed_over.unwrap_or_else(|| {
+                crate::tasks::block_on(Arc::clone(&self.permits).acquire_owned())
+                    .expect("the semaphore is never closed")
+            });
+            CallGuard {
+                lock: self,
+                _permit: permit,
+            }
+        }
+        #[cfg(not(feature = "tokio"))]
+        CallGuard {
+            lock: self,
+            _guard: self.lock.lock().unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    fn address(&self) -> usize {
+        std::ptr::from_ref(self) as usize
+    }
+}
+
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        // Before the permit is released, so as not to clear the next
+        // call's owner
+        self.lock.owner.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The address identifying the current thread.
+fn current_thread() -> usize {
+    THREAD.with(|thread| std::ptr::from_ref(thread) as usize)
+}
+
+impl Default for CallLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A turn to call a plugin, taken on the runtime by an `_async` call
+/// before it moves to the blocking pool.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct CallPermit {
+    lock: usize,
+    permit: OwnedSemaphorePermit,
+}
+
+#[cfg(feature = "tokio")]
+impl CallPermit {
+    /// Wait for any other call holding `lock` to return.
+    ///
+    /// # Panics
+    ///
+    /// Never: the semaphore is never closed.
+    pub async fn acquire(lock: &CallLock) -> Self {
+        let permit = Arc::clone(&lock.permits)
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        Self {
+            lock: lock.address(),
+            permit,
+        }
+    }
+
+    /// Run `call` on the current thread, handing it the permit so that
+    /// the host method it makes doesn't wait for the lock again.
+    pub fn run<T>(self, call: impl FnOnce() -> T) -> T {
+        /// Drops the permit if the call didn't take it, such as if it
+        /// failed before locking, or panicked.
+        struct Reclaim;
+
+        impl Drop for Reclaim {
+            fn drop(&mut self) {
+                HANDED_OVER.with(|slot| slot.borrow_mut().take());
+            }
+        }
+
+        HANDED_OVER.with(|slot| *slot.borrow_mut() = Some((self.lock, self.permit)));
+        let _reclaim = Reclaim;
+        call()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_calls_from_the_thread_holding_the_lock() {
+        let lock = CallLock::new();
+        let guard = lock.lock("outer").unwrap();
+        assert!(matches!(
+            lock.lock("inner"),
+            Err(Error::Reentrant { function }) if function == "inner"
+        ));
+        drop(guard);
+        assert!(lock.lock("again").is_ok());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn hands_permits_over_to_the_call() {
+        let lock = CallLock::new();
+        let permit = crate::tasks::block_on(CallPermit::acquire(&lock));
+        assert_eq!(lock.permits.available_permits(), 0);
+        permit.run(|| {
+            let _guard = lock.lock("test").unwrap();
+            assert_eq!(lock.permits.available_permits(), 0);
+        });
+        assert_eq!(lock.permits.available_permits(), 1);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn releases_permits_which_the_call_didnt_take() {
+        let lock = CallLock::new();
+        let permit = crate::tasks::block_on(CallPermit::acquire(&lock));
+        permit.run(|| ());
+        assert_eq!(lock.permits.available_permits(), 1);
+        let _guard = lock.lock("test").unwrap();
+        assert_eq!(lock.permits.available_permits(), 0);
+    }
+}
@@ -0,0 +1,226 @@
+//! Ordering plugins so that each is loaded after the plugins it depends
+//! on, and checking that they are.
+
+use crate::{Error, PluginMetadata, Result};
+
+/// Check that every dependency in `metadata` is among the plugins
+/// `loaded`, given by name and version.
+///
+/// # Errors
+///
+/// - [`Error::MissingDependency`] for the first dependency which is not loaded.
+pub(crate) fn check(metadata: &PluginMetadata, loaded: &[(String, semver::Version)]) -> Result<()> {
+    for dependency in &metadata.dependencies {
+        if !loaded.iter().any(|(name, version)| {
+            *name == dependency.name && dependency.requirement.matches(version)
+        }) {
+            return Err(Error::MissingDependency {
+                plugin: metadata.name.clone(),
+                dependency: dependency.name.clone(),
+                requirement: dependency.requirement.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// A plugin's place in the load order.
+pub(crate) enum Ordered {
+    /// Load the plugin at this index.
+    Load(usize),
+    /// The plugin at this index depends on itself through the plugins
+    /// named, so cannot be loaded.
+    Cycle(usize, Vec<String>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Unvisited,
+    Visiting,
+    Done,
+}
+
+/// Order plugins by their `metadata`, so that each comes after the
+/// plugins it depends on.
+pub(crate) fn load_order(metadata: &[Option<PluginMetadata>]) -> Vec<Ordered> {
+    let mut sorter = Sorter {
+        metadata,
+        state: vec![State::Unvisited; metadata.len()],
+        cycles: vec![None; metadata.len()],
+        stack: vec![],
+        order: vec![],
+    };
+    for idx in 0..metadata.len() {
+        sorter.visit(idx);
+    }
+    sorter.order
+}
+
+struct Sorter<'a> {
+    metadata: &'a [Option<PluginMetadata>],
+    state: Vec<State>,
+    cycles: Vec<Option<Vec<String>>>,
+    stack: Vec<usize>,
+    order: Vec<Ordered>,
+}
+
+impl Sorter<'_> {
+    fn visit(&mut self, idx: usize) {
+        match self.state[idx] {
+            State::Done => return,
+            State::Visiting => {
+                // Every plugin on the stack from here depends on itself
+                let start = self.stack.iter().position(|i| *i == idx).unwrap_or(0);
+                let members = self.stack[start..].to_vec();
+                let mut names = members.iter().map(|i| self.name(*i)).collect::<Vec<_>>();
+                names.push(self.name(idx));
+                for member in members {
+                    self.cycles[member] = Some(names.clone());
+                }
+                return;
+            }
+            State::Unvisited => (),
+        }
+
+        self.state[idx] = State::Visiting;
+        self.stack.push(idx);
+        for dependency in self.dependencies(idx) {
+            self.visit(dependency);
+        }
+        self.stack.pop();
+        self.state[idx] = State::Done;
+
+        self.order.push(match self.cycles[idx].take() {
+            Some(cycle) => Ordered::Cycle(idx, cycle),
+            None => Ordered::Load(idx),
+        });
+    }
+
+    /// The plugins which could satisfy the dependencies of the plugin at `idx`.
+    fn dependencies(&self, idx: usize) -> Vec<usize> {
+        let Some(metadata) = &self.metadata[idx] else {
+            return vec![];
+        };
+        metadata
+            .dependencies
+            .iter()
+            .flat_map(|dependency| {
+                self.metadata
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(other, other_metadata)| {
+                        let other_metadata = other_metadata.as_ref()?;
+                        (other_metadata.name == dependency.name
+                            && dependency.requirement.matches(&other_metadata.version))
+                        .then_some(other)
+                    })
+            })
+            .collect()
+    }
+
+    fn name(&self, idx: usize) -> String {
+        self.metadata[idx]
+            .as_ref()
+            .map(|metadata| metadata.name.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, load_order, Ordered};
+    use crate::{Error, PluginDependency, PluginMetadata};
+
+    fn plugin(name: &str, dependencies: &[(&str, &str)]) -> PluginMetadata {
+        PluginMetadata {
+            name: name.to_string(),
+            version: semver::Version::new(1, 0, 0),
+            authors: vec![],
+            description: None,
+            homepage: None,
+            license: None,
+            dependencies: dependencies
+                .iter()
+                .map(|(name, requirement)| PluginDependency {
+                    name: (*name).to_string(),
+                    requirement: requirement.parse().unwrap(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The order of the plugins, with the cycle each is in, if any.
+    fn order(metadata: &[Option<PluginMetadata>]) -> Vec<(usize, Option<Vec<String>>)> {
+        load_order(metadata)
+            .into_iter()
+            .map(|ordered| match ordered {
+                Ordered::Load(idx) => (idx, None),
+                Ordered::Cycle(idx, cycle) => (idx, Some(cycle)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loads_dependencies_first() {
+        let metadata = [
+            Some(plugin("app", &[("ui", "^1"), ("core", "^1")])),
+            Some(plugin("ui", &[("core", "^1")])),
+            None,
+            Some(plugin("core", &[])),
+        ];
+        assert_eq!(
+            order(&metadata),
+            [(3, None), (1, None), (0, None), (2, None)]
+        );
+    }
+
+    #[test]
+    fn ignores_incompatible_versions() {
+        let metadata = [
+            Some(plugin("app", &[("core", "^2")])),
+            Some(plugin("core", &[])),
+        ];
+        assert_eq!(order(&metadata), [(0, None), (1, None)]);
+    }
+
+    #[test]
+    fn finds_cycles() {
+        let metadata = [
+            Some(plugin("a", &[("b", "^1")])),
+            Some(plugin("b", &[("c", "^1")])),
+            Some(plugin("c", &[("a", "^1")])),
+            Some(plugin("d", &[("a", "^1")])),
+        ];
+        let order = order(&metadata);
+        for idx in 0..3 {
+            let (_, cycle) = order.iter().find(|(i, _)| *i == idx).unwrap();
+            assert_eq!(cycle.as_ref().unwrap().len(), 4);
+        }
+        // Plugins depending on a cycle are loaded after it, and then fail
+        // their dependency check
+        assert_eq!(order.last(), Some(&(3, None)));
+    }
+
+    #[test]
+    fn finds_plugins_depending_on_themselves() {
+        let metadata = [Some(plugin("a", &[("a", "^1")]))];
+        assert_eq!(
+            order(&metadata),
+            [(0, Some(vec!["a".to_string(), "a".to_string()]))]
+        );
+    }
+
+    #[test]
+    fn checks_dependencies_are_loaded() {
+        let app = plugin("app", &[("core", "^1.2")]);
+        let loaded = |version| vec![("core".to_string(), semver::Version::new(1, version, 0))];
+        check(&app, &loaded(3)).unwrap();
+        let result = check(&app, &loaded(1));
+        assert!(matches!(
+            result,
+            Err(Error::MissingDependency { plugin, dependency, .. })
+                if plugin == "app" && dependency == "core"
+        ));
+        assert!(check(&app, &[]).is_err());
+    }
+}
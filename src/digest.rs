@@ -0,0 +1,110 @@
+//! Checking plugin files against an allowlist of SHA-256 digests.
+
+use std::{collections::HashSet, fmt, fs, path::Path, str::FromStr};
+
+use sha2::{Digest, Sha256};
+
+use crate::{Error, Result};
+
+/// The SHA-256 digest of a plugin file.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PluginDigest(pub [u8; 32]);
+
+impl PluginDigest {
+    /// Calculate the digest of the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Io`] if the file cannot be read.
+    pub fn of_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::of_bytes(&fs::read(path)?))
+    }
+
+    /// Calculate the digest of a file's contents.
+    pub(crate) fn of_bytes(contents: &[u8]) -> Self {
+        Self(Sha256::digest(contents).into())
+    }
+}
+
+impl fmt::Display for PluginDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PluginDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PluginDigest({self})")
+    }
+}
+
+impl FromStr for PluginDigest {
+    type Err = Error;
+
+    /// Parse a digest written as 64 hexadecimal digits.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidDigest(s.to_string());
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let mut digest = [0; 32];
+        for (byte, hex) in digest.iter_mut().zip(s.as_bytes().chunks(2)) {
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(hex, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(digest))
+    }
+}
+
+/// Read an allowlist of digests from the file at `path`.
+///
+/// Each line holds a digest in hexadecimal, optionally followed by
+/// whitespace and anything else, such as the file name. This is the
+/// format written by `sha256sum`. Blank lines and lines starting with
+/// `#` are ignored.
+pub(crate) fn read_allowlist(path: &Path) -> Result<HashSet<PluginDigest>> {
+    parse_allowlist(&fs::read_to_string(path)?)
+}
+
+/// Parse the contents of an allowlist file.
+fn parse_allowlist(allowlist: &str) -> Result<HashSet<PluginDigest>> {
+    allowlist
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.split_whitespace().next().unwrap_or_default().parse())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_allowlist, PluginDigest};
+    use crate::Error;
+
+    #[test]
+    fn parses_allowlists() {
+        let digest = PluginDigest::of_bytes(b"plugin");
+        let allowlist = format!(
+            "# Plugins built by CI\n\n{digest}  libplugin.so\n  {}\n",
+            digest.to_string().to_uppercase()
+        );
+        let allowlist = parse_allowlist(&allowlist).unwrap();
+        assert_eq!(allowlist.len(), 1);
+        assert!(allowlist.contains(&digest));
+        assert!(!allowlist.contains(&PluginDigest::of_bytes(b"other plugin")));
+    }
+
+    #[test]
+    fn rejects_invalid_digests() {
+        for line in ["abc123", &"g".repeat(64), &"é".repeat(32), &"0".repeat(65)] {
+            let result = parse_allowlist(line);
+            assert!(
+                matches!(result, Err(Error::InvalidDigest(digest)) if digest == line),
+                "{line}"
+            );
+        }
+    }
+}
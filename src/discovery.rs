@@ -0,0 +1,36 @@
+//! The results of searching a directory for plugins.
+
+use std::path::PathBuf;
+
+use crate::Error;
+
+/// The plugins found by searching a directory, with the reason each
+/// other file was not loaded.
+///
+/// Returned by the `discover_plugins` and `discover_plugins_with`
+/// functions generated by `plugin_interface!`.
+#[derive(Debug)]
+pub struct DiscoveryReport<P> {
+    /// The plugins which were loaded.
+    pub loaded: Vec<P>,
+    /// The files which were not loaded.
+    pub rejected: Vec<RejectedPlugin>,
+}
+
+impl<P> Default for DiscoveryReport<P> {
+    fn default() -> Self {
+        Self {
+            loaded: vec![],
+            rejected: vec![],
+        }
+    }
+}
+
+/// A file which was not loaded as a plugin.
+#[derive(Debug)]
+pub struct RejectedPlugin {
+    /// The path to the file.
+    pub path: PathBuf,
+    /// Why the file was not loaded.
+    pub error: Error,
+}
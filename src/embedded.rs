@@ -0,0 +1,484 @@
+//! Reading the metadata a plugin stores in its file, without loading it.
+//!
+//! `plugin_impl!` places the plugin's encoded metadata in a section of
+//! its own, so hosts can order plugins by their dependencies, and filter
+//! them, before running any of their code. Only the tables needed to
+//! find that section are read, and any which don't make sense are taken
+//! to mean the file has no metadata, leaving the dynamic loader to
+//! reject it.
+
+use std::{
+    env::consts::ARCH,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
+
+use crate::{
+    header::{self, cpu_arch, u16_at, u32_at, u64_at, Format, HEADER_LEN},
+    metadata::MAX_ENCODED_LEN,
+    Error, PluginMetadata, Result,
+};
+
+/// The section holding a plugin's metadata in an ELF file. These names
+/// must match those given by `plugin_impl!`.
+const ELF_SECTION: &[u8] = b".dynamic_plugin_metadata";
+/// The section holding a plugin's metadata in a PE file.
+const PE_SECTION: &[u8] = b".dpmeta";
+/// The segment and section holding a plugin's metadata in a Mach-O file.
+const MACH_O_SECTION: (&[u8], &[u8]) = (b"__DATA", b"__dp_metadata");
+
+/// The longest table read to find the section.
+const MAX_TABLE_LEN: usize = 1024 * 1024;
+
+/// Read the metadata stored in the plugin `file`, if it has any.
+///
+/// # Errors
+///
+/// - [`Error::Io`] if the file cannot be read.
+/// - [`Error::InvalidMetadata`] if the metadata stored is not valid.
+pub(crate) fn read(file: &mut File) -> Result<Option<PluginMetadata>> {
+    let Some(format) = header::host_format() else {
+        return Ok(None);
+    };
+    file.rewind()?;
+    read_from(file, format)
+}
+
+fn read_from<R: Read + Seek>(file: &mut R, format: Format) -> Result<Option<PluginMetadata>> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    file.by_ref()
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+
+    let section = match format {
+        Format::Elf => elf_section(file, &header)?,
+        Format::Pe => pe_section(file, &header)?,
+        Format::MachO => mach_o_section(file, &header)?,
+    };
+    let Some((offset, len)) = section else {
+        return Ok(None);
+    };
+    // Sections may be padded, but not by more than a page
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|len| *len <= MAX_ENCODED_LEN + 4096)
+        .ok_or_else(|| Error::InvalidMetadata("the metadata is too long".to_string()))?;
+    let bytes = read_at(file, offset, len)?;
+    let (metadata, len) = PluginMetadata::decode(&bytes)?;
+    // Plugins linked into this one would add their own metadata
+    if bytes[len..].iter().any(|byte| *byte != 0) {
+        return Err(Error::InvalidMetadata(
+            "the library holds the metadata of more than one plugin".to_string(),
+        ));
+    }
+    Ok(Some(metadata))
+}
+
+/// Read `len` bytes of `file` from `offset`.
+fn read_at<R: Read + Seek>(file: &mut R, offset: u64, len: usize) -> Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = vec![0; len];
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Read the table of `count` entries of `entry_len` bytes at `offset`.
+fn read_table<R: Read + Seek>(
+    file: &mut R,
+    offset: u64,
+    count: usize,
+    entry_len: usize,
+) -> Result<Option<Vec<u8>>> {
+    match count.checked_mul(entry_len) {
+        Some(len) if len <= MAX_TABLE_LEN => read_at(file, offset, len).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// The bytes of a fixed-length name, up to the first NUL.
+fn name(bytes: &[u8]) -> &[u8] {
+    bytes.split(|byte| *byte == 0).next().unwrap_or_default()
+}
+
+/// The offset and length of the metadata section of an ELF file.
+fn elf_section<R: Read + Seek>(file: &mut R, header: &[u8]) -> Result<Option<(u64, u64)>> {
+    const SHT_NOBITS: u32 = 8;
+
+    let (Some(&class), Some(&data)) = (header.get(4), header.get(5)) else {
+        return Ok(None);
+    };
+    let (is_64, big_endian) = (class == 2, data == 2);
+    let fields = if is_64 {
+        (
+            u64_at(header, 0x28, big_endian),
+            u16_at(header, 0x3a, big_endian),
+            u16_at(header, 0x3c, big_endian),
+            u16_at(header, 0x3e, big_endian),
+        )
+    } else {
+        (
+            u32_at(header, 0x20, big_endian).map(u64::from),
+            u16_at(header, 0x2e, big_endian),
+            u16_at(header, 0x30, big_endian),
+            u16_at(header, 0x32, big_endian),
+        )
+    };
+    let (Some(table_offset), Some(entry_len), Some(count), Some(names_idx)) = fields else {
+        return Ok(None);
+    };
+    let entry_len = usize::from(entry_len);
+    if entry_len < if is_64 { 64 } else { 40 } {
+        return Ok(None);
+    }
+    let Some(table) = read_table(file, table_offset, usize::from(count), entry_len)? else {
+        return Ok(None);
+    };
+
+    // The name, type, offset and length of each section
+    let section = |idx: usize| {
+        let entry = table.get(idx * entry_len..(idx + 1) * entry_len)?;
+        let name = u32_at(entry, 0, big_endian)?;
+        let kind = u32_at(entry, 4, big_endian)?;
+        let (offset, len) = if is_64 {
+            (
+                u64_at(entry, 0x18, big_endian)?,
+                u64_at(entry, 0x20, big_endian)?,
+            )
+        } else {
+            (
+                u64::from(u32_at(entry, 0x10, big_endian)?),
+                u64::from(u32_at(entry, 0x14, big_endian)?),
+            )
+        };
+        Some((usize::try_from(name).ok()?, kind, offset, len))
+    };
+    let Some((_, _, names_offset, names_len)) = section(usize::from(names_idx)) else {
+        return Ok(None);
+    };
+    let Some(names) = read_table(
+        file,
+        names_offset,
+        usize::try_from(names_len).unwrap_or(usize::MAX),
+        1,
+    )?
+    else {
+        return Ok(None);
+    };
+
+    for idx in 0..usize::from(count) {
+        let Some((name_offset, kind, offset, len)) = section(idx) else {
+            return Ok(None);
+        };
+        if kind != SHT_NOBITS && names.get(name_offset..).map(name) == Some(ELF_SECTION) {
+            return Ok(Some((offset, len)));
+        }
+    }
+    Ok(None)
+}
+
+/// The offset and length of the metadata section of a PE file.
+fn pe_section<R: Read + Seek>(file: &mut R, header: &[u8]) -> Result<Option<(u64, u64)>> {
+    const ENTRY_LEN: usize = 40;
+
+    let fields = (|| {
+        let pe = usize::try_from(u32_at(header, 0x3c, false)?).ok()?;
+        let count = u16_at(header, pe + 6, false)?;
+        let optional_header_len = u16_at(header, pe + 20, false)?;
+        Some((pe + 24 + usize::from(optional_header_len), count))
+    })();
+    let Some((table_offset, count)) = fields else {
+        return Ok(None);
+    };
+    let Some(table) = read_table(file, table_offset as u64, usize::from(count), ENTRY_LEN)? else {
+        return Ok(None);
+    };
+
+    for entry in table.chunks_exact(ENTRY_LEN) {
+        if name(&entry[..8]) != PE_SECTION {
+            continue;
+        }
+        let (Some(virtual_len), Some(len), Some(offset)) = (
+            u32_at(entry, 8, false),
+            u32_at(entry, 16, false),
+            u32_at(entry, 20, false),
+        ) else {
+            return Ok(None);
+        };
+        // The data in the file is padded, so may be longer than the section
+        let len = if virtual_len == 0 {
+            len
+        } else {
+            len.min(virtual_len)
+        };
+        return Ok(Some((u64::from(offset), u64::from(len))));
+    }
+    Ok(None)
+}
+
+/// The offset and length of the metadata section of a Mach-O file, or of
+/// the library for the host's architecture in a universal binary.
+fn mach_o_section<R: Read + Seek>(file: &mut R, header: &[u8]) -> Result<Option<(u64, u64)>> {
+    const FAT_MAGIC: u32 = 0xcafe_babe;
+
+    let Some(magic) = u32_at(header, 0, true) else {
+        return Ok(None);
+    };
+    if magic != FAT_MAGIC {
+        return thin_mach_o_section(file, header, 0);
+    }
+    let count = u32_at(header, 4, true).unwrap_or_default();
+    for idx in 0..usize::try_from(count).unwrap_or_default().min(20) {
+        let entry = 8 + idx * 20;
+        let (Some(cpu_type), Some(offset)) =
+            (u32_at(header, entry, true), u32_at(header, entry + 8, true))
+        else {
+            return Ok(None);
+        };
+        if cpu_arch(cpu_type) == ARCH {
+            let offset = u64::from(offset);
+            let header = read_at(file, offset, 32)?;
+            return thin_mach_o_section(file, &header, offset);
+        }
+    }
+    Ok(None)
+}
+
+/// The offset and length of the metadata section of the Mach-O library
+/// at `base` in the file, whose header is `header`.
+fn thin_mach_o_section<R: Read + Seek>(
+    file: &mut R,
+    header: &[u8],
+    base: u64,
+) -> Result<Option<(u64, u64)>> {
+    const LC_SEGMENT: u32 = 0x1;
+    const LC_SEGMENT_64: u32 = 0x19;
+
+    let (is_64, big_endian) = match u32_at(header, 0, true) {
+        Some(0xfeed_face) => (false, true),
+        Some(0xfeed_facf) => (true, true),
+        Some(0xcefa_edfe) => (false, false),
+        Some(0xcffa_edfe) => (true, false),
+        _ => return Ok(None),
+    };
+    let (Some(count), Some(table_len)) = (
+        u32_at(header, 16, big_endian),
+        u32_at(header, 20, big_endian),
+    ) else {
+        return Ok(None);
+    };
+    let header_len = if is_64 { 32 } else { 28 };
+    let Some(commands) = read_table(
+        file,
+        base + header_len,
+        usize::try_from(table_len).unwrap_or(usize::MAX),
+        1,
+    )?
+    else {
+        return Ok(None);
+    };
+
+    let mut command = 0;
+    for _ in 0..count {
+        let (Some(kind), Some(command_len)) = (
+            u32_at(&commands, command, big_endian),
+            u32_at(&commands, command + 4, big_endian),
+        ) else {
+            return Ok(None);
+        };
+        // The offsets of the number of sections, the first section, and
+        // the offset and length of each, and the length of each
+        let layout = match kind {
+            LC_SEGMENT_64 => Some((64, 72, 48, 40, 80)),
+            LC_SEGMENT => Some((48, 56, 40, 36, 68)),
+            _ => None,
+        };
+        if let Some((count_at, first_at, offset_at, len_at, section_len)) = layout {
+            let Some(sections) = u32_at(&commands, command + count_at, big_endian) else {
+                return Ok(None);
+            };
+            for idx in 0..usize::try_from(sections).unwrap_or_default() {
+                let Some(section) = commands.get(
+                    command + first_at + idx * section_len
+                        ..command + first_at + (idx + 1) * section_len,
+                ) else {
+                    return Ok(None);
+                };
+                if (name(&section[16..32]), name(&section[..16])) != MACH_O_SECTION {
+                    continue;
+                }
+                let offset = u32_at(section, offset_at, big_endian);
+                let len = if kind == LC_SEGMENT_64 {
+                    u64_at(section, len_at, big_endian)
+                } else {
+                    u32_at(section, len_at, big_endian).map(u64::from)
+                };
+                let (Some(offset), Some(len)) = (offset, len) else {
+                    return Ok(None);
+                };
+                return Ok(Some((base + u64::from(offset), len)));
+            }
+        }
+        let Ok(command_len) = usize::try_from(command_len) else {
+            return Ok(None);
+        };
+        if command_len == 0 {
+            return Ok(None);
+        }
+        command += command_len;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{read_from, Format};
+    use crate::{__private::MetadataFields, Error};
+
+    const FIELDS: MetadataFields = MetadataFields {
+        name: "example",
+        version: "1.2.3",
+        authors: &[],
+        description: "",
+        homepage: "",
+        license: "",
+        dependencies: &[("core", "^1")],
+    };
+    static ENCODED: [u8; FIELDS.encoded_len()] = FIELDS.encode();
+
+    fn put(file: &mut Vec<u8>, offset: usize, bytes: &[u8]) {
+        if file.len() < offset + bytes.len() {
+            file.resize(offset + bytes.len(), 0);
+        }
+        file[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn put_u16(file: &mut Vec<u8>, offset: usize, value: u16) {
+        put(file, offset, &value.to_le_bytes());
+    }
+
+    fn put_u32(file: &mut Vec<u8>, offset: usize, value: u32) {
+        put(file, offset, &value.to_le_bytes());
+    }
+
+    fn put_u64(file: &mut Vec<u8>, offset: usize, value: u64) {
+        put(file, offset, &value.to_le_bytes());
+    }
+
+    /// A 64-bit ELF file with a section named `section` holding `data`.
+    fn elf(section: &str, data: &[u8]) -> Vec<u8> {
+        let names = format!("\0.shstrtab\0{section}\0");
+        let mut file = vec![];
+        put(&mut file, 0, b"\x7fELF\x02\x01\x01");
+        put_u16(&mut file, 16, 3);
+        put_u16(&mut file, 18, 62);
+        // The section headers, then the names, then the data
+        put_u64(&mut file, 0x28, 0x100);
+        put_u16(&mut file, 0x3a, 64);
+        put_u16(&mut file, 0x3c, 3);
+        put_u16(&mut file, 0x3e, 1);
+        let names_offset = 0x100 + 3 * 64;
+        let data_offset = names_offset + names.len();
+        put_u32(&mut file, 0x140, 1);
+        put_u32(&mut file, 0x144, 3);
+        put_u64(&mut file, 0x158, names_offset as u64);
+        put_u64(&mut file, 0x160, names.len() as u64);
+        put_u32(&mut file, 0x180, 11);
+        put_u32(&mut file, 0x184, 1);
+        put_u64(&mut file, 0x198, data_offset as u64);
+        put_u64(&mut file, 0x1a0, data.len() as u64);
+        put(&mut file, names_offset, names.as_bytes());
+        put(&mut file, data_offset, data);
+        file
+    }
+
+    /// A PE file with a section named `section` holding `data`, padded to
+    /// 512 bytes.
+    fn pe(section: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut file = vec![];
+        put(&mut file, 0, b"MZ");
+        put_u32(&mut file, 0x3c, 0x80);
+        put(&mut file, 0x80, b"PE\0\0");
+        put_u16(&mut file, 0x84, 0x8664);
+        put_u16(&mut file, 0x86, 1);
+        put_u16(&mut file, 0x94, 0xf0);
+        put_u16(&mut file, 0x96, 0x2000);
+        let entry = 0x80 + 24 + 0xf0;
+        put(&mut file, entry, section);
+        put_u32(&mut file, entry + 8, data.len().try_into().unwrap());
+        put_u32(&mut file, entry + 16, 512);
+        put_u32(&mut file, entry + 20, 0x400);
+        put(&mut file, 0x400, data);
+        file.resize(0x600, 0);
+        file
+    }
+
+    /// A 64-bit Mach-O file with a section in `segment` named `section`
+    /// holding `data`.
+    fn mach_o(segment: &[u8], section: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut file = vec![];
+        put_u32(&mut file, 0, 0xfeed_facf);
+        put_u32(&mut file, 4, 0x0100_0007);
+        put_u32(&mut file, 12, 6);
+        put_u32(&mut file, 16, 1);
+        put_u32(&mut file, 20, 72 + 80);
+        put_u32(&mut file, 32, 0x19);
+        put_u32(&mut file, 36, 72 + 80);
+        put(&mut file, 40, segment);
+        put_u32(&mut file, 32 + 64, 1);
+        let entry = 32 + 72;
+        put(&mut file, entry, section);
+        put(&mut file, entry + 16, segment);
+        put_u64(&mut file, entry + 40, data.len() as u64);
+        put_u32(&mut file, entry + 48, 0x200);
+        put(&mut file, 0x200, data);
+        file
+    }
+
+    fn read(file: Vec<u8>, format: Format) -> crate::Result<Option<String>> {
+        read_from(&mut Cursor::new(file), format)
+            .map(|metadata| metadata.map(|metadata| metadata.name))
+    }
+
+    #[test]
+    fn reads_elf_sections() {
+        let found = read(elf(".dynamic_plugin_metadata", &ENCODED), Format::Elf).unwrap();
+        assert_eq!(found.as_deref(), Some("example"));
+        assert_eq!(read(elf(".data", &ENCODED), Format::Elf).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_pe_sections() {
+        let found = read(pe(b".dpmeta", &ENCODED), Format::Pe).unwrap();
+        assert_eq!(found.as_deref(), Some("example"));
+        assert_eq!(read(pe(b".data", &ENCODED), Format::Pe).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_mach_o_sections() {
+        let found = read(mach_o(b"__DATA", b"__dp_metadata", &ENCODED), Format::MachO).unwrap();
+        assert_eq!(found.as_deref(), Some("example"));
+        assert_eq!(
+            read(mach_o(b"__TEXT", b"__dp_metadata", &ENCODED), Format::MachO).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_metadata_of_more_than_one_plugin() {
+        let both = [ENCODED, ENCODED].concat();
+        let result = read(elf(".dynamic_plugin_metadata", &both), Format::Elf);
+        assert!(matches!(result, Err(Error::InvalidMetadata(_))));
+    }
+
+    #[test]
+    fn handles_malformed_tables() {
+        let mut file = elf(".dynamic_plugin_metadata", &ENCODED);
+        // More section headers than fit in the file
+        file[0x3c..0x3e].copy_from_slice(&1000u16.to_le_bytes());
+        assert!(matches!(read(file, Format::Elf), Err(Error::Io(_))));
+        assert_eq!(read(b"\x7fELF".to_vec(), Format::Elf).unwrap(), None);
+        assert_eq!(read(vec![], Format::MachO).unwrap(), None);
+    }
+}
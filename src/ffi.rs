@@ -0,0 +1,436 @@
+//! FFI-safe types which can be used in plugin interfaces.
+
+use std::{
+    borrow::Cow,
+    ffi::{CStr, CString},
+};
+
+use crate::{Error, Result};
+
+/// A borrowed string passed from the host to a plugin.
+///
+/// Declare a string argument in an interface as `PluginStr`. The
+/// generated host method then takes anything which converts to a
+/// `PluginStr`, such as a `&str` or a `&CStr`, and the plugin
+/// implementation receives either a `&str` or a `&CStr`:
+///
+/// ```ignore
+/// plugin_interface! {
+///     extern trait ExamplePlugin {
+///         fn say_hello(to: PluginStr) -> bool;
+///     }
+/// }
+///
+/// plugin.say_hello("Jens")?;
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct PluginStr<'a>(PluginStrInner<'a>);
+
+#[derive(Clone, Copy, Debug)]
+enum PluginStrInner<'a> {
+    Str(&'a str),
+    CStr(&'a CStr),
+}
+
+impl<'a> PluginStr<'a> {
+    /// Get this string as a nul-terminated C string, allocating if
+    /// necessary.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::InteriorNul`] if the string contains a nul byte.
+    pub fn to_c_str(self) -> Result<Cow<'a, CStr>> {
+        match self.0 {
+            PluginStrInner::Str(s) => CString::new(s)
+                .map(Cow::Owned)
+                .map_err(|_| Error::InteriorNul),
+            PluginStrInner::CStr(s) => Ok(Cow::Borrowed(s)),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for PluginStr<'a> {
+    fn from(value: &'a str) -> Self {
+        Self(PluginStrInner::Str(value))
+    }
+}
+
+impl<'a> From<&'a String> for PluginStr<'a> {
+    fn from(value: &'a String) -> Self {
+        Self(PluginStrInner::Str(value))
+    }
+}
+
+impl<'a> From<&'a CStr> for PluginStr<'a> {
+    fn from(value: &'a CStr) -> Self {
+        Self(PluginStrInner::CStr(value))
+    }
+}
+
+impl<'a> From<&'a CString> for PluginStr<'a> {
+    fn from(value: &'a CString) -> Self {
+        Self(PluginStrInner::CStr(value))
+    }
+}
+
+/// An owned buffer of bytes returned from a plugin.
+///
+/// The buffer is freed by the allocator that created it, even when it
+/// is dropped on the other side of the plugin boundary. Implementations
+/// can return either a `PluginBuffer` or a `Vec<u8>` where the
+/// interface declares a `PluginBuffer`.
+#[repr(C)]
+pub struct PluginBuffer {
+    ptr: *mut u8,
+    len: usize,
+    capacity: usize,
+    drop: unsafe extern "C" fn(*mut u8, usize, usize),
+}
+
+// SAFETY: the buffer is uniquely owned, like a `Vec<u8>`.
+unsafe impl Send for PluginBuffer {}
+// SAFETY: the buffer is only read through shared references.
+unsafe impl Sync for PluginBuffer {}
+
+/// Free a buffer allocated as a `Vec<u8>` by this copy of the crate.
+unsafe extern "C" fn drop_vec(ptr: *mut u8, len: usize, capacity: usize) {
+    drop(Vec::from_raw_parts(ptr, len, capacity));
+}
+
+impl PluginBuffer {
+    /// View the contents of this buffer.
+    #[must_use]
+    pub fn as_slice(&self) -> &[u8] {
+        if self.len == 0 {
+            &[]
+        } else {
+            // SAFETY: `ptr` and `len` came from a `Vec<u8>`.
+            unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+        }
+    }
+
+    /// Copy the contents of this buffer into a `Vec` owned by the
+    /// current allocator, freeing the original buffer.
+    #[must_use]
+    pub fn into_vec(self) -> Vec<u8> {
+        self.as_slice().to_vec()
+    }
+}
+
+impl From<Vec<u8>> for PluginBuffer {
+    fn from(value: Vec<u8>) -> Self {
+        let mut value = std::mem::ManuallyDrop::new(value);
+        Self {
+            ptr: value.as_mut_ptr(),
+            len: value.len(),
+            capacity: value.capacity(),
+            drop: drop_vec,
+        }
+    }
+}
+
+impl From<PluginBuffer> for Vec<u8> {
+    fn from(value: PluginBuffer) -> Self {
+        value.into_vec()
+    }
+}
+
+impl Drop for PluginBuffer {
+    fn drop(&mut self) {
+        // SAFETY: the drop function was provided alongside this buffer.
+        unsafe { (self.drop)(self.ptr, self.len, self.capacity) }
+    }
+}
+
+impl std::ops::Deref for PluginBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for PluginBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl std::fmt::Debug for PluginBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PluginBuffer")
+            .field(&self.as_slice())
+            .finish()
+    }
+}
+
+/// An owned string returned from a plugin.
+///
+/// The string is freed by the allocator that created it, even when it
+/// is dropped on the other side of the plugin boundary. Implementations
+/// can return either a `PluginString` or a `String` where the interface
+/// declares a `PluginString`.
+///
+/// Strings received from the other side of the boundary are checked to
+/// be valid UTF-8 before they are returned, as the other side may not
+/// have made them from a `String`.
+#[repr(C)]
+pub struct PluginString {
+    buffer: PluginBuffer,
+}
+
+impl PluginString {
+    /// View the contents of this string.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        // SAFETY: the buffer was created from a `String` on this side of
+        // the boundary, or checked by `check` when it was received.
+        unsafe { std::str::from_utf8_unchecked(self.buffer.as_slice()) }
+    }
+
+    /// Copy the contents of this string into a `String` owned by the
+    /// current allocator, freeing the original string. Any invalid
+    /// UTF-8 is replaced with U+FFFD.
+    #[must_use]
+    pub fn into_string(self) -> String {
+        String::from_utf8_lossy(self.buffer.as_slice()).into_owned()
+    }
+
+    /// Check that a string received from the other side of the plugin
+    /// boundary is valid UTF-8.
+    pub(crate) fn check(&self) -> Result<()> {
+        std::str::from_utf8(self.buffer.as_slice()).map_err(|_| Error::InvalidUtf8)?;
+        Ok(())
+    }
+}
+
+impl From<String> for PluginString {
+    fn from(value: String) -> Self {
+        Self {
+            buffer: value.into_bytes().into(),
+        }
+    }
+}
+
+impl From<&str> for PluginString {
+    fn from(value: &str) -> Self {
+        value.to_owned().into()
+    }
+}
+
+impl From<PluginString> for String {
+    fn from(value: PluginString) -> Self {
+        value.into_string()
+    }
+}
+
+impl std::ops::Deref for PluginString {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for PluginString {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl std::fmt::Debug for PluginString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl std::fmt::Display for PluginString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+/// An FFI-safe error returned from a plugin function, made up of an
+/// error code and a message.
+#[repr(C)]
+pub struct PluginError {
+    code: i32,
+    message: PluginString,
+}
+
+impl PluginError {
+    /// Create a new error with a `code` and `message`.
+    pub fn new<S: Into<String>>(code: i32, message: S) -> Self {
+        Self {
+            code,
+            message: message.into().into(),
+        }
+    }
+
+    /// The error code.
+    #[must_use]
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    /// The error message.
+    #[must_use]
+    pub fn message(&self) -> &str {
+        self.message.as_str()
+    }
+
+    /// Check that an error received from the other side of the plugin
+    /// boundary has a valid UTF-8 message.
+    pub(crate) fn check(&self) -> Result<()> {
+        self.message.check()
+    }
+}
+
+impl std::fmt::Debug for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PluginError")
+            .field("code", &self.code)
+            .field("message", &self.message)
+            .finish()
+    }
+}
+
+impl std::fmt::Display for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// The C ABI representation of a `Result` returned from a plugin
+/// function. Interfaces declare `Result<T, E>`, and the macros convert
+/// to and from this type.
+#[repr(C)]
+pub struct PluginResult<T, E> {
+    is_ok: bool,
+    value: PluginResultValue<T, E>,
+}
+
+#[repr(C)]
+union PluginResultValue<T, E> {
+    ok: std::mem::ManuallyDrop<T>,
+    err: std::mem::ManuallyDrop<E>,
+}
+
+impl<T, E> PluginResult<T, E> {
+    /// View the value this holds.
+    pub(crate) fn as_result(&self) -> std::result::Result<&T, &E> {
+        // SAFETY: `is_ok` records which field is initialised.
+        unsafe {
+            if self.is_ok {
+                Ok(&self.value.ok)
+            } else {
+                Err(&self.value.err)
+            }
+        }
+    }
+
+    /// Convert back into a Rust `Result`.
+    ///
+    /// # Errors
+    ///
+    /// The error value, if this holds one.
+    pub fn into_result(self) -> std::result::Result<T, E> {
+        let mut this = std::mem::ManuallyDrop::new(self);
+        // SAFETY: `is_ok` records which field is initialised, and `this`
+        // is not dropped so the value is only taken once.
+        unsafe {
+            if this.is_ok {
+                Ok(std::mem::ManuallyDrop::take(&mut this.value.ok))
+            } else {
+                Err(std::mem::ManuallyDrop::take(&mut this.value.err))
+            }
+        }
+    }
+}
+
+impl<T, E> From<std::result::Result<T, E>> for PluginResult<T, E> {
+    fn from(value: std::result::Result<T, E>) -> Self {
+        match value {
+            Ok(ok) => Self {
+                is_ok: true,
+                value: PluginResultValue {
+                    ok: std::mem::ManuallyDrop::new(ok),
+                },
+            },
+            Err(err) => Self {
+                is_ok: false,
+                value: PluginResultValue {
+                    err: std::mem::ManuallyDrop::new(err),
+                },
+            },
+        }
+    }
+}
+
+impl<T, E> Drop for PluginResult<T, E> {
+    fn drop(&mut self) {
+        // SAFETY: `is_ok` records which field is initialised.
+        unsafe {
+            if self.is_ok {
+                std::mem::ManuallyDrop::drop(&mut self.value.ok);
+            } else {
+                std::mem::ManuallyDrop::drop(&mut self.value.err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn invalid_string() -> PluginString {
+        PluginString {
+            buffer: vec![b'a', 0xff, b'b'].into(),
+        }
+    }
+
+    /// An error whose message isn't valid UTF-8.
+    pub(crate) fn invalid_error() -> PluginError {
+        PluginError {
+            code: 1,
+            message: invalid_string(),
+        }
+    }
+
+    #[test]
+    fn buffers_round_trip() {
+        let buffer = PluginBuffer::from(vec![1, 2, 3]);
+        assert_eq!(buffer.as_slice(), &[1, 2, 3]);
+        assert_eq!(buffer.into_vec(), vec![1, 2, 3]);
+        assert!(PluginBuffer::from(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        let string = PluginString::from("Hello!");
+        assert!(string.check().is_ok());
+        assert_eq!(string.as_str(), "Hello!");
+        assert_eq!(string.into_string(), "Hello!");
+    }
+
+    #[test]
+    fn invalid_strings_are_rejected() {
+        assert!(matches!(invalid_string().check(), Err(Error::InvalidUtf8)));
+        assert_eq!(invalid_string().into_string(), "a\u{fffd}b");
+        assert!(matches!(invalid_error().check(), Err(Error::InvalidUtf8)));
+    }
+
+    #[test]
+    fn results_round_trip() {
+        let ok = PluginResult::<u32, PluginError>::from(Ok(5));
+        assert!(matches!(ok.as_result(), Ok(5)));
+        assert_eq!(ok.into_result().unwrap(), 5);
+        let err = PluginResult::<u32, PluginError>::from(Err(PluginError::new(2, "failed")));
+        let err = err.into_result().unwrap_err();
+        assert_eq!(err.code(), 2);
+        assert_eq!(err.message(), "failed");
+    }
+}
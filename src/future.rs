@@ -0,0 +1,154 @@
+//! Awaiting the `async` functions of a plugin.
+//!
+//! The host passes the plugin a callback, and a pointer to the shared
+//! state of the call. The plugin calls the callback exactly once, from
+//! any thread, when the function completes. This stores the result and
+//! wakes the task awaiting the call's [`PluginFuture`].
+
+use std::{
+    ffi::{c_void, CStr},
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll, Waker},
+};
+
+use libc::c_char;
+
+use crate::{Error, Result};
+
+/// A call to an `async` plugin function, which resolves when the plugin
+/// completes it.
+///
+/// The future borrows the plugin, so the plugin can't be unloaded while
+/// it is awaited. A plugin waits for any calls still running before it
+/// is unloaded, even if their futures have been dropped.
+#[must_use = "the plugin's result is lost unless the future is awaited"]
+pub struct PluginFuture<'a, T> {
+    call: Arc<Call<T>>,
+    /// The span of the call, entered while the future is polled.
+    #[cfg(feature = "tracing")]
+    span: Option<crate::trace::CallSpan>,
+    _plugin: PhantomData<&'a ()>,
+}
+
+/// The state shared between a [`PluginFuture`] and the plugin.
+struct Call<T> {
+    state: Mutex<CallState<T>>,
+}
+
+struct CallState<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T> PluginFuture<'_, T> {
+    /// Create the future for a new call, and the context to pass to
+    /// the plugin.
+    fn new() -> (Self, *mut c_void) {
+        let call = Arc::new(Call {
+            state: Mutex::new(CallState {
+                result: None,
+                waker: None,
+            }),
+        });
+        let context = Arc::into_raw(Arc::clone(&call)).cast_mut().cast();
+        (
+            Self {
+                call,
+                #[cfg(feature = "tracing")]
+                span: None,
+                _plugin: PhantomData,
+            },
+            context,
+        )
+    }
+
+    /// Enter `span` each time the future is polled, and finish it when
+    /// the call completes.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn instrument(mut self, span: crate::trace::CallSpan) -> Self {
+        self.span = Some(span);
+        self
+    }
+}
+
+impl<T> Future for PluginFuture<'_, T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.as_ref().map(crate::trace::CallSpan::enter);
+        let mut state = self
+            .call
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(result) = state.result.take() {
+            #[cfg(feature = "tracing")]
+            if let Some(span) = &self.span {
+                span.finish(&result);
+            }
+            Poll::Ready(result)
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for PluginFuture<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PluginFuture").finish_non_exhaustive()
+    }
+}
+
+/// Start a call to an `async` plugin function, returning its future and
+/// the context to pass to the plugin.
+pub fn async_call<'a, T>() -> (PluginFuture<'a, T>, *mut c_void) {
+    PluginFuture::new()
+}
+
+/// Check whether an `async` call to `function` panicked, given the
+/// message the plugin completed it with.
+///
+/// # Errors
+///
+/// - [`Error::PluginPanicked`] if the function panicked.
+/// - [`Error::InvalidUtf8`] if a string argument wasn't valid UTF-8.
+///
+/// # Safety
+///
+/// `panic` must be null, or point to a nul-terminated string.
+pub unsafe fn async_result(panic: *const c_char, function: &str) -> Result<()> {
+    if panic.is_null() {
+        Ok(())
+    } else {
+        let message = CStr::from_ptr(panic).to_string_lossy().into_owned();
+        Err(crate::__private::caught_panic(message, |message| {
+            Error::PluginPanicked {
+                function: function.to_string(),
+                message,
+            }
+        }))
+    }
+}
+
+/// Complete the call with `context`, waking the task awaiting it.
+///
+/// # Safety
+///
+/// `context` must have been returned by [`async_call`] for a call
+/// returning `T`, and not already completed.
+pub unsafe fn complete_async<T>(context: *mut c_void, result: Result<T>) {
+    let call = Arc::from_raw(context.cast_const().cast::<Call<T>>());
+    let waker = {
+        let mut state = call.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.result = Some(result);
+        state.waker.take()
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
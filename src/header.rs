@@ -0,0 +1,405 @@
+//! Checking a file is a shared library for the host's platform before
+//! it is opened.
+//!
+//! Opening a library built for another architecture, or a file which
+//! isn't a library at all, only gives an opaque error from the system's
+//! dynamic loader. Reading the file's header first gives a clearer
+//! error, and is faster for files which aren't libraries.
+
+use std::{env::consts::ARCH, fs::File, io::Read};
+
+use crate::{Error, Result};
+
+/// The number of bytes read from the start of a file, which is enough
+/// for the headers of every supported format.
+pub(crate) const HEADER_LEN: usize = 4096;
+
+/// The executable formats which can hold a shared library.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Elf,
+    Pe,
+    MachO,
+}
+
+/// The format of shared libraries on the host's platform, if known.
+pub(crate) fn host_format() -> Option<Format> {
+    if cfg!(target_os = "windows") {
+        Some(Format::Pe)
+    } else if cfg!(target_vendor = "apple") {
+        Some(Format::MachO)
+    } else if cfg!(unix) {
+        Some(Format::Elf)
+    } else {
+        None
+    }
+}
+
+/// The platform libraries are checked against.
+struct Host {
+    format: Format,
+    arch: &'static str,
+    pointer_width: u32,
+    big_endian: bool,
+}
+
+/// The architectures each format's headers are read as.
+fn known_archs(format: Format) -> &'static [&'static str] {
+    match format {
+        Format::Elf => &[
+            "x86",
+            "x86_64",
+            "arm",
+            "aarch64",
+            "mips",
+            "mips64",
+            "powerpc",
+            "powerpc64",
+            "s390x",
+            "riscv32",
+            "riscv64",
+            "loongarch64",
+        ],
+        Format::Pe | Format::MachO => &["x86", "x86_64", "arm", "aarch64"],
+    }
+}
+
+/// Check that `file`, read from its current position, is a shared
+/// library for the host's platform and architecture.
+///
+/// # Errors
+///
+/// - [`Error::Io`] if the file cannot be read.
+/// - [`Error::NotASharedLibrary`] if the file is not a shared library for the host's platform.
+/// - [`Error::WrongArchitecture`] if the library is for a different architecture.
+/// - [`Error::PointerWidthMismatch`] or [`Error::EndiannessMismatch`] if the library has a different pointer width or endianness.
+pub(crate) fn check(file: &File) -> Result<()> {
+    let Some(format) = host_format() else {
+        // Let the dynamic loader decide
+        return Ok(());
+    };
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    file.take(HEADER_LEN as u64).read_to_end(&mut header)?;
+
+    check_header(
+        &header,
+        &Host {
+            format,
+            arch: ARCH,
+            pointer_width: usize::BITS,
+            big_endian: cfg!(target_endian = "big"),
+        },
+    )
+}
+
+/// Check that `header`, the start of a file, is the header of a shared
+/// library for `host`.
+fn check_header(header: &[u8], host: &Host) -> Result<()> {
+    let archs = match host.format {
+        Format::Elf => elf_arch(header),
+        Format::Pe => pe_arch(header),
+        Format::MachO => mach_o_archs(header),
+    }
+    .ok_or(Error::NotASharedLibrary)?;
+
+    if host.format == Format::Elf {
+        check_elf_class(header, host)?;
+    }
+
+    // Architectures the headers aren't read as can't be compared
+    if !known_archs(host.format).contains(&host.arch) || archs.iter().any(|arch| arch == host.arch)
+    {
+        Ok(())
+    } else {
+        Err(Error::WrongArchitecture {
+            expected: host.arch.to_string(),
+            found: archs.join(", "),
+        })
+    }
+}
+
+/// Check that an ELF file has the pointer width and endianness of
+/// `host`, which its machine alone doesn't give.
+fn check_elf_class(header: &[u8], host: &Host) -> Result<()> {
+    let pointer_width = match header[4] {
+        1 => 32,
+        2 => 64,
+        _ => return Err(Error::NotASharedLibrary),
+    };
+    let big_endian = match header[5] {
+        1 => false,
+        2 => true,
+        _ => return Err(Error::NotASharedLibrary),
+    };
+    if pointer_width != host.pointer_width {
+        return Err(Error::PointerWidthMismatch {
+            expected: host.pointer_width,
+            found: pointer_width,
+        });
+    }
+    if big_endian != host.big_endian {
+        let endianness = |big_endian| if big_endian { "big" } else { "little" };
+        return Err(Error::EndiannessMismatch {
+            expected: endianness(host.big_endian).to_string(),
+            found: endianness(big_endian).to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Read a `u16` from `bytes` at `offset`.
+pub(crate) fn u16_at(bytes: &[u8], offset: usize, big_endian: bool) -> Option<u16> {
+    let bytes = bytes.get(offset..offset + 2)?.try_into().ok()?;
+    Some(if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    })
+}
+
+/// Read a `u32` from `bytes` at `offset`.
+pub(crate) fn u32_at(bytes: &[u8], offset: usize, big_endian: bool) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    })
+}
+
+/// Read a `u64` from `bytes` at `offset`.
+pub(crate) fn u64_at(bytes: &[u8], offset: usize, big_endian: bool) -> Option<u64> {
+    let bytes = bytes.get(offset..offset + 8)?.try_into().ok()?;
+    Some(if big_endian {
+        u64::from_be_bytes(bytes)
+    } else {
+        u64::from_le_bytes(bytes)
+    })
+}
+
+/// The architecture of an ELF shared object.
+fn elf_arch(header: &[u8]) -> Option<Vec<String>> {
+    const ET_DYN: u16 = 3;
+
+    if !header.starts_with(b"\x7fELF") {
+        return None;
+    }
+    let is_64 = *header.get(4)? == 2;
+    let big_endian = *header.get(5)? == 2;
+    if u16_at(header, 16, big_endian)? != ET_DYN {
+        return None;
+    }
+    let arch = match (u16_at(header, 18, big_endian)?, is_64) {
+        (3, _) => "x86",
+        (62, _) => "x86_64",
+        (40, _) => "arm",
+        (183, _) => "aarch64",
+        (8, false) => "mips",
+        (8, true) => "mips64",
+        (20, _) => "powerpc",
+        (21, _) => "powerpc64",
+        (22, _) => "s390x",
+        (243, false) => "riscv32",
+        (243, true) => "riscv64",
+        (258, _) => "loongarch64",
+        (machine, _) => return Some(vec![format!("unknown ELF machine {machine:#x}")]),
+    };
+    Some(vec![arch.to_string()])
+}
+
+/// The architecture of a PE dynamic-link library.
+fn pe_arch(header: &[u8]) -> Option<Vec<String>> {
+    const IMAGE_FILE_DLL: u16 = 0x2000;
+
+    if !header.starts_with(b"MZ") {
+        return None;
+    }
+    let pe = usize::try_from(u32_at(header, 0x3c, false)?).ok()?;
+    if header.get(pe..pe + 4)? != b"PE\0\0" {
+        return None;
+    }
+    if u16_at(header, pe + 22, false)? & IMAGE_FILE_DLL == 0 {
+        return None;
+    }
+    let arch = match u16_at(header, pe + 4, false)? {
+        0x014c => "x86",
+        0x8664 => "x86_64",
+        0x01c0 | 0x01c4 => "arm",
+        0xaa64 => "aarch64",
+        machine => return Some(vec![format!("unknown PE machine {machine:#x}")]),
+    };
+    Some(vec![arch.to_string()])
+}
+
+/// The architecture of the Mach-O CPU type `cpu_type`.
+pub(crate) fn cpu_arch(cpu_type: u32) -> String {
+    match cpu_type {
+        7 => "x86".to_string(),
+        0x0100_0007 => "x86_64".to_string(),
+        12 => "arm".to_string(),
+        0x0100_000c => "aarch64".to_string(),
+        cpu_type => format!("unknown Mach-O CPU type {cpu_type:#x}"),
+    }
+}
+
+/// The architectures of a Mach-O dynamic library or bundle. Universal
+/// binaries hold more than one.
+fn mach_o_archs(header: &[u8]) -> Option<Vec<String>> {
+    const MH_DYLIB: u32 = 6;
+    const MH_BUNDLE: u32 = 8;
+    const FAT_MAGIC: u32 = 0xcafe_babe;
+
+    let magic = u32_at(header, 0, true)?;
+    if magic == FAT_MAGIC {
+        // Java class files share this magic, but have a larger number here
+        let count = usize::try_from(u32_at(header, 4, true)?).ok()?;
+        if count > 20 {
+            return None;
+        }
+        return (0..count)
+            .map(|idx| u32_at(header, 8 + idx * 20, true).map(cpu_arch))
+            .collect();
+    }
+
+    let big_endian = match magic {
+        0xfeed_face | 0xfeed_facf => true,
+        0xcefa_edfe | 0xcffa_edfe => false,
+        _ => return None,
+    };
+    let file_type = u32_at(header, 12, big_endian)?;
+    if file_type != MH_DYLIB && file_type != MH_BUNDLE {
+        return None;
+    }
+    Some(vec![cpu_arch(u32_at(header, 4, big_endian)?)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_header, Format, Host};
+    use crate::Error;
+
+    const LINUX: Host = Host {
+        format: Format::Elf,
+        arch: "x86_64",
+        pointer_width: 64,
+        big_endian: false,
+    };
+    const WINDOWS: Host = Host {
+        format: Format::Pe,
+        arch: "x86_64",
+        pointer_width: 64,
+        big_endian: false,
+    };
+    const MACOS: Host = Host {
+        format: Format::MachO,
+        arch: "aarch64",
+        pointer_width: 64,
+        big_endian: false,
+    };
+
+    /// The header of an ELF shared object.
+    fn elf(class: u8, data: u8, machine: u16) -> Vec<u8> {
+        let mut header = vec![0; 64];
+        header[..6].copy_from_slice(&[0x7f, b'E', b'L', b'F', class, data]);
+        let (kind, machine) = if data == 2 {
+            (3u16.to_be_bytes(), machine.to_be_bytes())
+        } else {
+            (3u16.to_le_bytes(), machine.to_le_bytes())
+        };
+        header[16..18].copy_from_slice(&kind);
+        header[18..20].copy_from_slice(&machine);
+        header
+    }
+
+    /// The header of a PE dynamic-link library.
+    fn pe(machine: u16) -> Vec<u8> {
+        let mut header = vec![0; 0x100];
+        header[..2].copy_from_slice(b"MZ");
+        header[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        header[0x80..0x84].copy_from_slice(b"PE\0\0");
+        header[0x84..0x86].copy_from_slice(&machine.to_le_bytes());
+        header[0x96..0x98].copy_from_slice(&0x2000u16.to_le_bytes());
+        header
+    }
+
+    /// The header of a 64-bit Mach-O dynamic library.
+    fn mach_o(cpu_type: u32) -> Vec<u8> {
+        let mut header = vec![0; 32];
+        header[..4].copy_from_slice(&0xfeed_facfu32.to_le_bytes());
+        header[4..8].copy_from_slice(&cpu_type.to_le_bytes());
+        header[12..16].copy_from_slice(&6u32.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn accepts_libraries_for_the_host() {
+        check_header(&elf(2, 1, 62), &LINUX).unwrap();
+        check_header(&pe(0x8664), &WINDOWS).unwrap();
+        check_header(&mach_o(0x0100_000c), &MACOS).unwrap();
+    }
+
+    #[test]
+    fn rejects_libraries_of_other_platforms() {
+        for (header, host) in [
+            (pe(0x8664), &LINUX),
+            (mach_o(0x0100_0007), &LINUX),
+            (elf(2, 1, 62), &WINDOWS),
+            (elf(2, 1, 183), &MACOS),
+            (b"#!/bin/sh".to_vec(), &LINUX),
+        ] {
+            assert!(matches!(
+                check_header(&header, host),
+                Err(Error::NotASharedLibrary)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_libraries_for_other_architectures() {
+        for (header, host) in [
+            (elf(2, 1, 183), &LINUX),
+            (pe(0xaa64), &WINDOWS),
+            (mach_o(0x0100_0007), &MACOS),
+        ] {
+            assert!(matches!(
+                check_header(&header, host),
+                Err(Error::WrongArchitecture { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_elf_files_of_other_classes() {
+        assert!(matches!(
+            check_header(&elf(1, 1, 62), &LINUX),
+            Err(Error::PointerWidthMismatch {
+                expected: 64,
+                found: 32
+            })
+        ));
+        let big_endian_host = Host {
+            arch: "powerpc64",
+            big_endian: true,
+            ..LINUX
+        };
+        assert!(matches!(
+            check_header(&elf(2, 1, 21), &big_endian_host),
+            Err(Error::EndiannessMismatch { .. })
+        ));
+        check_header(&elf(2, 2, 21), &big_endian_host).unwrap();
+    }
+
+    #[test]
+    fn skips_architectures_it_cannot_read() {
+        let host = Host {
+            arch: "sparc64",
+            ..LINUX
+        };
+        check_header(&elf(2, 1, 43), &host).unwrap();
+        assert!(matches!(
+            check_header(&pe(0x8664), &host),
+            Err(Error::NotASharedLibrary)
+        ));
+    }
+}
@@ -0,0 +1,66 @@
+//! Functions provided by the host for plugins to call.
+
+use std::{any::Any, ffi::c_void, sync::Arc};
+
+/// A set of functions provided by the host, defined with
+/// `host_interface!`, which is passed to plugins when they are loaded.
+///
+/// Create one with the `host_api` function generated for the host
+/// interface, then give it to a [`PluginLoader`](crate::PluginLoader).
+#[derive(Clone)]
+pub struct HostApi {
+    inner: Arc<HostApiInner>,
+}
+
+struct HostApiInner {
+    signature: u64,
+    vtable: *const c_void,
+    _owner: Box<dyn Any>,
+}
+
+// SAFETY: the vtable only holds function pointers and a pointer to the
+// implementation, which is `Send + Sync`.
+unsafe impl Send for HostApiInner {}
+// SAFETY: as above.
+unsafe impl Sync for HostApiInner {}
+
+impl HostApi {
+    /// Create a host API from an `implementation` and a function which
+    /// builds the vtable given a pointer to it.
+    pub(crate) fn new<T, V, F>(signature: u64, implementation: T, vtable: F) -> Self
+    where
+        T: Send + Sync + 'static,
+        V: 'static,
+        F: FnOnce(*const c_void) -> V,
+    {
+        let implementation = Box::new(implementation);
+        let vtable = Box::new(vtable(std::ptr::from_ref(&*implementation).cast()));
+        let vtable_ptr = std::ptr::from_ref(&*vtable).cast();
+        Self {
+            inner: Arc::new(HostApiInner {
+                signature,
+                vtable: vtable_ptr,
+                _owner: Box::new((implementation, vtable)),
+            }),
+        }
+    }
+
+    /// The signature of the host interface this implements.
+    #[must_use]
+    pub fn signature(&self) -> u64 {
+        self.inner.signature
+    }
+
+    /// A pointer to the vtable passed to plugins.
+    pub(crate) fn vtable(&self) -> *const c_void {
+        self.inner.vtable
+    }
+}
+
+impl std::fmt::Debug for HostApi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostApi")
+            .field("signature", &self.inner.signature)
+            .finish_non_exhaustive()
+    }
+}
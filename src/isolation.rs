@@ -0,0 +1,861 @@
+//! Running plugins in a separate worker process.
+//!
+//! The worker is the host's own executable, started again with
+//! [`WORKER_VAR`] set to the signature of the plugin interface. The
+//! `run_isolated_worker` function generated by `plugin_interface!` sees
+//! this at the start of `main`, opens the plugin, then serves requests
+//! from the host over a socket on [`WORKER_FD`] until the host closes it.
+//! The worker inherits the plugin file the host checked on
+//! [`PLUGIN_FD`], and opens the plugin from it rather than by its path.
+//!
+//! Each request and reply is a length-prefixed message, encoded with
+//! [`Writer`] and read with [`Reader`]. If the worker crashes, the
+//! socket is closed and the host reports [`Error::PluginCrashed`]. If a
+//! request has a timeout and the worker doesn't reply in time, it is
+//! killed and the host reports [`Error::Timeout`]. If the plugin breaks
+//! its sandbox, the worker reports [`Error::SandboxViolation`] and exits.
+//! The host can kill a worker at any time, even while a request waits
+//! for its reply, after which it reports [`Error::WorkerKilled`].
+
+use std::{
+    env,
+    ffi::c_void,
+    fs::File,
+    io::{self, Read, Write},
+    os::unix::{
+        io::{AsRawFd, FromRawFd},
+        net::UnixStream,
+        process::{CommandExt, ExitStatusExt},
+    },
+    path::Path,
+    process::{self, Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    wire::{Reader, Writer},
+    Error, PluginDependency, PluginLibrary, PluginLoader, PluginMetadata, ResourceLimits, Result,
+};
+
+/// The environment variable which marks a process as a worker, holding
+/// the signature of the plugin interface it serves.
+const WORKER_VAR: &str = "DYNAMIC_PLUGIN_WORKER";
+
+/// The file descriptor of the worker's end of the socket.
+const WORKER_FD: i32 = 3;
+
+/// The file descriptor of the plugin file in the worker.
+const PLUGIN_FD: i32 = 4;
+
+/// How long to wait for a worker which has closed its socket to exit,
+/// before killing it.
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+/// Whether the worker owes the host a reply. Whichever of the reply and
+/// a sandbox violation takes this sends its message, so a violation
+/// outside a request, or while the reply is being sent, isn't mistaken
+/// for a reply.
+static REPLY_OWED: AtomicBool = AtomicBool::new(false);
+
+/// The longest message sent between the host and a worker. A worker
+/// which sends a longer one is taken to have crashed.
+const MAX_MESSAGE_LEN: usize = 256 * 1024 * 1024;
+
+// Requests from the host to the worker
+const OPEN: u8 = 0;
+const METADATA: u8 = 1;
+const INITIALISE: u8 = 2;
+const CALL: u8 = 3;
+const CREATE: u8 = 4;
+const DESTROY: u8 = 5;
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+const SANDBOX: u8 = 6;
+
+/// The function generated by `plugin_interface!` which calls the named
+/// plugin function in the worker, reading its arguments from a request
+/// and writing its results to the reply.
+pub type CallFn =
+    unsafe fn(&PluginLibrary, &str, *mut c_void, &mut Reader<'_>, &mut Writer) -> Result<()>;
+
+/// A worker process running a plugin.
+pub struct PluginWorker {
+    /// The state of the connection, held for the whole of each request.
+    state: Mutex<WorkerState>,
+    /// The worker process, only held briefly, so that it can be killed
+    /// while a request waits for its reply.
+    child: Mutex<Child>,
+    /// Whether the worker has stopped serving requests, so that this can
+    /// be checked without waiting for a request.
+    failed: AtomicBool,
+    /// Whether the host killed the worker.
+    killed: AtomicBool,
+    id: u32,
+    /// How long requests without their own timeout may take.
+    timeout: Option<Duration>,
+}
+
+struct WorkerState {
+    stream: UnixStream,
+    /// Why the worker stopped, once it has.
+    failure: Option<Failure>,
+    /// Whether the worker has been sandboxed.
+    sandboxed: bool,
+    /// The limits on the worker's resources.
+    limits: ResourceLimits,
+}
+
+/// Why a worker stopped serving requests.
+enum Failure {
+    /// The worker exited unexpectedly.
+    Crashed(ExitStatus),
+    /// The worker didn't reply to a request in time, and was killed.
+    TimedOut { function: String, timeout: Duration },
+    /// The plugin made a system call its sandbox doesn't allow.
+    Violated { syscall: Option<String> },
+    /// The worker used more of a resource than its limit.
+    LimitExceeded { resource: &'static str },
+    /// The host killed the worker.
+    Killed,
+}
+
+impl Failure {
+    /// The error returned by requests to the stopped worker.
+    fn error(&self) -> Error {
+        match self {
+            Self::Crashed(status) => Error::PluginCrashed(*status),
+            Self::TimedOut { function, timeout } => Error::Timeout {
+                function: function.clone(),
+                timeout: *timeout,
+            },
+            Self::Violated { syscall } => Error::SandboxViolation {
+                syscall: syscall.clone(),
+            },
+            Self::LimitExceeded { resource } => Error::ResourceLimitExceeded {
+                resource: (*resource).to_string(),
+            },
+            Self::Killed => Error::WorkerKilled,
+        }
+    }
+}
+
+impl PluginWorker {
+    /// Start a worker for the plugin interface with `signature`, whose
+    /// requests take at most `timeout` unless they give their own, and
+    /// whose resources are limited by `limits`. The worker inherits the
+    /// plugin `file` to open it from.
+    pub(crate) fn spawn(
+        signature: u64,
+        timeout: Option<Duration>,
+        limits: ResourceLimits,
+        file: &File,
+    ) -> Result<Self> {
+        // Workers which are served never get this far, so the host has
+        // not called `run_isolated_worker`. Starting another worker
+        // would do the same again.
+        if env::var_os(WORKER_VAR).is_some() {
+            return Err(Error::Isolation(
+                "this process was started as a worker, but `run_isolated_worker` was not called at the start of `main`".to_string(),
+            ));
+        }
+
+        let (stream, worker_stream) = UnixStream::pair()?;
+        let fd = worker_stream.as_raw_fd();
+        let file_fd = file.as_raw_fd();
+
+        let mut command = Command::new(env::current_exe()?);
+        command
+            .env(WORKER_VAR, signature.to_string())
+            .stdin(Stdio::null());
+        limits.prepare(&mut command);
+        // Safety: only async-signal-safe functions are called
+        unsafe {
+            command.pre_exec(move || {
+                // Both are closed on exec unless they are duplicated.
+                // Either may already be on the other's descriptor, so
+                // both are moved out of the way first.
+                let socket = libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, PLUGIN_FD + 1);
+                let plugin = libc::fcntl(file_fd, libc::F_DUPFD_CLOEXEC, PLUGIN_FD + 1);
+                if socket == -1
+                    || plugin == -1
+                    || libc::dup2(socket, WORKER_FD) == -1
+                    || libc::dup2(plugin, PLUGIN_FD) == -1
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                limits.apply()
+            });
+        }
+        let child = command.spawn()?;
+        drop(worker_stream);
+
+        Ok(Self {
+            id: child.id(),
+            state: Mutex::new(WorkerState {
+                stream,
+                failure: None,
+                sandboxed: false,
+                limits,
+            }),
+            child: Mutex::new(child),
+            failed: AtomicBool::new(false),
+            killed: AtomicBool::new(false),
+            timeout,
+        })
+    }
+
+    /// The worker's process ID.
+    #[must_use]
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Whether the worker is still serving requests, rather than having
+    /// crashed or been killed. This doesn't wait for a call in flight.
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        !self.failed.load(Ordering::SeqCst)
+    }
+
+    /// Kill the worker, such as when a call without a timeout hangs. A
+    /// call in flight, and every later call, returns
+    /// [`Error::WorkerKilled`]. This doesn't wait for a call in flight.
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::SeqCst);
+        self.failed.store(true, Ordering::SeqCst);
+        // The worker may already have exited
+        let _ = self.child().kill();
+    }
+
+    fn child(&self) -> MutexGuard<'_, Child> {
+        self.child.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Remember why the worker stopped, returning the error for the
+    /// request which found out.
+    fn fail(&self, state: &mut WorkerState, failure: Failure) -> Error {
+        self.failed.store(true, Ordering::SeqCst);
+        let error = failure.error();
+        state.failure = Some(failure);
+        error
+    }
+
+    /// Kill the worker, which can't be used again, and wait for it to
+    /// exit.
+    fn kill_and_wait(&self) -> io::Result<ExitStatus> {
+        let mut child = self.child();
+        let _ = child.kill();
+        child.wait()
+    }
+
+    /// Send a request to the worker, returning its reply. `function`
+    /// names what the request runs in the plugin, and `timeout` replaces
+    /// the worker's timeout if given.
+    fn request(
+        &self,
+        request: &Writer,
+        function: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(failure) = &state.failure {
+            return Err(failure.error());
+        }
+        if self.killed.load(Ordering::SeqCst) {
+            return Err(self.reap(&mut state)?);
+        }
+        if request.as_bytes().len() > MAX_MESSAGE_LEN {
+            return Err(Error::Isolation(format!(
+                "the request to run `{function}` is too long to send to the worker"
+            )));
+        }
+        let timeout = timeout.or(self.timeout);
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let stream = &mut state.stream;
+        match send(stream, request.as_bytes(), deadline).and_then(|()| receive(stream, deadline)) {
+            Ok(reply) => {
+                let mut reader = Reader::new(&reply);
+                if reader.read_bool()? {
+                    return Ok(reader.rest().to_vec());
+                }
+                let error = read_error(&mut reader)?;
+                if let Error::SandboxViolation { syscall } = &error {
+                    // The worker exits after reporting a violation
+                    self.wait_or_kill()?;
+                    let syscall = syscall.clone();
+                    self.fail(&mut state, Failure::Violated { syscall });
+                }
+                Err(error)
+            }
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                // The worker can't be trusted to serve more requests
+                let status = self.kill_and_wait()?;
+                Err(self.fail(&mut state, Failure::Crashed(status)))
+            }
+            Err(error) => match timeout {
+                Some(timeout) if is_timeout(&error) && !self.killed.load(Ordering::SeqCst) => {
+                    // The worker may be part way through the request, so
+                    // it can't be used again
+                    self.kill_and_wait()?;
+                    let function = function.to_string();
+                    Err(self.fail(&mut state, Failure::TimedOut { function, timeout }))
+                }
+                _ => Err(self.reap(&mut state)?),
+            },
+        }
+    }
+
+    /// Restrict the worker to `policy`, before opening the plugin at
+    /// `path` in it.
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    pub(crate) fn sandbox(&self, policy: &crate::SandboxPolicy, path: &Path) -> Result<()> {
+        let mut request = Writer::new();
+        request.write_u8(SANDBOX);
+        policy.write(&mut request, path);
+        self.request(&request, "sandbox", None)?;
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .sandboxed = true;
+        Ok(())
+    }
+
+    /// Open the plugin at `path` in the worker, from the file it inherited.
+    pub(crate) fn open(&self, path: &Path, check_signature: bool, signature: u64) -> Result<()> {
+        let mut request = Writer::new();
+        request.write_u8(OPEN);
+        request.write_bytes(path.as_os_str().as_encoded_bytes());
+        request.write_bool(check_signature);
+        request.write_u64(signature);
+        self.request(&request, "open", None).map(drop)
+    }
+
+    /// Read the plugin's metadata.
+    pub(crate) fn metadata(&self) -> Result<Option<PluginMetadata>> {
+        let mut request = Writer::new();
+        request.write_u8(METADATA);
+        let reply = self.request(&request, "metadata", None)?;
+        let mut reply = Reader::new(&reply);
+        if reply.read_bool()? {
+            read_metadata(&mut reply).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Call the plugin's `on_load` hook, if it has one.
+    pub(crate) fn initialise(&self) -> Result<()> {
+        let mut request = Writer::new();
+        request.write_u8(INITIALISE);
+        self.request(&request, "on_load", None).map(drop)
+    }
+
+    /// Call the plugin function called `function`, on the instance with
+    /// `handle` if it is a method, returning the encoded results.
+    /// `timeout` replaces the worker's timeout for this call if given.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::PluginCrashed`] if the worker has crashed.
+    /// - [`Error::Timeout`] if the call times out, or an earlier request did.
+    /// - Any error from calling the function in the worker.
+    #[doc(hidden)]
+    pub fn call(
+        &self,
+        function: &str,
+        handle: *mut c_void,
+        args: &Writer,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u8>> {
+        let mut request = Writer::new();
+        request.write_u8(CALL);
+        request.write_str(function);
+        request.write_handle(handle);
+        request.write_bytes(args.as_bytes());
+        self.request(&request, function, timeout)
+    }
+
+    /// Create a new instance of the plugin's state, returning its handle
+    /// in the worker.
+    pub(crate) fn create_instance(&self) -> Result<*mut c_void> {
+        let mut request = Writer::new();
+        request.write_u8(CREATE);
+        let reply = self.request(&request, "create", None)?;
+        Reader::new(&reply).read_handle()
+    }
+
+    /// Destroy an instance of the plugin's state.
+    pub(crate) fn destroy_instance(&self, handle: *mut c_void) {
+        let mut request = Writer::new();
+        request.write_u8(DESTROY);
+        request.write_handle(handle);
+        // Nothing can be done if this fails
+        let _ = self.request(&request, "destroy", None);
+    }
+}
+
+impl PluginWorker {
+    /// Wait for a worker which has closed its socket to exit, killing it
+    /// if it doesn't, and remember why it stopped. Returns the error for
+    /// later requests.
+    fn reap(&self, state: &mut WorkerState) -> Result<Error> {
+        let (status, killed) = self.wait_or_kill()?;
+        let exceeded = state.limits.exceeded(status).filter(|_| !killed);
+        let failure = if self.killed.load(Ordering::SeqCst) {
+            Failure::Killed
+        } else if let Some(resource) = exceeded {
+            Failure::LimitExceeded { resource }
+        } else if state.sandboxed && status.signal() == Some(libc::SIGSYS) {
+            // The sandbox kills workers it can't report a violation from,
+            // such as while signals are blocked to start a process
+            Failure::Violated { syscall: None }
+        } else {
+            Failure::Crashed(status)
+        };
+        Ok(self.fail(state, failure))
+    }
+
+    /// Wait for the worker to exit, killing it if it hasn't after
+    /// [`EXIT_GRACE`]. Returns its exit status, and whether it was killed.
+    fn wait_or_kill(&self) -> io::Result<(ExitStatus, bool)> {
+        let deadline = Instant::now() + EXIT_GRACE;
+        loop {
+            if let Some(status) = self.child().try_wait()? {
+                return Ok((status, false));
+            }
+            if Instant::now() >= deadline {
+                return Ok((self.kill_and_wait()?, true));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Drop for PluginWorker {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        // The worker unloads the plugin and exits when its socket closes
+        let _ = state.stream.shutdown(std::net::Shutdown::Both);
+        if state.failure.is_none() {
+            // A plugin whose `on_unload` hook hangs is killed
+            let _ = self.wait_or_kill();
+        }
+    }
+}
+
+impl std::fmt::Debug for PluginWorker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PluginWorker")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Send a length-prefixed message, giving up at `deadline` if given.
+fn send(stream: &mut UnixStream, message: &[u8], deadline: Option<Instant>) -> io::Result<()> {
+    let len = u32::try_from(message.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too long"))?;
+    stream.set_write_timeout(time_left(deadline)?)?;
+    stream.write_all(&len.to_le_bytes())?;
+    stream.set_write_timeout(time_left(deadline)?)?;
+    stream.write_all(message)
+}
+
+/// Receive a length-prefixed message, giving up at `deadline` if given.
+/// Messages longer than [`MAX_MESSAGE_LEN`] are invalid data.
+fn receive(stream: &mut UnixStream, deadline: Option<Instant>) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    stream.set_read_timeout(time_left(deadline)?)?;
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the message is too long",
+        ));
+    }
+    let mut message = vec![0; len];
+    stream.set_read_timeout(time_left(deadline)?)?;
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+/// The time left until `deadline`, as a socket timeout.
+fn time_left(deadline: Option<Instant>) -> io::Result<Option<Duration>> {
+    match deadline {
+        None => Ok(None),
+        // A zero timeout would mean waiting forever
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => Ok(Some(left)),
+            _ => Err(io::ErrorKind::TimedOut.into()),
+        },
+    }
+}
+
+/// Whether a socket error means a timeout passed.
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Serve the plugin interface with `signature` if this process was
+/// started as its worker, then exit. Otherwise, return immediately.
+pub(crate) fn run_worker(signature: u64, call: CallFn) {
+    if env::var(WORKER_VAR).ok() != Some(signature.to_string()) {
+        return;
+    }
+    // Don't let processes the plugin starts think they are workers too
+    env::remove_var(WORKER_VAR);
+    crate::limits::watch_memory();
+
+    // Safety: the host gave the worker this end of the socket
+    let mut stream = unsafe { UnixStream::from_raw_fd(WORKER_FD) };
+    let mut library = None;
+    // Whether the plugin's `on_load` hook has succeeded
+    let mut loaded = false;
+    while let Ok(request) = receive(&mut stream, None) {
+        REPLY_OWED.store(true, Ordering::SeqCst);
+        let mut reply = Writer::new();
+        let mut results = Writer::new();
+        match handle(&mut library, &request, call, &mut results) {
+            Ok(()) => {
+                loaded |= request.first() == Some(&INITIALISE);
+                reply.write_bool(true);
+                reply.write_raw(results.as_bytes());
+            }
+            Err(error) => {
+                reply.write_bool(false);
+                write_error(&mut reply, &error);
+            }
+        }
+        if reply.as_bytes().len() > MAX_MESSAGE_LEN {
+            reply = Writer::new();
+            reply.write_bool(false);
+            write_error(
+                &mut reply,
+                &Error::Isolation("the reply is too long to send to the host".to_string()),
+            );
+        }
+        if !REPLY_OWED.swap(false, Ordering::SeqCst) {
+            // A sandbox violation was reported instead, and the worker
+            // is exiting
+            loop {
+                thread::park();
+            }
+        }
+        if send(&mut stream, reply.as_bytes(), None).is_err() {
+            break;
+        }
+    }
+
+    // The host has closed the plugin
+    if let Some(library) = library.filter(|_| loaded) {
+        // Safety: the library was opened as a plugin
+        // The worker exits either way
+        let _ = unsafe { crate::__private::on_unload(&library) };
+    }
+    process::exit(0);
+}
+
+/// Handle one request in the worker.
+fn handle(
+    library: &mut Option<PluginLibrary>,
+    request: &[u8],
+    call: CallFn,
+    results: &mut Writer,
+) -> Result<()> {
+    let mut request = Reader::new(request);
+    let kind = request.read_u8()?;
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    if kind == SANDBOX {
+        return crate::SandboxPolicy::read(&mut request)?.apply();
+    }
+    if kind == OPEN {
+        let path = request.read_bytes()?;
+        // Safety: the path was written by `as_encoded_bytes` in the host
+        let path = Path::new(unsafe { std::ffi::OsStr::from_encoded_bytes_unchecked(&path) });
+        let check_signature = request.read_bool()?;
+        let signature = request.read_u64()?;
+        if library.is_some() {
+            return Err(Error::Isolation("the plugin is already open".to_string()));
+        }
+        // Safety: the host gave the worker the plugin file, and it is
+        // only opened once
+        let file = unsafe { File::from_raw_fd(PLUGIN_FD) };
+        let loader = PluginLoader::new().check_signature(check_signature);
+        *library = Some(PluginLibrary::Dynamic {
+            library: loader.open_library(path, &file, signature)?,
+            path: path.to_path_buf(),
+            digest: None,
+        });
+        return Ok(());
+    }
+
+    let library = library
+        .as_ref()
+        .ok_or_else(|| Error::Isolation("the plugin has not been opened".to_string()))?;
+    match kind {
+        METADATA => {
+            let metadata = PluginLoader::metadata(library)?;
+            results.write_bool(metadata.is_some());
+            if let Some(metadata) = metadata {
+                write_metadata(results, &metadata);
+            }
+        }
+        INITIALISE => {
+            PluginLoader::new().initialise(library)?;
+        }
+        CALL => {
+            let function = request.read_str()?;
+            let handle = request.read_handle()?;
+            let args = request.read_bytes()?;
+            // Safety: the host generated the request from the same interface
+            unsafe { call(library, &function, handle, &mut Reader::new(&args), results)? };
+        }
+        CREATE => {
+            // Safety: the host only creates instances of plugins with methods
+            let handle = unsafe { crate::__private::create_instance(library)? };
+            results.write_handle(handle);
+        }
+        DESTROY => {
+            let handle = request.read_handle()?;
+            // Safety: the host only destroys instances it created
+            unsafe { crate::__private::destroy_instance(library, handle) };
+        }
+        _ => return Err(Error::Isolation(format!("unknown request {kind}"))),
+    }
+    Ok(())
+}
+
+fn write_metadata(writer: &mut Writer, metadata: &PluginMetadata) {
+    let write_option = |writer: &mut Writer, value: &Option<String>| {
+        writer.write_bool(value.is_some());
+        if let Some(value) = value {
+            writer.write_str(value);
+        }
+    };
+    writer.write_str(&metadata.name);
+    writer.write_str(&metadata.version.to_string());
+    writer.write_u64(metadata.authors.len() as u64);
+    for author in &metadata.authors {
+        writer.write_str(author);
+    }
+    write_option(writer, &metadata.description);
+    write_option(writer, &metadata.homepage);
+    write_option(writer, &metadata.license);
+    writer.write_u64(metadata.dependencies.len() as u64);
+    for dependency in &metadata.dependencies {
+        writer.write_str(&dependency.name);
+        writer.write_str(&dependency.requirement.to_string());
+    }
+}
+
+fn read_metadata(reader: &mut Reader<'_>) -> Result<PluginMetadata> {
+    let read_option = |reader: &mut Reader<'_>| -> Result<Option<String>> {
+        if reader.read_bool()? {
+            reader.read_str().map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+    let invalid = |e: semver::Error| Error::InvalidMetadata(e.to_string());
+    Ok(PluginMetadata {
+        name: reader.read_str()?,
+        version: reader.read_str()?.parse().map_err(invalid)?,
+        authors: (0..reader.read_u64()?)
+            .map(|_| reader.read_str())
+            .collect::<Result<_>>()?,
+        description: read_option(reader)?,
+        homepage: read_option(reader)?,
+        license: read_option(reader)?,
+        dependencies: (0..reader.read_u64()?)
+            .map(|_| {
+                Ok(PluginDependency {
+                    name: reader.read_str()?,
+                    requirement: reader.read_str()?.parse().map_err(invalid)?,
+                })
+            })
+            .collect::<Result<_>>()?,
+    })
+}
+
+// Errors sent from the worker to the host
+const OTHER: u8 = 0;
+const PANICKED: u8 = 1;
+const INIT_FAILED: u8 = 2;
+const NOT_A_PLUGIN: u8 = 3;
+const INVALID_SIGNATURE: u8 = 4;
+const INVALID_METADATA: u8 = 5;
+const MISSING_HOST_API: u8 = 6;
+const SIGNATURE_SCHEME_MISMATCH: u8 = 7;
+const CRATE_VERSION_MISMATCH: u8 = 8;
+const TARGET_MISMATCH: u8 = 9;
+const POINTER_WIDTH_MISMATCH: u8 = 10;
+const ENDIANNESS_MISMATCH: u8 = 11;
+const INTERIOR_NUL: u8 = 12;
+const SANDBOX_FAILED: u8 = 13;
+const SANDBOX_VIOLATION: u8 = 14;
+const INCOMPATIBLE_ABI: u8 = 15;
+const INVALID_UTF8: u8 = 16;
+
+/// Write an error from the worker. Errors which can't be rebuilt in the
+/// host are sent as their message.
+fn write_error(writer: &mut Writer, error: &Error) {
+    let strings = |writer: &mut Writer, kind: u8, a: &str, b: &str| {
+        writer.write_u8(kind);
+        writer.write_str(a);
+        writer.write_str(b);
+    };
+    let numbers = |writer: &mut Writer, kind: u8, a: u32, b: u32| {
+        writer.write_u8(kind);
+        writer.write_u32(a);
+        writer.write_u32(b);
+    };
+    match error {
+        Error::PluginPanicked { function, message } => strings(writer, PANICKED, function, message),
+        Error::InitFailed(error) => {
+            writer.write_u8(INIT_FAILED);
+            writer.write_plugin_error(error);
+        }
+        Error::NotAPlugin => writer.write_u8(NOT_A_PLUGIN),
+        Error::InvalidPluginSignature => writer.write_u8(INVALID_SIGNATURE),
+        Error::InvalidMetadata(message) => {
+            writer.write_u8(INVALID_METADATA);
+            writer.write_str(message);
+        }
+        Error::MissingHostApi => writer.write_u8(MISSING_HOST_API),
+        Error::IncompatibleAbi => writer.write_u8(INCOMPATIBLE_ABI),
+        Error::SignatureSchemeMismatch { expected, found } => {
+            numbers(writer, SIGNATURE_SCHEME_MISMATCH, *expected, *found);
+        }
+        Error::CrateVersionMismatch { expected, found } => {
+            strings(writer, CRATE_VERSION_MISMATCH, expected, found);
+        }
+        Error::TargetMismatch { expected, found } => {
+            strings(writer, TARGET_MISMATCH, expected, found);
+        }
+        Error::PointerWidthMismatch { expected, found } => {
+            numbers(writer, POINTER_WIDTH_MISMATCH, *expected, *found);
+        }
+        Error::EndiannessMismatch { expected, found } => {
+            strings(writer, ENDIANNESS_MISMATCH, expected, found);
+        }
+        Error::InteriorNul => writer.write_u8(INTERIOR_NUL),
+        Error::InvalidUtf8 => writer.write_u8(INVALID_UTF8),
+        Error::Sandbox(message) => {
+            writer.write_u8(SANDBOX_FAILED);
+            writer.write_str(message);
+        }
+        Error::SandboxViolation { syscall } => {
+            writer.write_u8(SANDBOX_VIOLATION);
+            writer.write_str(syscall.as_deref().unwrap_or_default());
+        }
+        error => {
+            writer.write_u8(OTHER);
+            writer.write_str(&error.to_string());
+        }
+    }
+}
+
+/// Read an error sent by the worker.
+fn read_error(reader: &mut Reader<'_>) -> Result<Error> {
+    Ok(match reader.read_u8()? {
+        PANICKED => Error::PluginPanicked {
+            function: reader.read_str()?,
+            message: reader.read_str()?,
+        },
+        INIT_FAILED => Error::InitFailed(reader.read_plugin_error()?),
+        NOT_A_PLUGIN => Error::NotAPlugin,
+        INVALID_SIGNATURE => Error::InvalidPluginSignature,
+        INVALID_METADATA => Error::InvalidMetadata(reader.read_str()?),
+        MISSING_HOST_API => Error::MissingHostApi,
+        INCOMPATIBLE_ABI => Error::IncompatibleAbi,
+        INVALID_UTF8 => Error::InvalidUtf8,
+        SIGNATURE_SCHEME_MISMATCH => Error::SignatureSchemeMismatch {
+            expected: reader.read_u32()?,
+            found: reader.read_u32()?,
+        },
+        CRATE_VERSION_MISMATCH => Error::CrateVersionMismatch {
+            expected: reader.read_str()?,
+            found: reader.read_str()?,
+        },
+        TARGET_MISMATCH => Error::TargetMismatch {
+            expected: reader.read_str()?,
+            found: reader.read_str()?,
+        },
+        POINTER_WIDTH_MISMATCH => Error::PointerWidthMismatch {
+            expected: reader.read_u32()?,
+            found: reader.read_u32()?,
+        },
+        ENDIANNESS_MISMATCH => Error::EndiannessMismatch {
+            expected: reader.read_str()?,
+            found: reader.read_str()?,
+        },
+        INTERIOR_NUL => Error::InteriorNul,
+        SANDBOX_FAILED => Error::Sandbox(reader.read_str()?),
+        SANDBOX_VIOLATION => Error::SandboxViolation {
+            syscall: Some(reader.read_str()?).filter(|syscall| !syscall.is_empty()),
+        },
+        _ => Error::Isolation(reader.read_str()?),
+    })
+}
+
+/// Reply to the host's current request with [`Error::SandboxViolation`],
+/// from the handler of the signal the sandbox sends when `syscall` is
+/// trapped, or an empty name if it isn't known. Nothing is allocated, as
+/// this runs in a signal handler.
+///
+/// Nothing is sent if no reply is owed, such as when a thread of the
+/// plugin's breaks the sandbox between requests. The host then finds
+/// the worker has crashed when it next sends a request.
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+pub(crate) fn report_violation(syscall: &'static str) {
+    if !REPLY_OWED.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let mut frame = [0; 64];
+    let name = &syscall.as_bytes()[..syscall.len().min(frame.len() - 14)];
+    // Not successful, the error, then the name of the system call
+    let len = 2 + 8 + name.len();
+    #[allow(clippy::cast_possible_truncation)]
+    frame[..4].copy_from_slice(&(len as u32).to_le_bytes());
+    frame[5] = SANDBOX_VIOLATION;
+    frame[6..14].copy_from_slice(&(name.len() as u64).to_le_bytes());
+    frame[14..14 + name.len()].copy_from_slice(name);
+    // Safety: the host gave the worker this end of the socket
+    unsafe { libc::write(WORKER_FD, frame.as_ptr().cast(), 4 + len) };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        os::unix::net::UnixStream,
+    };
+
+    use super::{receive, send, MAX_MESSAGE_LEN};
+
+    #[test]
+    fn sends_messages() {
+        let (mut host, mut worker) = UnixStream::pair().unwrap();
+        send(&mut host, b"request", None).unwrap();
+        assert_eq!(receive(&mut worker, None).unwrap(), b"request");
+        send(&mut worker, b"", None).unwrap();
+        assert_eq!(receive(&mut host, None).unwrap(), b"");
+    }
+
+    #[test]
+    fn rejects_long_messages() {
+        let (mut host, mut worker) = UnixStream::pair().unwrap();
+        let len = u32::try_from(MAX_MESSAGE_LEN + 1).unwrap();
+        worker.write_all(&len.to_le_bytes()).unwrap();
+        let error = receive(&mut host, None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
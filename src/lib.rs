@@ -2,6 +2,41 @@
 #![warn(clippy::pedantic)]
 #![doc = include_str!("../README.md")]
 
+#[doc(hidden)]
+pub mod __private;
+mod abi;
+#[cfg(feature = "tokio")]
+mod blocking;
+mod calls;
+mod dependencies;
+mod digest;
+mod discovery;
+mod embedded;
+mod ffi;
+mod future;
+mod header;
+mod host_api;
+#[cfg(unix)]
+mod isolation;
+mod library;
+#[cfg(unix)]
+mod limits;
+mod loader;
+#[cfg(unix)]
+mod location;
+mod metadata;
+mod opened;
+mod registry;
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+mod sandbox;
+#[cfg(feature = "signatures")]
+mod signatures;
+mod tasks;
+#[cfg(feature = "tracing")]
+mod trace;
+#[cfg(unix)]
+mod wire;
+
 // Re-export macros
 pub use dynamic_plugin_macros::*;
 pub use const_format::concatcp as const_concat;
@@ -12,6 +47,27 @@ pub use libloading::Symbol as PluginLibrarySymbol;
 
 /// Re-exported libc types for convenience.
 pub use libc;
+/// Re-exported semver types, used for plugin versions.
+pub use semver;
+/// Re-exported ed25519 types, used to check plugins are signed by trusted keys.
+#[cfg(feature = "signatures")]
+pub use ed25519_dalek;
+
+pub use digest::PluginDigest;
+pub use discovery::{DiscoveryReport, RejectedPlugin};
+pub use ffi::{PluginBuffer, PluginError, PluginResult, PluginStr, PluginString};
+pub use future::PluginFuture;
+pub use host_api::HostApi;
+#[cfg(unix)]
+pub use isolation::PluginWorker;
+pub use library::{PluginLibrary, StaticPlugin};
+#[cfg(unix)]
+pub use limits::ResourceLimits;
+pub use loader::PluginLoader;
+pub use metadata::{PluginDependency, PluginMetadata};
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+pub use sandbox::SandboxPolicy;
+pub use tasks::PluginTask;
 
 /// The result type returned by dynamic plugin functions.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -23,13 +79,237 @@ pub enum Error {
     #[error("An error while calling the plugin library: {0}")]
     DynamicLibrary(#[from] libloading::Error),
 
+    /// The plugin file could not be read.
+    #[error("The plugin file could not be read: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The loader has an allowlist of digests, and the plugin's digest is not in it.
+    #[error("The plugin's digest {digest} is not in the allowlist.")]
+    NotAllowed {
+        /// The plugin file's SHA-256 digest.
+        digest: PluginDigest,
+    },
+
+    /// A digest in an allowlist is not valid.
+    #[error("`{0}` is not a valid SHA-256 digest.")]
+    InvalidDigest(String),
+
+    /// The loader requires plugins to be signed, and the plugin is not signed by a trusted key.
+    #[error("The plugin is not signed by a trusted key.")]
+    Untrusted,
+
+    /// The loader requires plugins to be in secure locations, and the plugin file or a directory above it could be changed by another user.
+    #[error("`{}` is not a secure location for a plugin: {reason}.", .path.display())]
+    InsecurePluginLocation {
+        /// The plugin file or directory which is insecure.
+        path: std::path::PathBuf,
+        /// Why the location is insecure.
+        reason: String,
+    },
+
+    /// The file is not a shared library for the host's platform.
+    #[error("The file is not a shared library for this platform.")]
+    NotASharedLibrary,
+
+    /// The library was built for a different architecture to the host.
+    #[error("The library is for {found}, but the host is {expected}.")]
+    WrongArchitecture {
+        /// The host's architecture.
+        expected: String,
+        /// The library's architectures.
+        found: String,
+    },
+
     /// The discovered library is not a plugin, as in it does not expose the `_dynamic_plugin_signature` function.
     #[error("The discovered library is not a plugin.")]
     NotAPlugin,
 
+    /// A statically linked plugin does not have a function it was expected to.
+    #[error("The plugin does not have the function `{0}`.")]
+    MissingSymbol(String),
+
+    /// The plugin doesn't say how it was built, so can't be checked to
+    /// be compatible with the host.
+    #[error("The plugin doesn't say how it was built, so may be incompatible.")]
+    IncompatibleAbi,
+
+    /// The plugin's signature was calculated with a different scheme to the host's.
+    #[error("The plugin's signature was calculated with scheme {found}, but the host uses scheme {expected}.")]
+    SignatureSchemeMismatch {
+        /// The host's signature scheme.
+        expected: u32,
+        /// The plugin's signature scheme.
+        found: u32,
+    },
+
+    /// The plugin was built with an incompatible version of `dynamic-plugin`.
+    #[error("The plugin was built with dynamic-plugin {found}, which is incompatible with the host's {expected}.")]
+    CrateVersionMismatch {
+        /// The host's version of `dynamic-plugin`.
+        expected: String,
+        /// The plugin's version of `dynamic-plugin`.
+        found: String,
+    },
+
+    /// The plugin was built for a different target to the host.
+    #[error("The plugin was built for `{found}`, but the host is `{expected}`.")]
+    TargetMismatch {
+        /// The host's target triple.
+        expected: String,
+        /// The plugin's target triple.
+        found: String,
+    },
+
+    /// The plugin has a different pointer width to the host.
+    #[error("The plugin has {found}-bit pointers, but the host has {expected}-bit pointers.")]
+    PointerWidthMismatch {
+        /// The host's pointer width, in bits.
+        expected: u32,
+        /// The plugin's pointer width, in bits.
+        found: u32,
+    },
+
+    /// The plugin has a different endianness to the host.
+    #[error("The plugin is {found} endian, but the host is {expected} endian.")]
+    EndiannessMismatch {
+        /// The host's endianness.
+        expected: String,
+        /// The plugin's endianness.
+        found: String,
+    },
+
     /// The plugin's signature (i.e. name, function names, function arguments and function return types) does not match the expected value.
     #[error("The plugin's signature does not match.")]
     InvalidPluginSignature,
+
+    /// The plugin's metadata is not valid.
+    #[error("The plugin's metadata is not valid: {0}")]
+    InvalidMetadata(String),
+
+    /// The plugin was rejected by the loader's filter.
+    #[error("The plugin was rejected by the loader's filter.")]
+    Filtered,
+
+    /// The plugin depends on another plugin, which was not loaded.
+    #[error("The plugin `{plugin}` depends on `{dependency}` {requirement}, which is not loaded.")]
+    MissingDependency {
+        /// The name of the plugin with the dependency.
+        plugin: String,
+        /// The name of the plugin depended on.
+        dependency: String,
+        /// The versions of the plugin depended on which are compatible.
+        requirement: semver::VersionReq,
+    },
+
+    /// The plugin depends on itself, through the dependencies of other plugins.
+    #[error("The plugins depend on each other in a cycle: {}", .0.join(" -> "))]
+    DependencyCycle(Vec<String>),
+
+    /// A string passed to the plugin contains a nul byte, so cannot be converted to a C string.
+    #[error("A string passed to the plugin contains a nul byte.")]
+    InteriorNul,
+
+    /// A string passed across the plugin boundary is not valid UTF-8.
+    #[error("A string passed across the plugin boundary is not valid UTF-8.")]
+    InvalidUtf8,
+
+    /// The plugin panicked while running a function. The panic was caught inside the plugin.
+    #[error("The plugin panicked in `{function}`: {message}")]
+    PluginPanicked {
+        /// The name of the function which panicked.
+        function: String,
+        /// The panic message, including where in the plugin it occurred if known.
+        message: String,
+    },
+
+    /// The plugin's `on_load` hook reported an error.
+    #[error("The plugin failed to initialise: {0}")]
+    InitFailed(PluginError),
+
+    /// The plugin uses a host API, but none was provided to the loader.
+    #[error("The plugin uses a host API, but none was provided.")]
+    MissingHostApi,
+
+    /// The plugin uses a different host API to the one provided to the loader.
+    #[error("The plugin's host API signature does not match.")]
+    InvalidHostApiSignature,
+
+    /// A host API function panicked while being called by the plugin.
+    #[error("The host panicked in `{function}`: {message}")]
+    HostPanicked {
+        /// The name of the host function which panicked.
+        function: String,
+        /// The panic message, including where in the host it occurred if known.
+        message: String,
+    },
+
+    /// The worker process running an isolated plugin exited unexpectedly.
+    /// Every later call to the plugin returns this error.
+    #[error("The isolated plugin crashed: {0}")]
+    PluginCrashed(std::process::ExitStatus),
+
+    /// An isolated plugin could not be used, for example because a
+    /// function's arguments can't be sent to its worker process.
+    #[error("The isolated plugin could not be used: {0}")]
+    Isolation(String),
+
+    /// An isolated plugin didn't return from a function in time, so its
+    /// worker process was killed. Every later call to the plugin returns
+    /// this error.
+    #[error("The isolated plugin did not return from `{function}` within {timeout:?}.")]
+    Timeout {
+        /// The name of the function which timed out.
+        function: String,
+        /// How long the plugin had to return.
+        timeout: std::time::Duration,
+    },
+
+    /// An isolated plugin's sandbox could not be set up.
+    #[error("The plugin's sandbox could not be set up: {0}")]
+    Sandbox(String),
+
+    /// An isolated plugin made a system call its sandbox doesn't allow,
+    /// so its worker process was stopped. Every later call to the plugin
+    /// returns this error.
+    #[error(
+        "The isolated plugin was stopped for breaking its sandbox policy{}.",
+        .syscall.as_ref().map(|syscall| format!(" by calling `{syscall}`")).unwrap_or_default()
+    )]
+    SandboxViolation {
+        /// The name of the system call, if it is known.
+        syscall: Option<String>,
+    },
+
+    /// An isolated plugin's worker process used more of a resource than
+    /// its limit allows, so was stopped. Every later call to the plugin
+    /// returns this error.
+    #[error("The isolated plugin was stopped for using more {resource} than its limit allows.")]
+    ResourceLimitExceeded {
+        /// The resource, such as "memory" or "CPU time".
+        resource: String,
+    },
+
+    /// The host killed an isolated plugin's worker process. Every later
+    /// call to the plugin returns this error.
+    #[error("The isolated plugin's worker process was killed.")]
+    WorkerKilled,
+
+    /// A call run on tokio's blocking thread pool was cancelled before
+    /// it started, as the runtime shut down.
+    #[error("The call to `{function}` was cancelled, as the runtime shut down.")]
+    Cancelled {
+        /// The name of the function which was called.
+        function: String,
+    },
+
+    /// A plugin which runs one call at a time was called again from the
+    /// thread already calling it, such as by a host function it called,
+    /// which would deadlock.
+    #[error("`{function}` was called while the plugin was already running a call on this thread.")]
+    Reentrant {
+        /// The name of the function which was called.
+        function: String,
+    },
 }
 
 /// Statically assert an expression with an error message.
@@ -44,8 +324,6 @@ macro_rules! static_assert {
             if !($exp) {
                 core::panic!("{}", $msg);
             }
-
-            ()
         };
     };
 }
@@ -0,0 +1,160 @@
+//! Where a plugin's functions are found.
+
+use std::{
+    ffi::c_void,
+    marker::PhantomData,
+    mem,
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use crate::PluginWorker;
+use crate::{Error, PluginDigest, PluginDynamicLibrary, Result};
+
+/// The library a plugin's functions are found in: either a dynamic
+/// library loaded from disk, or a plugin linked statically into the host.
+pub enum PluginLibrary {
+    /// A plugin loaded from a dynamic library.
+    Dynamic {
+        /// The opened library.
+        library: PluginDynamicLibrary,
+        /// The path the library was opened from.
+        path: PathBuf,
+        /// The digest of the library file, if the loader calculated it.
+        digest: Option<PluginDigest>,
+    },
+    /// A plugin linked statically into the host.
+    Static(&'static [StaticSymbol]),
+    /// A plugin running in a separate worker process.
+    #[cfg(unix)]
+    Isolated {
+        /// The worker process.
+        worker: PluginWorker,
+        /// The path the library was opened from.
+        path: PathBuf,
+        /// The digest of the library file, if the loader calculated it.
+        digest: Option<PluginDigest>,
+    },
+}
+
+impl PluginLibrary {
+    /// Get the function called `name` from the plugin.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::DynamicLibrary`] if a dynamic library does not have the function.
+    /// - [`Error::MissingSymbol`] if a static plugin does not have the function.
+    /// - [`Error::Isolation`] if the plugin is isolated, as its functions are in another process.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the function pointer type of the function.
+    pub unsafe fn get<T: Copy>(&self, name: &[u8]) -> Result<T> {
+        match self {
+            Self::Dynamic { library, .. } => Ok(*library.get::<T>(name)?),
+            Self::Static(symbols) => {
+                debug_assert_eq!(mem::size_of::<T>(), mem::size_of::<*const c_void>());
+                symbols
+                    .iter()
+                    .find(|symbol| symbol.name.as_bytes() == name)
+                    .map(|symbol| mem::transmute_copy(&symbol.ptr))
+                    .ok_or_else(|| Error::MissingSymbol(String::from_utf8_lossy(name).into_owned()))
+            }
+            #[cfg(unix)]
+            Self::Isolated { .. } => Err(Error::Isolation(
+                "the plugin's functions are in its worker process".to_string(),
+            )),
+        }
+    }
+
+    /// The path the plugin was opened from, if it was loaded from a
+    /// dynamic library.
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Dynamic { path, .. } => Some(path),
+            #[cfg(unix)]
+            Self::Isolated { path, .. } => Some(path),
+            Self::Static(_) => None,
+        }
+    }
+
+    /// Whether the plugin can still be called. Isolated plugins are
+    /// unhealthy once their worker has crashed or timed out.
+    #[must_use]
+    pub fn is_healthy(&self) -> bool {
+        match self {
+            #[cfg(unix)]
+            Self::Isolated { worker, .. } => worker.is_healthy(),
+            _ => true,
+        }
+    }
+
+    /// Kill the worker process of an isolated plugin, such as when a call
+    /// hangs. Calls in flight and later calls return
+    /// [`Error::WorkerKilled`]. Plugins which aren't isolated can't be
+    /// killed, so this does nothing for them.
+    pub fn kill_worker(&self) {
+        #[cfg(unix)]
+        if let Self::Isolated { worker, .. } = self {
+            worker.kill();
+        }
+    }
+
+    /// The SHA-256 digest of the plugin's library file, if the loader
+    /// calculated it.
+    #[must_use]
+    pub fn digest(&self) -> Option<&PluginDigest> {
+        match self {
+            Self::Dynamic { digest, .. } => digest.as_ref(),
+            #[cfg(unix)]
+            Self::Isolated { digest, .. } => digest.as_ref(),
+            Self::Static(_) => None,
+        }
+    }
+}
+
+/// A plugin for the interface `P`, linked statically into the host.
+///
+/// `plugin_impl!` generates one of these named `STATIC_PLUGIN`. Pass it
+/// to the `from_static` function generated by `plugin_interface!` to use
+/// the plugin in the same way as one loaded from disk.
+pub struct StaticPlugin<P> {
+    symbols: &'static [StaticSymbol],
+    _plugin: PhantomData<fn() -> P>,
+}
+
+impl<P> StaticPlugin<P> {
+    #[doc(hidden)]
+    #[must_use]
+    pub const fn new(symbols: &'static [StaticSymbol]) -> Self {
+        Self {
+            symbols,
+            _plugin: PhantomData,
+        }
+    }
+
+    /// The plugin's functions, as a [`PluginLibrary`].
+    #[must_use]
+    pub fn library(&self) -> PluginLibrary {
+        PluginLibrary::Static(self.symbols)
+    }
+}
+
+/// A function exported by a statically linked plugin.
+#[doc(hidden)]
+pub struct StaticSymbol {
+    name: &'static str,
+    ptr: *const c_void,
+}
+
+impl StaticSymbol {
+    #[must_use]
+    pub const fn new(name: &'static str, ptr: *const c_void) -> Self {
+        Self { name, ptr }
+    }
+}
+
+// Safety: the pointer is to a function, which can be called from any thread
+unsafe impl Send for StaticSymbol {}
+unsafe impl Sync for StaticSymbol {}
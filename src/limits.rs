@@ -0,0 +1,193 @@
+//! Limiting the resources an isolated plugin's worker process can use.
+//!
+//! Limits are applied with `setrlimit` as the worker starts. A worker
+//! which uses too much CPU time is sent `SIGXCPU`. One which runs out of
+//! memory aborts when an allocation fails, so the worker catches the
+//! abort and exits with [`OUT_OF_MEMORY`] instead if the allocation
+//! failed for lack of memory. The host can tell from how the worker
+//! exited whether a limit stopped it, and any other exit is a crash.
+//!
+//! The abort is only recognised if nothing changes `errno` after the
+//! allocation fails, so workers whose memory is limited don't print
+//! backtraces, which would.
+
+use std::{
+    ffi::c_int,
+    io,
+    os::unix::process::ExitStatusExt,
+    process::{Command, ExitStatus},
+    time::Duration,
+};
+
+/// The status a worker whose memory is limited exits with when an
+/// allocation fails for lack of memory.
+const OUT_OF_MEMORY: c_int = 86;
+
+/// Limits on the resources each isolated plugin's worker process can
+/// use. Nothing is limited by default.
+///
+/// Give limits to
+/// [`PluginLoader::resource_limits`](crate::PluginLoader::resource_limits)
+/// to apply them to the worker of each plugin the loader opens.
+///
+/// ```ignore
+/// let limits = ResourceLimits::new()
+///     .memory(512 * 1024 * 1024)
+///     .cpu_time(Duration::from_secs(60))
+///     .open_files(64);
+/// let loader = PluginLoader::new().isolate(true).resource_limits(limits);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    memory: Option<u64>,
+    cpu_time: Option<Duration>,
+    open_files: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Create limits which don't limit anything.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the worker's address space to `bytes`. This includes the
+    /// host's own executable, which the worker runs, as well as the
+    /// plugin.
+    #[must_use]
+    pub fn memory(mut self, bytes: u64) -> Self {
+        self.memory = Some(bytes);
+        self
+    }
+
+    /// Limit the CPU time the worker can use over its whole life to
+    /// `time`, rounded up to whole seconds.
+    #[must_use]
+    pub fn cpu_time(mut self, time: Duration) -> Self {
+        self.cpu_time = Some(time);
+        self
+    }
+
+    /// Limit the number of files the worker can have open at once to
+    /// `count`. The worker uses a few itself. Opening more fails in the
+    /// plugin, rather than stopping it.
+    #[must_use]
+    pub fn open_files(mut self, count: u64) -> Self {
+        self.open_files = Some(count);
+        self
+    }
+
+    /// Prepare the `command` which starts the worker for the limits.
+    pub(crate) fn prepare(&self, command: &mut Command) {
+        if self.memory.is_some() {
+            command.env("RUST_BACKTRACE", "0");
+        }
+    }
+
+    /// Apply the limits to the current process. This runs in a newly
+    /// forked worker, so only calls async-signal-safe functions.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        let set = |resource, soft: u64, hard: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: soft,
+                rlim_max: hard,
+            };
+            // Safety: `limit` is a valid `rlimit`
+            if unsafe { libc::setrlimit(resource, std::ptr::from_ref(&limit)) } == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        };
+        if let Some(bytes) = self.memory {
+            set(libc::RLIMIT_AS, bytes, bytes)?;
+        }
+        if let Some(time) = self.cpu_time {
+            let seconds = time.as_secs() + u64::from(time.subsec_nanos() > 0);
+            // The worker is sent `SIGXCPU` at the soft limit, and killed
+            // at the hard limit if it handles that
+            set(libc::RLIMIT_CPU, seconds, seconds + 1)?;
+        }
+        if let Some(count) = self.open_files {
+            set(libc::RLIMIT_NOFILE, count, count)?;
+        }
+        Ok(())
+    }
+
+    /// The resource whose limit stopped a worker which exited with
+    /// `status`, if one did.
+    pub(crate) fn exceeded(&self, status: ExitStatus) -> Option<&'static str> {
+        if status.signal() == Some(libc::SIGXCPU) && self.cpu_time.is_some() {
+            Some("CPU time")
+        } else if status.code() == Some(OUT_OF_MEMORY) && self.memory.is_some() {
+            Some("memory")
+        } else {
+            None
+        }
+    }
+}
+
+/// Make the worker exit with [`OUT_OF_MEMORY`] when it aborts because an
+/// allocation failed, if its memory is limited. Otherwise running out of
+/// memory is reported as a crash.
+pub(crate) fn watch_memory() {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // Safety: `limit` is a valid `rlimit`
+    let limited = unsafe { libc::getrlimit(libc::RLIMIT_AS, std::ptr::from_mut(&mut limit)) } == 0
+        && limit.rlim_cur != libc::RLIM_INFINITY;
+    if !limited {
+        return;
+    }
+    // Safety: the handler only calls async-signal-safe functions
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_abort as *const () as libc::sighandler_t;
+        libc::sigaction(
+            libc::SIGABRT,
+            std::ptr::from_ref(&action),
+            std::ptr::null_mut(),
+        );
+    }
+}
+
+/// Handle `SIGABRT` in a worker whose memory is limited.
+extern "C" fn on_abort(_: c_int) {
+    // An allocation which fails leaves `ENOMEM` in `errno`, which
+    // printing the failure then aborting doesn't change
+    if io::Error::last_os_error().raw_os_error() == Some(libc::ENOMEM) {
+        // Safety: `_exit` is async-signal-safe
+        unsafe { libc::_exit(OUT_OF_MEMORY) };
+    }
+    // Abort as usual once the handler returns
+    // Safety: `signal` and `raise` are async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGABRT, libc::SIG_DFL);
+        libc::raise(libc::SIGABRT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_reports_limits_which_were_hit() {
+        let limits = ResourceLimits::new()
+            .memory(1024 * 1024 * 1024)
+            .cpu_time(Duration::from_secs(1));
+        let signalled = |signal| ExitStatus::from_raw(signal);
+        let exited = |code| ExitStatus::from_raw(code << 8);
+        assert_eq!(limits.exceeded(signalled(libc::SIGXCPU)), Some("CPU time"));
+        assert_eq!(limits.exceeded(exited(OUT_OF_MEMORY)), Some("memory"));
+        assert_eq!(limits.exceeded(signalled(libc::SIGABRT)), None);
+        assert_eq!(limits.exceeded(signalled(libc::SIGKILL)), None);
+        assert_eq!(limits.exceeded(exited(1)), None);
+
+        let unlimited = ResourceLimits::new();
+        assert_eq!(unlimited.exceeded(signalled(libc::SIGXCPU)), None);
+        assert_eq!(unlimited.exceeded(exited(OUT_OF_MEMORY)), None);
+    }
+}
@@ -0,0 +1,681 @@
+//! Options for loading plugins.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{c_void, OsStr},
+    fmt,
+    fs::File,
+    io::{Read, Seek},
+    mem::MaybeUninit,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    __private::{self, AbiDescriptor},
+    dependencies::{self, Ordered},
+    digest, embedded, header, opened, registry, DiscoveryReport, Error, HostApi, PluginDigest,
+    PluginDynamicLibrary, PluginError, PluginLibrary, PluginLibrarySymbol, PluginMetadata,
+    RejectedPlugin, Result,
+};
+
+type Filter = dyn Fn(&PluginMetadata) -> bool + Send + Sync;
+
+/// Options controlling how plugins are loaded.
+///
+/// The `load_plugin_with` and `find_plugins_with` functions generated by
+/// `plugin_interface!` take a loader.
+///
+/// ```ignore
+/// let loader = PluginLoader::new().host_api(ExampleHost::host_api(MyHost));
+/// let plugin = ExamplePlugin::load_plugin_with("libexample_plugin.so", &loader)?;
+/// ```
+#[derive(Clone)]
+pub struct PluginLoader {
+    check_signature: bool,
+    host_api: Option<HostApi>,
+    filter: Option<Arc<Filter>>,
+    allowlist: Option<Arc<HashSet<PluginDigest>>>,
+    #[cfg(feature = "signatures")]
+    trusted_keys: Vec<ed25519_dalek::VerifyingKey>,
+    #[cfg(unix)]
+    require_secure_location: bool,
+    #[cfg(unix)]
+    isolate: bool,
+    #[cfg(unix)]
+    call_timeout: Option<Duration>,
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    sandbox: Option<crate::SandboxPolicy>,
+    #[cfg(unix)]
+    resource_limits: Option<crate::ResourceLimits>,
+    /// Limits for the plugins with these names, instead of `resource_limits`.
+    #[cfg(unix)]
+    plugin_resource_limits: HashMap<String, crate::ResourceLimits>,
+}
+
+impl Default for PluginLoader {
+    fn default() -> Self {
+        Self {
+            check_signature: true,
+            host_api: None,
+            filter: None,
+            allowlist: None,
+            #[cfg(feature = "signatures")]
+            trusted_keys: vec![],
+            #[cfg(unix)]
+            require_secure_location: false,
+            #[cfg(unix)]
+            isolate: false,
+            #[cfg(unix)]
+            call_timeout: None,
+            #[cfg(all(target_os = "linux", feature = "sandbox"))]
+            sandbox: None,
+            #[cfg(unix)]
+            resource_limits: None,
+            #[cfg(unix)]
+            plugin_resource_limits: HashMap::new(),
+        }
+    }
+}
+
+impl fmt::Debug for PluginLoader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("PluginLoader");
+        debug
+            .field("check_signature", &self.check_signature)
+            .field("host_api", &self.host_api)
+            .field("filter", &self.filter.is_some())
+            .field("allowlist", &self.allowlist);
+        #[cfg(feature = "signatures")]
+        debug.field("trusted_keys", &self.trusted_keys);
+        #[cfg(unix)]
+        debug
+            .field("require_secure_location", &self.require_secure_location)
+            .field("isolate", &self.isolate)
+            .field("call_timeout", &self.call_timeout)
+            .field("resource_limits", &self.resource_limits)
+            .field("plugin_resource_limits", &self.plugin_resource_limits);
+        #[cfg(all(target_os = "linux", feature = "sandbox"))]
+        debug.field("sandbox", &self.sandbox);
+        debug.finish()
+    }
+}
+
+impl PluginLoader {
+    /// Create a loader with the default options.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether to check the plugin's signature matches the
+    /// interface. This is enabled by default.
+    #[must_use]
+    pub fn check_signature(mut self, check_signature: bool) -> Self {
+        self.check_signature = check_signature;
+        self
+    }
+
+    /// Provide the host API to plugins that use one.
+    #[must_use]
+    pub fn host_api(mut self, host_api: HostApi) -> Self {
+        self.host_api = Some(host_api);
+        self
+    }
+
+    /// Only load plugins whose metadata passes `filter`. Plugins which
+    /// don't export any metadata are not loaded.
+    #[must_use]
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&PluginMetadata) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Only load plugins whose SHA-256 digest is one of `digests`, or
+    /// in another allowlist given to this loader. The digest of each
+    /// loaded plugin is available from its `digest` method.
+    #[must_use]
+    pub fn allow_digests<I>(mut self, digests: I) -> Self
+    where
+        I: IntoIterator<Item = PluginDigest>,
+    {
+        let mut allowlist = self
+            .allowlist
+            .take()
+            .map(Arc::unwrap_or_clone)
+            .unwrap_or_default();
+        allowlist.extend(digests);
+        self.allowlist = Some(Arc::new(allowlist));
+        self
+    }
+
+    /// Only load plugins whose SHA-256 digest is listed in the file at
+    /// `path`, or in another allowlist given to this loader.
+    ///
+    /// Each line of the file holds a digest in hexadecimal, optionally
+    /// followed by whitespace and the file name, as written by
+    /// `sha256sum`. Blank lines and lines starting with `#` are ignored.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Io`] if the file cannot be read.
+    /// - [`Error::InvalidDigest`] if a line of the file does not start with a valid digest.
+    pub fn allowlist_file<P: AsRef<Path>>(self, path: P) -> Result<Self> {
+        Ok(self.allow_digests(digest::read_allowlist(path.as_ref())?))
+    }
+
+    /// Only load plugins signed by `key`, or another trusted key. Each
+    /// plugin's ed25519 signature over the library file is read from a
+    /// file next to it, with `.sig` added to its name.
+    ///
+    /// The file is checked before it is opened, so it must not be
+    /// writable by anyone who shouldn't be able to change it.
+    #[cfg(feature = "signatures")]
+    #[must_use]
+    pub fn trusted_key(mut self, key: ed25519_dalek::VerifyingKey) -> Self {
+        self.trusted_keys.push(key);
+        self
+    }
+
+    /// Set whether to only load plugins from secure locations. This is
+    /// disabled by default.
+    ///
+    /// When enabled, the plugin file and every directory above it must be
+    /// owned by the host's user or root, and must not be writable by
+    /// their group or other users. Directories with the sticky bit set
+    /// which are owned by root, such as `/tmp`, are allowed.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn require_secure_location(mut self, require_secure_location: bool) -> Self {
+        self.require_secure_location = require_secure_location;
+        self
+    }
+
+    /// Set whether to run each plugin in its own worker process. This is
+    /// disabled by default.
+    ///
+    /// Calls to an isolated plugin are sent to its worker, so a plugin
+    /// which crashes returns [`Error::PluginCrashed`] rather than taking
+    /// down the host. The worker runs the host's own executable, which
+    /// must call the `run_isolated_worker` function generated for the
+    /// interface at the start of `main`.
+    ///
+    /// Isolated plugins can't use a host API, and functions which take or
+    /// return pointers can't be called.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn isolate(mut self, isolate: bool) -> Self {
+        self.isolate = isolate;
+        self
+    }
+
+    /// Set how long isolated plugins have to return from each call,
+    /// including opening the plugin and its `on_load` hook. Functions
+    /// given a `#[timeout]` in the plugin interface, or whose interface
+    /// is given one, use that instead.
+    ///
+    /// A plugin which takes longer has its worker killed, and returns
+    /// [`Error::Timeout`] from that call and every later one. Only
+    /// isolated plugins can be stopped safely, so opening a plugin with
+    /// a timeout fails unless the loader isolates plugins.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn call_timeout(mut self, timeout: Duration) -> Self {
+        self.call_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit the resources each isolated plugin's worker can use. The
+    /// limits are applied as each worker starts, so opening a plugin
+    /// with limits fails unless the loader isolates plugins.
+    ///
+    /// A plugin which is stopped for using too much memory or CPU time
+    /// returns [`Error::ResourceLimitExceeded`] from that call and every
+    /// later one.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn resource_limits(mut self, limits: crate::ResourceLimits) -> Self {
+        self.resource_limits = Some(limits);
+        self
+    }
+
+    /// Limit the resources the worker of the plugin called `name` can
+    /// use, instead of the limits given to
+    /// [`resource_limits`](Self::resource_limits). The name is the one in
+    /// the metadata stored in the plugin's file, which is read before the
+    /// worker starts.
+    #[cfg(unix)]
+    #[must_use]
+    pub fn plugin_resource_limits<S: Into<String>>(
+        mut self,
+        name: S,
+        limits: crate::ResourceLimits,
+    ) -> Self {
+        self.plugin_resource_limits.insert(name.into(), limits);
+        self
+    }
+
+    /// Restrict what isolated plugins can do to `policy`. The policy is
+    /// applied to each plugin's worker before the plugin is opened, so
+    /// opening a plugin with a sandbox fails unless the loader isolates
+    /// plugins.
+    ///
+    /// A plugin which makes a system call the policy denies is stopped,
+    /// and returns [`Error::SandboxViolation`] from that call and every
+    /// later one.
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    #[must_use]
+    pub fn sandbox(mut self, policy: crate::SandboxPolicy) -> Self {
+        self.sandbox = Some(policy);
+        self
+    }
+
+    /// The host API given to plugins, if any.
+    #[must_use]
+    pub fn get_host_api(&self) -> Option<&HostApi> {
+        self.host_api.as_ref()
+    }
+
+    /// Open the library at `path`, checking that it is a plugin with
+    /// the given `signature`. When signatures are checked, the toolchain
+    /// and ABI the plugin was built with are also checked to match the
+    /// host's. If the loader isolates plugins, the library is opened in
+    /// a new worker process.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Io`] if the file cannot be read, or a worker cannot be started.
+    /// - [`Error::InsecurePluginLocation`] if the loader requires secure locations and the file is not in one.
+    /// - [`Error::NotASharedLibrary`] if the file is not a shared library for the host's platform.
+    /// - [`Error::WrongArchitecture`] if the library is for a different architecture.
+    /// - [`Error::PointerWidthMismatch`] or [`Error::EndiannessMismatch`] if the library has a different pointer width or endianness.
+    /// - [`Error::Untrusted`] if the loader has trusted keys and the library is not signed by one.
+    /// - [`Error::NotAllowed`] if the loader has an allowlist and the library's digest is not in it.
+    /// - [`Error::DynamicLibrary`] if the library cannot be opened.
+    /// - [`Error::InvalidMetadata`] if plugins have resource limits of their own and the metadata stored in the file is not valid.
+    /// - [`Error::NotAPlugin`] if the library is not a plugin.
+    /// - [`Error::IncompatibleAbi`] if signatures are checked and the plugin doesn't say how it was built.
+    /// - [`Error::SignatureSchemeMismatch`], [`Error::CrateVersionMismatch`],
+    ///   [`Error::TargetMismatch`], [`Error::PointerWidthMismatch`] or
+    ///   [`Error::EndiannessMismatch`] if signatures are checked and the
+    ///   plugin was built differently to the host.
+    /// - [`Error::InvalidPluginSignature`] if signatures are checked and do not match.
+    /// - [`Error::Isolation`] if the loader has a call timeout, resource limits or sandbox but doesn't isolate plugins.
+    /// - [`Error::Sandbox`] if the plugin is isolated and its sandbox can't be set up.
+    /// - [`Error::PluginCrashed`] if the plugin is isolated and its worker crashes.
+    /// - [`Error::Timeout`] if the plugin is isolated and takes longer than the call timeout to open.
+    pub fn open<P>(&self, path: P, signature: u64) -> Result<PluginLibrary>
+    where
+        P: AsRef<OsStr>,
+    {
+        let path = Path::new(path.as_ref());
+        #[cfg_attr(not(unix), allow(unused_mut))]
+        let (mut file, digest) = self.check_file(path)?;
+
+        #[cfg(unix)]
+        if self.call_timeout.is_some() && !self.isolate {
+            return Err(Error::Isolation(
+                "call timeouts need the loader to isolate plugins".to_string(),
+            ));
+        }
+        #[cfg(unix)]
+        if (self.resource_limits.is_some() || !self.plugin_resource_limits.is_empty())
+            && !self.isolate
+        {
+            return Err(Error::Isolation(
+                "resource limits need the loader to isolate plugins".to_string(),
+            ));
+        }
+        #[cfg(all(target_os = "linux", feature = "sandbox"))]
+        if self.sandbox.is_some() && !self.isolate {
+            return Err(Error::Isolation(
+                "sandboxes need the loader to isolate plugins".to_string(),
+            ));
+        }
+        #[cfg(unix)]
+        if self.isolate {
+            let limits = self.resource_limits_for(&mut file)?;
+            let worker = crate::PluginWorker::spawn(signature, self.call_timeout, limits, &file)?;
+            #[cfg(all(target_os = "linux", feature = "sandbox"))]
+            if let Some(policy) = &self.sandbox {
+                worker.sandbox(policy, path)?;
+            }
+            worker.open(path, self.check_signature, signature)?;
+            return Ok(PluginLibrary::Isolated {
+                worker,
+                path: path.to_path_buf(),
+                digest,
+            });
+        }
+
+        Ok(PluginLibrary::Dynamic {
+            library: self.open_library(path, &file, signature)?,
+            path: path.to_path_buf(),
+            digest,
+        })
+    }
+
+    /// Open the file at `path` and check it before it is loaded,
+    /// returning it with its digest if the loader has an allowlist.
+    /// Every check reads the open file, so the plugin must be loaded
+    /// from it too.
+    fn check_file(&self, path: &Path) -> Result<(File, Option<PluginDigest>)> {
+        let mut file = File::open(path)?;
+        #[cfg(unix)]
+        if self.require_secure_location {
+            crate::location::check(path, &file)?;
+        }
+        // Check the file's header before handing it to the dynamic loader
+        header::check(&file)?;
+
+        #[cfg(feature = "signatures")]
+        let signed = !self.trusted_keys.is_empty();
+        #[cfg(not(feature = "signatures"))]
+        let signed = false;
+        if !signed && self.allowlist.is_none() {
+            return Ok((file, None));
+        }
+        let mut contents = Vec::new();
+        file.rewind()?;
+        file.read_to_end(&mut contents)?;
+        #[cfg(feature = "signatures")]
+        if signed {
+            crate::signatures::check(path, &contents, &self.trusted_keys)?;
+        }
+        if let Some(allowlist) = &self.allowlist {
+            let digest = PluginDigest::of_bytes(&contents);
+            if !allowlist.contains(&digest) {
+                return Err(Error::NotAllowed { digest });
+            }
+            Ok((file, Some(digest)))
+        } else {
+            Ok((file, None))
+        }
+    }
+
+    /// The limits for the worker of the plugin `file`, found by the name
+    /// in its stored metadata if any plugins have limits of their own.
+    #[cfg(unix)]
+    fn resource_limits_for(&self, file: &mut File) -> Result<crate::ResourceLimits> {
+        let default = self.resource_limits.unwrap_or_default();
+        if self.plugin_resource_limits.is_empty() {
+            return Ok(default);
+        }
+        let limits = embedded::read(file)?
+            .and_then(|metadata| self.plugin_resource_limits.get(&metadata.name).copied());
+        Ok(limits.unwrap_or(default))
+    }
+
+    /// Open the library `file`, which was opened from `path`, in this
+    /// process, checking that it is a plugin with the given `signature`.
+    pub(crate) fn open_library(
+        &self,
+        path: &Path,
+        file: &File,
+        signature: u64,
+    ) -> Result<PluginDynamicLibrary> {
+        unsafe {
+            // Attempt to load library
+            let library = opened::load(path, file)?;
+
+            // Check that signature function exists
+            let func: PluginLibrarySymbol<unsafe extern "C" fn() -> u64> = library
+                .get(b"_dynamic_plugin_signature")
+                .map_err(|_| Error::NotAPlugin)?;
+            if self.check_signature {
+                // Check the plugin was built compatibly
+                let abi: PluginLibrarySymbol<unsafe extern "C" fn() -> *const AbiDescriptor> =
+                    library
+                        .get(b"_dynamic_plugin_abi")
+                        .map_err(|_| Error::IncompatibleAbi)?;
+                (*abi()).check()?;
+
+                // Check plugin library signature
+                if func() != signature {
+                    return Err(Error::InvalidPluginSignature);
+                }
+            }
+
+            Ok(library)
+        }
+    }
+
+    /// Prepare an opened plugin `library` to be used. Its metadata is
+    /// read and checked against the filter, and the plugins it depends on
+    /// are checked to be loaded. Then, unless another plugin is already
+    /// using the library, it is given the host API if it uses
+    /// one, and its `on_load` hook is called if it has one. Returns the
+    /// plugin's metadata, if it exports any.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::InvalidMetadata`] if the plugin's metadata is not valid.
+    /// - [`Error::Filtered`] if the plugin is rejected by the filter.
+    /// - [`Error::MissingDependency`] if a plugin it depends on is not loaded.
+    /// - [`Error::MissingHostApi`] if the plugin uses a host API but none was provided.
+    /// - [`Error::InvalidHostApiSignature`] if the plugin uses a different host API.
+    /// - [`Error::InitFailed`] if the plugin's `on_load` hook fails.
+    /// - [`Error::PluginPanicked`] if the plugin's `on_load` hook panics.
+    /// - [`Error::InvalidUtf8`] if the plugin's `on_load` hook fails with a message which isn't valid UTF-8.
+    /// - [`Error::Isolation`] if the plugin is isolated and uses a host API.
+    /// - [`Error::PluginCrashed`] if the plugin is isolated and its worker crashes.
+    /// - [`Error::Timeout`] if the plugin is isolated and its `on_load` hook takes longer than the call timeout.
+    pub fn initialise(&self, library: &PluginLibrary) -> Result<Option<PluginMetadata>> {
+        let metadata = Self::metadata(library)?;
+        self.check_filter(metadata.as_ref())?;
+        if let Some(metadata) = &metadata {
+            dependencies::check(metadata, &registry::loaded())?;
+        }
+
+        #[cfg(unix)]
+        if let PluginLibrary::Isolated { worker, .. } = library {
+            // The worker has no host API to give the plugin
+            let prepare = || match worker.initialise() {
+                Err(Error::MissingHostApi) if self.host_api.is_some() => Err(Error::Isolation(
+                    "plugins which use a host API can't be isolated".to_string(),
+                )),
+                result => result.map(|()| None),
+            };
+            return registry::acquire(library, metadata.as_ref(), prepare, |_| Ok(()))
+                .map(|()| metadata);
+        }
+
+        registry::acquire(
+            library,
+            metadata.as_ref(),
+            // Safety: the library was opened as a plugin
+            || unsafe { self.prepare(library) },
+            |host_api| self.join(host_api),
+        )?;
+        Ok(metadata)
+    }
+
+    /// Prepare `library` for its first plugin, giving it the host API if
+    /// it uses one, then calling its `on_load` hook if it has one. Returns
+    /// the host API it was given.
+    ///
+    /// # Safety
+    ///
+    /// `library` must be a plugin, exposing `_dynamic_plugin_set_host_api`
+    /// and `_dynamic_plugin_on_load` if it exposes anything by those names.
+    unsafe fn prepare(&self, library: &PluginLibrary) -> Result<Option<HostApi>> {
+        let set_host_api = library
+            .get::<unsafe extern "C" fn(*const c_void) -> bool>(b"_dynamic_plugin_set_host_api");
+        // Plugins without this function don't use a host API
+        let host_api = if let Ok(set_host_api) = set_host_api {
+            let host_api = self.host_api.as_ref().ok_or(Error::MissingHostApi)?;
+            if !set_host_api(host_api.vtable()) {
+                return Err(Error::InvalidHostApiSignature);
+            }
+            Some(host_api.clone())
+        } else {
+            None
+        };
+
+        let on_load = library
+            .get::<unsafe extern "C" fn(*mut PluginError) -> bool>(b"_dynamic_plugin_on_load");
+        if let Ok(on_load) = on_load {
+            let mut error = MaybeUninit::uninit();
+            let loaded = on_load(error.as_mut_ptr());
+            let result = __private::check_panic(library, "on_load").and_then(|()| {
+                if loaded {
+                    Ok(())
+                } else {
+                    let error = error.assume_init();
+                    error.check()?;
+                    Err(Error::InitFailed(error))
+                }
+            });
+            if let Err(error) = result {
+                // The host API is dropped, so the plugin can't keep it
+                __private::clear_host_api(library);
+                return Err(error);
+            }
+        }
+        Ok(host_api)
+    }
+
+    /// Check that a plugin can use a library which is already in use,
+    /// and was given `host_api` by the plugin which first used it. The
+    /// library keeps that host API, so this loader's must be for the same
+    /// host interface.
+    fn join(&self, host_api: Option<&HostApi>) -> Result<()> {
+        let Some(host_api) = host_api else {
+            return Ok(());
+        };
+        match &self.host_api {
+            None => Err(Error::MissingHostApi),
+            Some(ours) if ours.signature() != host_api.signature() => {
+                Err(Error::InvalidHostApiSignature)
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Check a plugin's `metadata` against the filter, if the loader has
+    /// one. Plugins without metadata are rejected by any filter.
+    fn check_filter(&self, metadata: Option<&PluginMetadata>) -> Result<()> {
+        match &self.filter {
+            Some(filter) if !metadata.is_some_and(|metadata| filter(metadata)) => {
+                Err(Error::Filtered)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Load the plugins at `paths` with the given `signature`, each after
+    /// the plugins it depends on, adding them to `report`. Each plugin's
+    /// metadata is read from its file before any are loaded, so plugins
+    /// rejected by the filter or by their dependencies are never opened.
+    /// Each initialised library and its metadata is turned into a plugin
+    /// with `plugin`.
+    ///
+    /// Plugins are rejected with [`Error::MissingDependency`] if one of
+    /// their dependencies was not loaded, or [`Error::DependencyCycle`] if
+    /// they depend on themselves through other plugins.
+    pub fn load_in_order<P, F>(
+        &self,
+        paths: Vec<PathBuf>,
+        signature: u64,
+        report: &mut DiscoveryReport<P>,
+        mut plugin: F,
+    ) where
+        F: FnMut(PluginLibrary, Option<PluginMetadata>) -> P,
+    {
+        let mut candidates = vec![];
+        let mut metadata = vec![];
+        for path in paths {
+            let read =
+                Self::read_metadata(&path).and_then(|m| self.check_filter(m.as_ref()).map(|()| m));
+            match read {
+                Ok(m) => {
+                    candidates.push(Some(path));
+                    metadata.push(m);
+                }
+                Err(error) => report.rejected.push(RejectedPlugin { path, error }),
+            }
+        }
+
+        for ordered in dependencies::load_order(&metadata) {
+            let (idx, cycle) = match ordered {
+                Ordered::Load(idx) => (idx, None),
+                Ordered::Cycle(idx, cycle) => (idx, Some(cycle)),
+            };
+            let Some(path) = candidates[idx].take() else {
+                continue;
+            };
+            let result = if let Some(cycle) = cycle {
+                Err(Error::DependencyCycle(cycle))
+            } else {
+                // Dependencies are checked against the plugins loaded
+                // so far when the plugin is initialised
+                self.open(&path, signature).and_then(|library| {
+                    let metadata = self.initialise(&library)?;
+                    Ok(plugin(library, metadata))
+                })
+            };
+            match result {
+                Ok(loaded) => report.loaded.push(loaded),
+                Err(error) => report.rejected.push(RejectedPlugin { path, error }),
+            }
+        }
+    }
+
+    /// Read the metadata of the plugin at `path` from its file, without
+    /// loading it.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Io`] if the file cannot be read.
+    /// - [`Error::NotASharedLibrary`] if the file is not a shared library for the host's platform.
+    /// - [`Error::WrongArchitecture`] if the library is for a different architecture.
+    /// - [`Error::InvalidMetadata`] if the plugin's metadata is not valid.
+    pub fn read_metadata<P: AsRef<OsStr>>(path: P) -> Result<Option<PluginMetadata>> {
+        let mut file = File::open(path.as_ref())?;
+        header::check(&file)?;
+        embedded::read(&mut file)
+    }
+
+    /// Read the metadata exported by the plugin `library`, if any.
+    pub(crate) fn metadata(library: &PluginLibrary) -> Result<Option<PluginMetadata>> {
+        #[cfg(unix)]
+        if let PluginLibrary::Isolated { worker, .. } = library {
+            return worker.metadata();
+        }
+        unsafe {
+            let Ok(metadata) =
+                library.get::<unsafe extern "C" fn() -> *const u8>(b"_dynamic_plugin_metadata")
+            else {
+                return Ok(None);
+            };
+            PluginMetadata::from_raw(metadata()).map(Some)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::StaticSymbol;
+
+    unsafe extern "C" fn fail_to_load(error: *mut PluginError) -> bool {
+        error.write(crate::ffi::tests::invalid_error());
+        false
+    }
+
+    static FAILS_TO_LOAD: [StaticSymbol; 1] = [StaticSymbol::new(
+        "_dynamic_plugin_on_load",
+        fail_to_load as *const c_void,
+    )];
+
+    #[test]
+    fn rejects_invalid_utf8_in_on_load_errors() {
+        let library = PluginLibrary::Static(&FAILS_TO_LOAD);
+        let result = PluginLoader::new().initialise(&library);
+        assert!(matches!(result, Err(Error::InvalidUtf8)));
+    }
+}
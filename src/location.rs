@@ -0,0 +1,245 @@
+//! Checking plugin files are in locations only trusted users can change.
+//!
+//! A library which another user can replace, or which sits in a
+//! directory another user can write to, lets that user run code as the
+//! host. The file and every directory above it must be owned by the
+//! host's user or root, and not be writable by anyone else.
+
+use std::{
+    env,
+    ffi::OsString,
+    fs::{self, File, Metadata},
+    io,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{Error, Result};
+
+/// The permission bits allowing the group and others to write.
+const GROUP_OR_OTHER_WRITE: u32 = 0o022;
+
+/// The permission bit only allowing owners to remove or rename entries
+/// in a directory.
+const STICKY: u32 = 0o1000;
+
+/// The most symbolic links followed in a path, as on Linux.
+const MAX_LINKS: usize = 40;
+
+/// Check that `file`, opened from `path`, and every directory above it,
+/// can only be changed by the host's user or root.
+///
+/// The path is walked one component at a time. Each symbolic link met
+/// is followed through its target, so the directories the target leads
+/// through are checked as well as the one the link is in. The path must
+/// end at the open file.
+///
+/// # Errors
+///
+/// - [`Error::Io`] if the file or a directory above it cannot be read, or the path has too many links.
+/// - [`Error::InsecurePluginLocation`] if the file or a directory above it could be changed by another user.
+pub(crate) fn check(path: &Path, file: &File) -> Result<()> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir()?.join(path)
+    };
+    // The components left to walk, with the next one last
+    let mut remaining = components(&path);
+    // The directory reached so far, which has no links in it
+    let mut directory = PathBuf::new();
+    let mut links = 0;
+
+    while let Some(name) = remaining.pop() {
+        match Path::new(&name).components().next() {
+            Some(Component::RootDir) => {
+                directory = PathBuf::from("/");
+                check_directory(&directory, &fs::metadata(&directory)?)?;
+                continue;
+            }
+            Some(Component::ParentDir) => {
+                // The parent was checked on the way down
+                directory.pop();
+                continue;
+            }
+            Some(Component::Normal(_)) => {}
+            _ => continue,
+        }
+        let next = directory.join(name);
+        let metadata = fs::symlink_metadata(&next)?;
+        if metadata.is_symlink() {
+            links += 1;
+            if links > MAX_LINKS {
+                return Err(io::Error::from_raw_os_error(libc::ELOOP).into());
+            }
+            // The link is in a directory which has been checked, so
+            // only where it leads is left to check
+            let target = fs::read_link(&next)?;
+            remaining.extend(components(&target));
+        } else if remaining.is_empty() {
+            return check_file(&next, &metadata, file);
+        } else {
+            check_directory(&next, &metadata)?;
+            directory = next;
+        }
+    }
+    Err(insecure(&path, "it is not a regular file".to_string()))
+}
+
+/// Check that `file`, opened from `path` whose metadata is `found`, can
+/// only be changed by the host's user or root.
+fn check_file(path: &Path, found: &Metadata, file: &File) -> Result<()> {
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(insecure(path, "it is not a regular file".to_string()));
+    }
+    check_owner(&metadata).map_err(|reason| insecure(path, reason))?;
+    if metadata.permissions().mode() & GROUP_OR_OTHER_WRITE != 0 {
+        return Err(insecure(
+            path,
+            "it is writable by its group or other users".to_string(),
+        ));
+    }
+    if (found.dev(), found.ino()) != (metadata.dev(), metadata.ino()) {
+        return Err(insecure(
+            path,
+            "it was replaced while being opened".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that `directory`, whose metadata is `metadata`, can only be
+/// changed by the host's user or root.
+fn check_directory(directory: &Path, metadata: &Metadata) -> Result<()> {
+    if !metadata.is_dir() {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR).into());
+    }
+    check_owner(metadata).map_err(|reason| insecure(directory, reason))?;
+
+    // Shared directories such as `/tmp` are safe if only the owners
+    // of entries can replace them
+    let mode = metadata.permissions().mode();
+    let shared = mode & STICKY != 0 && metadata.uid() == 0;
+    if mode & GROUP_OR_OTHER_WRITE != 0 && !shared {
+        return Err(insecure(
+            directory,
+            "the directory is writable by its group or other users".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The error for a file or directory which another user could change.
+fn insecure(path: &Path, reason: String) -> Error {
+    Error::InsecurePluginLocation {
+        path: path.to_path_buf(),
+        reason,
+    }
+}
+
+/// The components of `path`, last first.
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .map(|component| component.as_os_str().to_os_string())
+        .collect()
+}
+
+/// Check that a file is owned by the host's user or root.
+fn check_owner(metadata: &Metadata) -> std::result::Result<(), String> {
+    // Safety: `geteuid` is always successful
+    let host_uid = unsafe { libc::geteuid() };
+    let uid = metadata.uid();
+    if uid == 0 || uid == host_uid {
+        Ok(())
+    } else {
+        Err(format!("it is owned by user {uid}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File, Permissions},
+        os::unix::fs::{symlink, PermissionsExt},
+        path::Path,
+    };
+
+    use super::check;
+    use crate::Error;
+
+    /// Check the file at `path`, giving the path reported as insecure.
+    fn insecure_path(path: &Path) -> Option<String> {
+        match check(path, &File::open(path).unwrap()) {
+            Ok(()) => None,
+            Err(Error::InsecurePluginLocation { path, .. }) => {
+                Some(path.file_name().unwrap().to_string_lossy().into_owned())
+            }
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    #[test]
+    fn checks_every_directory() {
+        let dir =
+            std::env::temp_dir().join(format!("dynamic-plugin-location-{}", std::process::id()));
+        let secure = dir.join("secure");
+        let shared = dir.join("shared");
+        fs::create_dir_all(&secure).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+        fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&secure, Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&shared, Permissions::from_mode(0o777)).unwrap();
+        for directory in [&secure, &shared] {
+            fs::write(directory.join("libplugin.so"), "plugin").unwrap();
+            fs::set_permissions(
+                directory.join("libplugin.so"),
+                Permissions::from_mode(0o644),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(insecure_path(&secure.join("libplugin.so")), None);
+        assert_eq!(
+            insecure_path(&shared.join("libplugin.so")).as_deref(),
+            Some("shared")
+        );
+        fs::set_permissions(secure.join("libplugin.so"), Permissions::from_mode(0o664)).unwrap();
+        assert_eq!(
+            insecure_path(&secure.join("libplugin.so")).as_deref(),
+            Some("libplugin.so")
+        );
+        fs::set_permissions(secure.join("libplugin.so"), Permissions::from_mode(0o644)).unwrap();
+
+        // Links are checked where they are and where they lead
+        symlink("../secure/libplugin.so", shared.join("secure.so")).unwrap();
+        assert_eq!(
+            insecure_path(&shared.join("secure.so")).as_deref(),
+            Some("shared")
+        );
+        symlink("../shared/libplugin.so", secure.join("shared.so")).unwrap();
+        assert_eq!(
+            insecure_path(&secure.join("shared.so")).as_deref(),
+            Some("shared")
+        );
+        symlink(&shared, secure.join("link")).unwrap();
+        assert_eq!(
+            insecure_path(&secure.join("link/libplugin.so")).as_deref(),
+            Some("shared")
+        );
+        symlink(".", secure.join("here")).unwrap();
+        assert_eq!(insecure_path(&secure.join("here/here/libplugin.so")), None);
+
+        // The path must lead to the file which was opened
+        let file = File::open(secure.join("libplugin.so")).unwrap();
+        fs::rename(secure.join("libplugin.so"), secure.join("old.so")).unwrap();
+        fs::write(secure.join("libplugin.so"), "replaced").unwrap();
+        assert!(matches!(
+            check(&secure.join("libplugin.so"), &file),
+            Err(Error::InsecurePluginLocation { .. })
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
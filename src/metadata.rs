@@ -0,0 +1,447 @@
+//! Information plugins give about themselves.
+//!
+//! Plugins store their metadata encoded in a section of their own, so
+//! that hosts can read it from the file before loading the plugin, and
+//! export a function returning the same bytes once it is loaded.
+
+use std::slice;
+
+use crate::{Error, Result};
+
+/// Information a plugin gives about itself, set with `metadata: { ... },`
+/// in `plugin_impl!`. Any fields not given there are taken from the
+/// plugin crate's `Cargo.toml`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginMetadata {
+    /// The plugin's name.
+    pub name: String,
+    /// The plugin's version.
+    pub version: semver::Version,
+    /// The plugin's authors.
+    pub authors: Vec<String>,
+    /// A description of the plugin.
+    pub description: Option<String>,
+    /// The plugin's homepage.
+    pub homepage: Option<String>,
+    /// The plugin's license.
+    pub license: Option<String>,
+    /// The other plugins this plugin requires to be loaded first.
+    pub dependencies: Vec<PluginDependency>,
+}
+
+/// Another plugin which a plugin requires to be loaded first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PluginDependency {
+    /// The name of the plugin depended on.
+    pub name: String,
+    /// The versions of the plugin which are compatible.
+    pub requirement: semver::VersionReq,
+}
+
+/// The bytes which start encoded metadata, ending with the version of
+/// the encoding.
+const MAGIC: [u8; 8] = *b"DPMETA\0\x01";
+/// The length of the magic bytes and the length of the rest.
+const HEADER_LEN: usize = MAGIC.len() + 4;
+/// The longest encoded metadata read from a file.
+pub(crate) const MAX_ENCODED_LEN: usize = 64 * 1024;
+
+impl PluginMetadata {
+    /// Decode the metadata encoded at the start of `bytes`, returning it
+    /// and the number of bytes it took.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::InvalidMetadata`] if the metadata is not encoded correctly, or the plugin's version or the version requirement of a dependency is not valid.
+    pub(crate) fn decode(bytes: &[u8]) -> Result<(Self, usize)> {
+        let len = encoded_len(bytes)?;
+        let body = bytes
+            .get(HEADER_LEN..len)
+            .ok_or_else(|| Error::InvalidMetadata("the metadata ends unexpectedly".to_string()))?;
+        let mut reader = Reader(body);
+        let name = reader.str()?;
+        let version = reader.str()?;
+        let version = version
+            .parse()
+            .map_err(|e| Error::InvalidMetadata(format!("invalid version `{version}`: {e}")))?;
+        let authors = (0..reader.u32()?)
+            .map(|_| reader.str())
+            .collect::<Result<_>>()?;
+        let description = reader.optional()?;
+        let homepage = reader.optional()?;
+        let license = reader.optional()?;
+        let dependencies = (0..reader.u32()?)
+            .map(|_| {
+                let name = reader.str()?;
+                let requirement = reader.str()?;
+                Ok(PluginDependency {
+                    requirement: requirement.parse().map_err(|e| {
+                        Error::InvalidMetadata(format!(
+                            "invalid version requirement `{requirement}` for `{name}`: {e}"
+                        ))
+                    })?,
+                    name,
+                })
+            })
+            .collect::<Result<_>>()?;
+        if !reader.0.is_empty() {
+            return Err(Error::InvalidMetadata(
+                "unexpected bytes after the metadata".to_string(),
+            ));
+        }
+        let metadata = Self {
+            name,
+            version,
+            authors,
+            description,
+            homepage,
+            license,
+            dependencies,
+        };
+        Ok((metadata, len))
+    }
+
+    /// Decode the metadata exported by a plugin.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::InvalidMetadata`] if the metadata is not valid.
+    ///
+    /// # Safety
+    ///
+    /// `encoded` must have been exported by a plugin built with these
+    /// macros.
+    pub(crate) unsafe fn from_raw(encoded: *const u8) -> Result<Self> {
+        let header = slice::from_raw_parts(encoded, HEADER_LEN);
+        let len = encoded_len(header)?;
+        Self::decode(slice::from_raw_parts(encoded, len)).map(|(metadata, _)| metadata)
+    }
+}
+
+/// The length of the encoded metadata at the start of `bytes`, checking
+/// that it starts with the magic bytes.
+fn encoded_len(bytes: &[u8]) -> Result<usize> {
+    let invalid = || Error::InvalidMetadata("the metadata is not encoded correctly".to_string());
+    if bytes.get(..MAGIC.len()) != Some(&MAGIC) {
+        return Err(invalid());
+    }
+    let len = bytes[MAGIC.len()..]
+        .get(..4)
+        .and_then(|len| len.try_into().ok())
+        .map(u32::from_le_bytes)
+        .ok_or_else(invalid)?;
+    let len = HEADER_LEN + usize::try_from(len).map_err(|_| invalid())?;
+    if len > MAX_ENCODED_LEN {
+        return Err(invalid());
+    }
+    Ok(len)
+}
+
+/// Encoded metadata being decoded.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        if self.0.len() < len {
+            return Err(Error::InvalidMetadata(
+                "the metadata ends unexpectedly".to_string(),
+            ));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(
+            bytes.try_into().expect("4 bytes were taken"),
+        ))
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn optional(&mut self) -> Result<Option<String>> {
+        let present = self.take(1)?[0] != 0;
+        present.then(|| self.str()).transpose()
+    }
+}
+
+/// The metadata given to `plugin_impl!`, to be encoded when the plugin
+/// is compiled. Empty optional fields are missing.
+#[doc(hidden)]
+pub struct MetadataFields {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub authors: &'static [&'static str],
+    pub description: &'static str,
+    pub homepage: &'static str,
+    pub license: &'static str,
+    pub dependencies: &'static [(&'static str, &'static str)],
+}
+
+impl MetadataFields {
+    /// The number of bytes these fields take once encoded.
+    #[must_use]
+    pub const fn encoded_len(&self) -> usize {
+        let mut len = HEADER_LEN + str_len(self.name) + str_len(self.version) + 4;
+        let mut idx = 0;
+        while idx < self.authors.len() {
+            len += str_len(self.authors[idx]);
+            idx += 1;
+        }
+        len += optional_len(self.description) + optional_len(self.homepage);
+        len += optional_len(self.license) + 4;
+        let mut idx = 0;
+        while idx < self.dependencies.len() {
+            len += str_len(self.dependencies[idx].0) + str_len(self.dependencies[idx].1);
+            idx += 1;
+        }
+        len
+    }
+
+    /// Encode these fields. `N` must be [`Self::encoded_len`].
+    ///
+    /// # Panics
+    ///
+    /// If `N` is not [`Self::encoded_len`], or the fields are too long to
+    /// encode.
+    #[must_use]
+    pub const fn encode<const N: usize>(&self) -> [u8; N] {
+        assert!(
+            N == self.encoded_len() && N <= MAX_ENCODED_LEN,
+            "the plugin's metadata cannot be encoded"
+        );
+        let mut writer = Writer {
+            bytes: [0; N],
+            pos: 0,
+        }
+        .bytes(&MAGIC)
+        .len(N - HEADER_LEN)
+        .str(self.name)
+        .str(self.version)
+        .len(self.authors.len());
+        let mut idx = 0;
+        while idx < self.authors.len() {
+            writer = writer.str(self.authors[idx]);
+            idx += 1;
+        }
+        writer = writer
+            .optional(self.description)
+            .optional(self.homepage)
+            .optional(self.license)
+            .len(self.dependencies.len());
+        let mut idx = 0;
+        while idx < self.dependencies.len() {
+            writer = writer
+                .str(self.dependencies[idx].0)
+                .str(self.dependencies[idx].1);
+            idx += 1;
+        }
+        writer.bytes
+    }
+}
+
+const fn str_len(s: &str) -> usize {
+    4 + s.len()
+}
+
+const fn optional_len(s: &str) -> usize {
+    if s.is_empty() {
+        1
+    } else {
+        1 + str_len(s)
+    }
+}
+
+/// Metadata being encoded when a plugin is compiled.
+struct Writer<const N: usize> {
+    bytes: [u8; N],
+    pos: usize,
+}
+
+impl<const N: usize> Writer<N> {
+    const fn bytes(mut self, bytes: &[u8]) -> Self {
+        let mut idx = 0;
+        while idx < bytes.len() {
+            self.bytes[self.pos] = bytes[idx];
+            self.pos += 1;
+            idx += 1;
+        }
+        self
+    }
+
+    /// Write a length, which fits as the whole is no longer than
+    /// [`MAX_ENCODED_LEN`].
+    #[allow(clippy::cast_possible_truncation)]
+    const fn len(self, len: usize) -> Self {
+        self.bytes(&(len as u32).to_le_bytes())
+    }
+
+    const fn str(self, s: &str) -> Self {
+        self.len(s.len()).bytes(s.as_bytes())
+    }
+
+    const fn optional(self, s: &str) -> Self {
+        if s.is_empty() {
+            self.bytes(&[0])
+        } else {
+            self.bytes(&[1]).str(s)
+        }
+    }
+}
+
+/// The number of items in `joined`, a list set by Cargo separated by
+/// colons.
+#[doc(hidden)]
+#[must_use]
+pub const fn cargo_list_len(joined: &str) -> usize {
+    let bytes = joined.as_bytes();
+    let mut count = 0;
+    let mut start = 0;
+    let mut idx = 0;
+    while idx <= bytes.len() {
+        if idx == bytes.len() || bytes[idx] == b':' {
+            if idx > start {
+                count += 1;
+            }
+            start = idx + 1;
+        }
+        idx += 1;
+    }
+    count
+}
+
+/// Split `joined`, a list set by Cargo separated by colons, into the `N`
+/// items it holds. `N` must be [`cargo_list_len`].
+#[doc(hidden)]
+#[must_use]
+pub const fn cargo_list<const N: usize>(joined: &'static str) -> [&'static str; N] {
+    let bytes = joined.as_bytes();
+    let mut items = [""; N];
+    let mut count = 0;
+    let mut start = 0;
+    let mut idx = 0;
+    while idx <= bytes.len() {
+        if idx == bytes.len() || bytes[idx] == b':' {
+            if idx > start {
+                // Safety: the item is within `joined`, and is split on
+                // an ASCII character so is still UTF-8
+                items[count] = unsafe {
+                    std::str::from_utf8_unchecked(slice::from_raw_parts(
+                        bytes.as_ptr().add(start),
+                        idx - start,
+                    ))
+                };
+                count += 1;
+            }
+            start = idx + 1;
+        }
+        idx += 1;
+    }
+    items
+}
+
+/// A string in the C ABI.
+#[doc(hidden)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawStr {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl RawStr {
+    #[must_use]
+    pub const fn new(s: &'static str) -> Self {
+        Self {
+            ptr: s.as_ptr(),
+            len: s.len(),
+        }
+    }
+
+    pub(crate) unsafe fn to_string(self) -> Option<String> {
+        if self.ptr.is_null() {
+            None
+        } else {
+            Some(String::from_utf8_lossy(slice::from_raw_parts(self.ptr, self.len)).into_owned())
+        }
+    }
+}
+
+// Safety: the strings are static and never written to
+unsafe impl Sync for RawStr {}
+
+#[cfg(test)]
+mod tests {
+    use super::{cargo_list, cargo_list_len, MetadataFields, PluginMetadata};
+    use crate::Error;
+
+    const FIELDS: MetadataFields = MetadataFields {
+        name: "example",
+        version: "1.2.3",
+        authors: &["Ana", "Bé"],
+        description: "An example",
+        homepage: "",
+        license: "MIT",
+        dependencies: &[("core", "^1")],
+    };
+    static ENCODED: [u8; FIELDS.encoded_len()] = FIELDS.encode();
+
+    #[test]
+    fn round_trips() {
+        let (metadata, len) = PluginMetadata::decode(&ENCODED).unwrap();
+        assert_eq!(len, ENCODED.len());
+        assert_eq!(metadata.name, "example");
+        assert_eq!(metadata.version, semver::Version::new(1, 2, 3));
+        assert_eq!(metadata.authors, ["Ana", "Bé"]);
+        assert_eq!(metadata.description.as_deref(), Some("An example"));
+        assert_eq!(metadata.homepage, None);
+        assert_eq!(metadata.license.as_deref(), Some("MIT"));
+        assert_eq!(metadata.dependencies[0].name, "core");
+        assert_eq!(metadata.dependencies[0].requirement.to_string(), "^1");
+        assert_eq!(
+            unsafe { PluginMetadata::from_raw(ENCODED.as_ptr()) }.unwrap(),
+            metadata
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_encodings() {
+        let decode = |bytes: &[u8]| PluginMetadata::decode(bytes).map(|(metadata, _)| metadata);
+        assert!(matches!(
+            decode(b"not metadata"),
+            Err(Error::InvalidMetadata(_))
+        ));
+        assert!(matches!(
+            decode(&ENCODED[..ENCODED.len() - 1]),
+            Err(Error::InvalidMetadata(_))
+        ));
+        let mut invalid_version = ENCODED;
+        // The first byte of the version
+        invalid_version[12 + 4 + "example".len() + 4] = b'x';
+        assert!(matches!(
+            decode(&invalid_version),
+            Err(Error::InvalidMetadata(_))
+        ));
+    }
+
+    fn split<const N: usize>(joined: &'static str) -> [&'static str; N] {
+        assert_eq!(cargo_list_len(joined), N);
+        cargo_list(joined)
+    }
+
+    #[test]
+    fn splits_cargo_lists() {
+        assert_eq!(split::<0>(""), [""; 0]);
+        assert_eq!(
+            split::<1>("Ana <ana@example.com>"),
+            ["Ana <ana@example.com>"]
+        );
+        assert_eq!(split::<2>("Ana:Bé"), ["Ana", "Bé"]);
+        assert_eq!(split::<2>(":Ana::Bé:"), ["Ana", "Bé"]);
+    }
+}
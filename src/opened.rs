@@ -0,0 +1,153 @@
+//! Loading plugins from the files which were checked.
+//!
+//! Checking a plugin's file, then loading it by its path, would let the
+//! file be replaced in between. On Linux, the open file is loaded
+//! instead, through its name in `/proc/self/fd`.
+//!
+//! glibc reuses a library which is already loaded under the name it is
+//! given. A descriptor's name can outlive the descriptor, when the
+//! library loaded through it can't be unloaded or was loaded again
+//! through another descriptor, so a library which might have been
+//! reused is checked to be mapped from the file. If it isn't, the file
+//! is loaded again through a new descriptor.
+
+use std::{fs::File, path::Path};
+
+use crate::{PluginDynamicLibrary, Result};
+
+/// Load the plugin `file`, which was opened from `path`.
+///
+/// # Errors
+///
+/// - [`Error::Io`](crate::Error::Io) if the file can't be loaded through a descriptor of its own.
+/// - [`Error::DynamicLibrary`](crate::Error::DynamicLibrary) if the library cannot be opened.
+pub(crate) fn load(path: &Path, file: &File) -> Result<PluginDynamicLibrary> {
+    #[cfg(target_os = "linux")]
+    // `/proc` may not be mounted
+    if Path::new("/proc/self/fd").is_dir() {
+        return linux::load(file);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = file;
+    // Safety: the caller has checked the file is a plugin
+    Ok(unsafe { PluginDynamicLibrary::new(path)? })
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{fs::File, os::unix::io::AsRawFd};
+
+    use crate::{PluginDynamicLibrary, Result};
+
+    /// How many descriptors to try loading a file through.
+    #[cfg(target_env = "gnu")]
+    const MAX_ATTEMPTS: usize = 16;
+
+    /// Load `file` through its name in `/proc/self/fd`.
+    #[cfg(not(target_env = "gnu"))]
+    pub(super) fn load(file: &File) -> Result<PluginDynamicLibrary> {
+        // Other C libraries match libraries loaded by path by their files
+        let name = format!("/proc/self/fd/{}", file.as_raw_fd());
+        // Safety: the caller has checked the file is a plugin
+        Ok(unsafe { PluginDynamicLibrary::new(name)? })
+    }
+
+    /// Load `file` through its name in `/proc/self/fd`, or through a
+    /// new descriptor if another library was loaded under that name.
+    #[cfg(target_env = "gnu")]
+    pub(super) fn load(file: &File) -> Result<PluginDynamicLibrary> {
+        use std::os::unix::fs::MetadataExt;
+
+        use libloading::os::unix::{Library, RTLD_LAZY, RTLD_LOCAL};
+
+        let metadata = file.metadata()?;
+        // New descriptors, kept open so each attempt has a different name
+        let mut descriptors = Vec::new();
+        let mut fd = file.as_raw_fd();
+        for _ in 0..MAX_ATTEMPTS {
+            let name = format!("/proc/self/fd/{fd}");
+            // Safety: only libraries which are already loaded are opened
+            let reused = unsafe {
+                Library::open(Some(&name), RTLD_LAZY | RTLD_LOCAL | libc::RTLD_NOLOAD).is_ok()
+            };
+            // Safety: the caller has checked the file is a plugin
+            let handle = unsafe { Library::open(Some(&name), RTLD_LAZY | RTLD_LOCAL)? }.into_raw();
+            // Safety: the handle is from `dlopen`, and isn't closed yet
+            let mapped = if reused {
+                unsafe { mapped_file(handle) }
+            } else {
+                Ok(None)
+            };
+            // Safety: the handle is from `into_raw`
+            let library = PluginDynamicLibrary::from(unsafe { Library::from_raw(handle) });
+            if !reused || mapped? == Some((metadata.dev(), metadata.ino())) {
+                return Ok(library);
+            }
+            drop(library);
+            let descriptor = file.try_clone()?;
+            fd = descriptor.as_raw_fd();
+            descriptors.push(descriptor);
+        }
+        Err(std::io::Error::other("another library is loaded under the plugin file's names").into())
+    }
+
+    /// The device and inode of the file the library with `handle` is
+    /// mapped from.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be from `dlopen`, and not yet closed.
+    #[cfg(target_env = "gnu")]
+    unsafe fn mapped_file(handle: *mut std::ffi::c_void) -> Result<Option<(u64, u64)>> {
+        use std::{ffi::c_void, fs};
+
+        /// The start of glibc's `struct link_map`.
+        #[repr(C)]
+        struct LinkMap {
+            addr: usize,
+            name: *const libc::c_char,
+            dynamic: *const c_void,
+        }
+
+        let mut link_map: *const LinkMap = std::ptr::null();
+        // glibc writes a pointer to the library's link map
+        let result = libc::dlinfo(
+            handle,
+            libc::RTLD_DI_LINKMAP,
+            std::ptr::addr_of_mut!(link_map).cast(),
+        );
+        if result != 0 || link_map.is_null() {
+            return Ok(None);
+        }
+        // The library's dynamic section is mapped from its file, and
+        // its link map lives as long as it is open
+        let address = (*link_map).dynamic as usize;
+
+        for line in fs::read_to_string("/proc/self/maps")?.lines() {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [range, _, _, device, inode, ..] = fields[..] else {
+                continue;
+            };
+            let Some((start, end)) = range.split_once('-') else {
+                continue;
+            };
+            let (Ok(start), Ok(end)) = (
+                usize::from_str_radix(start, 16),
+                usize::from_str_radix(end, 16),
+            ) else {
+                continue;
+            };
+            if !(start..end).contains(&address) {
+                continue;
+            }
+            let device = device.split_once(':').and_then(|(major, minor)| {
+                Some(libc::makedev(
+                    u32::from_str_radix(major, 16).ok()?,
+                    u32::from_str_radix(minor, 16).ok()?,
+                ))
+            });
+            return Ok(device.zip(inode.parse().ok()));
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,299 @@
+//! The plugin libraries in use by this process.
+//!
+//! One library can be used by several plugins, for example when it is
+//! loaded twice or linked statically and used more than once. Each
+//! library is prepared when its first plugin is loaded, and cleaned up
+//! when its last plugin is dropped, so that state the library keeps for
+//! the whole process, like the host API it calls, lives as long as any
+//! plugin using it. The name and version of each plugin in use is kept
+//! too, so that plugins can be checked to have their dependencies.
+
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+use crate::{HostApi, PluginLibrary, PluginMetadata, Result};
+
+/// What identifies a library, however many times it is opened.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Key {
+    /// The address of a function in a library loaded into this process.
+    Address(usize),
+    /// The process ID of an isolated plugin's worker.
+    #[cfg(unix)]
+    Worker(u32),
+}
+
+impl Key {
+    /// The key of `library`, if it can be identified. Libraries which
+    /// weren't built with these macros can't be, so are prepared and
+    /// cleaned up for each plugin.
+    fn of(library: &PluginLibrary) -> Option<Self> {
+        match library {
+            PluginLibrary::Dynamic { .. } => {
+                // Safety: only the address of the function is used
+                let signature = unsafe {
+                    library.get::<unsafe extern "C" fn() -> u64>(b"_dynamic_plugin_signature")
+                };
+                signature
+                    .ok()
+                    .map(|signature| Self::Address(signature as usize))
+            }
+            PluginLibrary::Static(symbols) => Some(Self::Address(symbols.as_ptr() as usize)),
+            #[cfg(unix)]
+            PluginLibrary::Isolated { worker, .. } => Some(Self::Worker(worker.id())),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum State {
+    /// The first plugin using the library is preparing it.
+    Preparing,
+    /// The library is ready to use.
+    Ready,
+    /// The last plugin using the library is cleaning it up.
+    CleaningUp,
+}
+
+struct Entry {
+    key: Key,
+    state: State,
+    /// How many plugins are using the library.
+    users: usize,
+    /// The name and version of the plugin in the library, if it gives
+    /// them.
+    plugin: Option<(String, semver::Version)>,
+    /// The host API given to the library, kept alive until it is cleaned
+    /// up.
+    host_api: Option<HostApi>,
+}
+
+static LIBRARIES: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+/// Notified when a library is prepared or cleaned up.
+static CHANGED: Condvar = Condvar::new();
+
+fn lock() -> MutexGuard<'static, Vec<Entry>> {
+    LIBRARIES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Start using `library`, whose plugin gave `metadata`. If it is not
+/// already in use, `prepare` is called to prepare it, returning the host
+/// API it was given. Otherwise, `join` is called with the host API it was given
+/// first, to check the new plugin can share it.
+///
+/// # Errors
+///
+/// - Any error returned by `prepare` or `join`, in which case the
+///   library is not in use by the plugin.
+pub(crate) fn acquire(
+    library: &PluginLibrary,
+    metadata: Option<&PluginMetadata>,
+    prepare: impl FnOnce() -> Result<Option<HostApi>>,
+    join: impl FnOnce(Option<&HostApi>) -> Result<()>,
+) -> Result<()> {
+    let Some(key) = Key::of(library) else {
+        return prepare().map(drop);
+    };
+
+    let mut libraries = lock();
+    loop {
+        match libraries.iter_mut().find(|entry| entry.key == key) {
+            None => break,
+            Some(entry) if entry.state == State::Ready => {
+                join(entry.host_api.as_ref())?;
+                entry.users += 1;
+                return Ok(());
+            }
+            // Wait for another thread to finish with it
+            Some(_) => {
+                libraries = CHANGED
+                    .wait(libraries)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+        }
+    }
+    libraries.push(Entry {
+        key,
+        state: State::Preparing,
+        users: 1,
+        plugin: metadata.map(|metadata| (metadata.name.clone(), metadata.version.clone())),
+        host_api: None,
+    });
+    drop(libraries);
+
+    // The library's code may load other plugins, so it is run unlocked
+    let result = prepare();
+
+    let mut libraries = lock();
+    let idx = libraries
+        .iter()
+        .position(|entry| entry.key == key)
+        .expect("libraries being prepared stay registered");
+    let result = match result {
+        Ok(host_api) => {
+            libraries[idx].state = State::Ready;
+            libraries[idx].host_api = host_api;
+            Ok(())
+        }
+        Err(error) => {
+            libraries.remove(idx);
+            Err(error)
+        }
+    };
+    CHANGED.notify_all();
+    result
+}
+
+/// The name and version of each plugin ready to use.
+pub(crate) fn loaded() -> Vec<(String, semver::Version)> {
+    lock()
+        .iter()
+        .filter(|entry| entry.state == State::Ready)
+        .filter_map(|entry| entry.plugin.clone())
+        .collect()
+}
+
+/// Stop using `library` for a plugin. If no other plugin is using it,
+/// `clean_up` is called to clean it up.
+pub(crate) fn release(library: &PluginLibrary, clean_up: impl FnOnce()) {
+    let Some(key) = Key::of(library) else {
+        return clean_up();
+    };
+
+    let mut libraries = lock();
+    let Some(entry) = libraries.iter_mut().find(|entry| entry.key == key) else {
+        return;
+    };
+    entry.users -= 1;
+    if entry.users > 0 {
+        return;
+    }
+    entry.state = State::CleaningUp;
+    drop(libraries);
+
+    clean_up();
+
+    let mut libraries = lock();
+    let idx = libraries
+        .iter()
+        .position(|entry| entry.key == key)
+        .expect("libraries being cleaned up stay registered");
+    let entry = libraries.remove(idx);
+    CHANGED.notify_all();
+    drop(libraries);
+    // The host API is dropped unlocked, as it runs the host's code
+    drop(entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::{library::StaticSymbol, Error};
+
+    fn library(symbols: &'static [StaticSymbol; 1]) -> PluginLibrary {
+        PluginLibrary::Static(symbols)
+    }
+
+    #[test]
+    fn prepares_and_cleans_up_once() {
+        static SYMBOLS: [StaticSymbol; 1] = [StaticSymbol::new("a", std::ptr::null())];
+        let prepared = Cell::new(0);
+        let joined = Cell::new(0);
+        let cleaned_up = Cell::new(0);
+        let acquire = || {
+            acquire(
+                &library(&SYMBOLS),
+                None,
+                || {
+                    prepared.set(prepared.get() + 1);
+                    Ok(None)
+                },
+                |_| {
+                    joined.set(joined.get() + 1);
+                    Ok(())
+                },
+            )
+        };
+        let release = || release(&library(&SYMBOLS), || cleaned_up.set(cleaned_up.get() + 1));
+
+        acquire().unwrap();
+        acquire().unwrap();
+        assert_eq!((prepared.get(), joined.get()), (1, 1));
+        release();
+        assert_eq!(cleaned_up.get(), 0);
+        release();
+        assert_eq!(cleaned_up.get(), 1);
+
+        // The library is prepared again once it is used again
+        acquire().unwrap();
+        assert_eq!(prepared.get(), 2);
+        release();
+        assert_eq!(cleaned_up.get(), 2);
+    }
+
+    #[test]
+    fn forgets_libraries_which_fail_to_prepare() {
+        static SYMBOLS: [StaticSymbol; 1] = [StaticSymbol::new("b", std::ptr::null())];
+        let result = acquire(
+            &library(&SYMBOLS),
+            None,
+            || Err(Error::Filtered),
+            |_| Ok(()),
+        );
+        assert!(matches!(result, Err(Error::Filtered)));
+        let prepared = Cell::new(false);
+        acquire(
+            &library(&SYMBOLS),
+            None,
+            || {
+                prepared.set(true);
+                Ok(None)
+            },
+            |_| Ok(()),
+        )
+        .unwrap();
+        assert!(prepared.get());
+        release(&library(&SYMBOLS), || ());
+    }
+
+    #[test]
+    fn rejects_plugins_which_cannot_join() {
+        static SYMBOLS: [StaticSymbol; 1] = [StaticSymbol::new("c", std::ptr::null())];
+        acquire(&library(&SYMBOLS), None, || Ok(None), |_| Ok(())).unwrap();
+        let result = acquire(
+            &library(&SYMBOLS),
+            None,
+            || Ok(None),
+            |_| Err(Error::MissingHostApi),
+        );
+        assert!(matches!(result, Err(Error::MissingHostApi)));
+        // Only the first plugin is using the library
+        let cleaned_up = Cell::new(false);
+        release(&library(&SYMBOLS), || cleaned_up.set(true));
+        assert!(cleaned_up.get());
+    }
+
+    #[test]
+    fn knows_which_plugins_are_loaded() {
+        static SYMBOLS: [StaticSymbol; 1] = [StaticSymbol::new("d", std::ptr::null())];
+        let metadata = PluginMetadata {
+            name: "registered".to_string(),
+            version: semver::Version::new(1, 2, 3),
+            authors: vec![],
+            description: None,
+            homepage: None,
+            license: None,
+            dependencies: vec![],
+        };
+        let is_loaded = || {
+            loaded()
+                .iter()
+                .any(|(name, version)| name == "registered" && *version == metadata.version)
+        };
+        acquire(&library(&SYMBOLS), Some(&metadata), || Ok(None), |_| Ok(())).unwrap();
+        assert!(is_loaded());
+        release(&library(&SYMBOLS), || ());
+        assert!(!is_loaded());
+    }
+}
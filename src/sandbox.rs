@@ -0,0 +1,290 @@
+//! Restricting what isolated plugins can do, with Landlock and seccomp.
+//!
+//! The worker applies the loader's [`SandboxPolicy`] to itself before it
+//! opens the plugin. Landlock limits the files the plugin can use, which
+//! fail with a permission error. Seccomp stops the plugin making network
+//! connections or starting processes: the system call traps, and the
+//! worker reports it to the host then exits. `io_uring`, which would let
+//! the plugin make system calls seccomp can't see, is unsupported.
+
+use std::{
+    collections::BTreeMap,
+    ffi::{c_int, c_uint, c_void, OsStr},
+    path::{Path, PathBuf},
+};
+
+use landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+    ABI,
+};
+use seccompiler::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule,
+};
+
+use crate::{
+    wire::{Reader, Writer},
+    Error, Result,
+};
+
+/// The newest Landlock ABI the sandbox uses. Older kernels enforce as
+/// much of it as they support.
+const LANDLOCK_ABI: ABI = ABI::V5;
+
+/// What an isolated plugin is allowed to do. Everything which isn't
+/// allowed is denied.
+///
+/// Give a policy to [`PluginLoader::sandbox`](crate::PluginLoader::sandbox)
+/// to apply it to each plugin's worker before the plugin is opened. The
+/// plugin's own library file can always be read.
+///
+/// ```ignore
+/// let policy = SandboxPolicy::new()
+///     .allow_read("/usr/share/my-daemon")
+///     .allow_write("/var/lib/my-daemon/plugin-data");
+/// let loader = PluginLoader::new().isolate(true).sandbox(policy);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SandboxPolicy {
+    read_paths: Vec<PathBuf>,
+    write_paths: Vec<PathBuf>,
+    network: bool,
+    processes: bool,
+}
+
+impl SandboxPolicy {
+    /// Create a policy which denies everything.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow the plugin to read the file at `path`, or anything in the
+    /// directory at `path`.
+    #[must_use]
+    pub fn allow_read<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.read_paths.push(path.into());
+        self
+    }
+
+    /// Allow the plugin to read and change the file at `path`, or
+    /// anything in the directory at `path`.
+    #[must_use]
+    pub fn allow_write<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.write_paths.push(path.into());
+        self
+    }
+
+    /// Set whether the plugin may open network sockets. This is denied
+    /// by default.
+    #[must_use]
+    pub fn allow_network(mut self, allow: bool) -> Self {
+        self.network = allow;
+        self
+    }
+
+    /// Set whether the plugin may start other processes. This is denied
+    /// by default. Plugins can always start threads.
+    #[must_use]
+    pub fn allow_processes(mut self, allow: bool) -> Self {
+        self.processes = allow;
+        self
+    }
+
+    /// Write the policy to send to the worker running the plugin at
+    /// `plugin_path`.
+    pub(crate) fn write(&self, writer: &mut Writer, plugin_path: &Path) {
+        let write_paths = |writer: &mut Writer, paths: &[&Path]| {
+            writer.write_u64(paths.len() as u64);
+            for path in paths {
+                writer.write_bytes(path.as_os_str().as_encoded_bytes());
+            }
+        };
+        let read_paths = self
+            .read_paths
+            .iter()
+            .map(PathBuf::as_path)
+            .chain([plugin_path])
+            .collect::<Vec<_>>();
+        write_paths(writer, &read_paths);
+        write_paths(
+            writer,
+            &self
+                .write_paths
+                .iter()
+                .map(PathBuf::as_path)
+                .collect::<Vec<_>>(),
+        );
+        writer.write_bool(self.network);
+        writer.write_bool(self.processes);
+    }
+
+    /// Read a policy sent by the host.
+    pub(crate) fn read(reader: &mut Reader<'_>) -> Result<Self> {
+        let read_paths = |reader: &mut Reader<'_>| {
+            (0..reader.read_u64()?)
+                .map(|_| {
+                    let path = reader.read_bytes()?;
+                    // Safety: the path was written by `as_encoded_bytes` in the host
+                    Ok(PathBuf::from(unsafe {
+                        OsStr::from_encoded_bytes_unchecked(&path)
+                    }))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            read_paths: read_paths(reader)?,
+            write_paths: read_paths(reader)?,
+            network: reader.read_bool()?,
+            processes: reader.read_bool()?,
+        })
+    }
+
+    /// Restrict the worker to this policy. This can't be undone.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Sandbox`] if the kernel doesn't support Landlock, or
+    ///   the policy can't be applied.
+    pub(crate) fn apply(&self) -> Result<()> {
+        self.restrict_paths()?;
+        self.restrict_system_calls()
+    }
+
+    /// Only allow the paths in the policy to be used.
+    fn restrict_paths(&self) -> Result<()> {
+        let failed = |error: landlock::RulesetError| Error::Sandbox(error.to_string());
+        let status = Ruleset::default()
+            .handle_access(AccessFs::from_all(LANDLOCK_ABI))
+            .map_err(failed)?
+            .create()
+            .map_err(failed)?
+            .add_rules(path_beneath_rules(
+                &self.read_paths,
+                AccessFs::from_read(LANDLOCK_ABI),
+            ))
+            .map_err(failed)?
+            .add_rules(path_beneath_rules(
+                &self.write_paths,
+                AccessFs::from_all(LANDLOCK_ABI),
+            ))
+            .map_err(failed)?
+            .restrict_self()
+            .map_err(failed)?;
+        if status.ruleset == RulesetStatus::NotEnforced {
+            return Err(Error::Sandbox(
+                "the kernel doesn't support Landlock".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Trap the system calls the policy denies.
+    fn restrict_system_calls(&self) -> Result<()> {
+        let failed = |error: seccompiler::BackendError| Error::Sandbox(error.to_string());
+        let mut trapped: BTreeMap<i64, Vec<SeccompRule>> = BTreeMap::new();
+        // `clone3` passes its flags in memory, which seccomp can't read
+        let mut unsupported: BTreeMap<i64, Vec<SeccompRule>> = BTreeMap::new();
+        if !self.network {
+            trapped.insert(libc::SYS_socket, vec![]);
+        }
+        if !self.processes {
+            // Threads are started with `clone` too, but share the worker's sandbox
+            let new_process = SeccompCondition::new(
+                0,
+                SeccompCmpArgLen::Qword,
+                SeccompCmpOp::MaskedEq(libc::CLONE_THREAD as u64),
+                0,
+            )
+            .and_then(|condition| SeccompRule::new(vec![condition]))
+            .map_err(failed)?;
+            trapped.insert(libc::SYS_clone, vec![new_process]);
+            #[cfg(target_arch = "x86_64")]
+            {
+                trapped.insert(libc::SYS_fork, vec![]);
+                trapped.insert(libc::SYS_vfork, vec![]);
+            }
+            trapped.insert(libc::SYS_execve, vec![]);
+            trapped.insert(libc::SYS_execveat, vec![]);
+            // The C library falls back to `clone` when this isn't supported
+            unsupported.insert(libc::SYS_clone3, vec![]);
+        }
+        // io_uring makes system calls, such as opening sockets, without
+        // seccomp seeing them. Libraries using it fall back to ordinary
+        // system calls when it isn't supported.
+        for syscall in [
+            libc::SYS_io_uring_setup,
+            libc::SYS_io_uring_enter,
+            libc::SYS_io_uring_register,
+        ] {
+            unsupported.insert(syscall, vec![]);
+        }
+
+        let arch = std::env::consts::ARCH
+            .try_into()
+            .map_err(|error: seccompiler::BackendError| Error::Sandbox(error.to_string()))?;
+        let trap: BpfProgram =
+            SeccompFilter::new(trapped, SeccompAction::Allow, SeccompAction::Trap, arch)
+                .and_then(TryInto::try_into)
+                .map_err(failed)?;
+        let enosys: BpfProgram = SeccompFilter::new(
+            unsupported,
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::ENOSYS.unsigned_abs()),
+            arch,
+        )
+        .and_then(TryInto::try_into)
+        .map_err(failed)?;
+
+        // Safety: the handler only calls async-signal-safe functions
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_violation as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO;
+            if libc::sigaction(
+                libc::SIGSYS,
+                std::ptr::from_ref(&action),
+                std::ptr::null_mut(),
+            ) == -1
+            {
+                return Err(Error::Sandbox(std::io::Error::last_os_error().to_string()));
+            }
+        }
+        for program in [&trap, &enosys] {
+            seccompiler::apply_filter_all_threads(program)
+                .map_err(|error| Error::Sandbox(error.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// The start of the `siginfo_t` the kernel gives to a `SIGSYS` handler.
+#[repr(C)]
+struct SigsysInfo {
+    signo: c_int,
+    errno: c_int,
+    code: c_int,
+    call_addr: *mut c_void,
+    syscall: c_int,
+    arch: c_uint,
+}
+
+/// Report a system call trapped by the sandbox to the host, then exit.
+extern "C" fn on_violation(_: c_int, info: *mut libc::siginfo_t, _: *mut c_void) {
+    // Safety: the kernel gives `SIGSYS` handlers this layout
+    let syscall = libc::c_long::from(unsafe { (*info.cast::<SigsysInfo>()).syscall });
+    let name = match syscall {
+        libc::SYS_socket => "socket",
+        libc::SYS_clone => "clone",
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_fork => "fork",
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_vfork => "vfork",
+        libc::SYS_execve => "execve",
+        libc::SYS_execveat => "execveat",
+        _ => "",
+    };
+    crate::isolation::report_violation(name);
+    // Safety: exiting straight away is async-signal-safe
+    unsafe { libc::_exit(1) };
+}
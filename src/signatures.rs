@@ -0,0 +1,81 @@
+//! Checking plugin files are signed by a trusted key.
+//!
+//! A plugin's signature is an ed25519 signature over the whole library
+//! file, stored in a file next to it with `.sig` added to its name.
+
+use std::{ffi::OsString, fs, path::Path};
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+use crate::{Error, Result};
+
+/// Check that `library`, the contents of the library at `path`, is
+/// signed by one of `trusted_keys`.
+///
+/// # Errors
+///
+/// - [`Error::Untrusted`] if the library has no signature, or is not signed by a trusted key.
+pub(crate) fn check(path: &Path, library: &[u8], trusted_keys: &[VerifyingKey]) -> Result<()> {
+    let mut signature_path = OsString::from(path);
+    signature_path.push(".sig");
+    let signature = fs::read(signature_path).map_err(|_| Error::Untrusted)?;
+    let signature = Signature::from_slice(&signature).map_err(|_| Error::Untrusted)?;
+
+    if trusted_keys
+        .iter()
+        .any(|key| key.verify(library, &signature).is_ok())
+    {
+        Ok(())
+    } else {
+        Err(Error::Untrusted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::check;
+    use crate::Error;
+
+    #[test]
+    fn checks_signatures() {
+        let dir =
+            std::env::temp_dir().join(format!("dynamic-plugin-signatures-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("libplugin.so");
+        let library = b"plugin";
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let other_key = SigningKey::from_bytes(&[2; 32]);
+        let untrusted = |result| matches!(result, Err(Error::Untrusted));
+
+        // Without a signature
+        assert!(untrusted(check(&path, library, &[key.verifying_key()])));
+
+        fs::write(dir.join("libplugin.so.sig"), key.sign(library).to_bytes()).unwrap();
+        check(&path, library, &[key.verifying_key()]).unwrap();
+        check(
+            &path,
+            library,
+            &[other_key.verifying_key(), key.verifying_key()],
+        )
+        .unwrap();
+        assert!(untrusted(check(
+            &path,
+            library,
+            &[other_key.verifying_key()]
+        )));
+        assert!(untrusted(check(
+            &path,
+            b"changed plugin",
+            &[key.verifying_key()]
+        )));
+
+        fs::write(dir.join("libplugin.so.sig"), b"not a signature").unwrap();
+        assert!(untrusted(check(&path, library, &[key.verifying_key()])));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
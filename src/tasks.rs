@@ -0,0 +1,322 @@
+//! Running the `async` functions of a plugin.
+//!
+//! Each call runs as a task, on a thread of its own by default, or on
+//! the executor given to `plugin_impl!` with `spawner`. The task calls
+//! back to the host when it completes. Each plugin counts its running
+//! tasks, and isn't unloaded while any are running, or while the threads
+//! they ran on are still exiting. A plugin whose tasks don't complete in
+//! [`UNLOAD_GRACE`] is left loaded, as unloading it would pull its code
+//! out from under them.
+
+use std::{
+    ffi::{c_void, CString},
+    future::{poll_fn, Future},
+    marker::PhantomData,
+    mem::MaybeUninit,
+    pin::{pin, Pin},
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, JoinHandle, Thread},
+    time::{Duration, Instant},
+};
+
+use libc::c_char;
+
+use crate::__private::{catch, take_last_panic};
+
+/// A task running an `async` plugin function, as given to the executor
+/// named by `plugin_impl!`'s `spawner` option.
+///
+/// A spawner is a `fn(PluginTask)` which runs the task to completion,
+/// such as by spawning it on the plugin's own runtime:
+///
+/// ```ignore
+/// fn spawn(task: PluginTask) {
+///     RUNTIME.spawn(task);
+/// }
+///
+/// plugin_impl! {
+///     Fetcher,
+///     spawner: spawn,
+///
+///     async fn fetch(url: &str) -> String {
+///         // ...
+///     }
+/// }
+/// ```
+///
+/// A task which is dropped before it completes fails its call.
+pub type PluginTask = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// How long to wait for a plugin's tasks to complete before it is
+/// unloaded.
+const UNLOAD_GRACE: Duration = Duration::from_secs(10);
+
+/// The tasks of one plugin which are still running. `plugin_impl!`
+/// gives each plugin with `async` functions one of these.
+pub struct RunningTasks {
+    state: Mutex<TasksState>,
+    /// Notified when the last running task completes.
+    idle: Condvar,
+}
+
+struct TasksState {
+    running: usize,
+    /// The threads tasks were started on, which haven't been joined.
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl RunningTasks {
+    /// Create an empty set of tasks.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(TasksState {
+                running: 0,
+                threads: Vec::new(),
+            }),
+            idle: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TasksState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Count a task which has completed, after it has called back to the
+    /// host. This is the last thing the task does.
+    fn completed(&self) {
+        let mut state = self.lock();
+        state.running -= 1;
+        if state.running == 0 {
+            self.idle.notify_all();
+        }
+    }
+
+    /// Wait until no tasks are running, and their threads have exited,
+    /// so that the plugin can be unloaded. Returns `false` if they are
+    /// still running after [`UNLOAD_GRACE`].
+    pub fn wait(&self) -> bool {
+        self.wait_for(UNLOAD_GRACE)
+    }
+
+    fn wait_for(&self, grace: Duration) -> bool {
+        let deadline = Instant::now() + grace;
+        let mut state = self.lock();
+        while state.running > 0 {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return false;
+            }
+            state = self
+                .idle
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        let threads = std::mem::take(&mut state.threads);
+        drop(state);
+        // Their tasks have completed, so the threads are only exiting
+        for thread in threads {
+            let _ = thread.join();
+        }
+        true
+    }
+}
+
+impl Default for RunningTasks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The context the host passes to an `async` function, to give back
+/// when the function completes.
+pub struct AsyncContext(*mut c_void);
+
+// Safety: the host's completion callback can be called from any thread
+unsafe impl Send for AsyncContext {}
+
+impl AsyncContext {
+    /// Wrap the context passed by the host.
+    #[must_use]
+    pub fn new(context: *mut c_void) -> Self {
+        Self(context)
+    }
+
+    /// The context passed by the host.
+    #[must_use]
+    pub fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// A running call, which completes it with an error if it is dropped
+/// before it finishes.
+struct Task<R, C: FnOnce(MaybeUninit<R>, *const c_char)> {
+    tasks: &'static RunningTasks,
+    complete: Option<C>,
+    _result: PhantomData<fn(R)>,
+}
+
+impl<R, C: FnOnce(MaybeUninit<R>, *const c_char)> Task<R, C> {
+    /// Start a task, counting it in `tasks` until it completes.
+    fn new(tasks: &'static RunningTasks, complete: C) -> Self {
+        tasks.lock().running += 1;
+        Self {
+            tasks,
+            complete: Some(complete),
+            _result: PhantomData,
+        }
+    }
+
+    /// Call back to the host with the outcome of the call: its result,
+    /// or the message it panicked with.
+    fn finish(mut self, outcome: Result<R, String>) {
+        if let Some(complete) = self.complete.take() {
+            finish(complete, outcome);
+        }
+    }
+}
+
+impl<R, C: FnOnce(MaybeUninit<R>, *const c_char)> Drop for Task<R, C> {
+    fn drop(&mut self) {
+        if let Some(complete) = self.complete.take() {
+            finish(
+                complete,
+                Err("the plugin's executor dropped the task before it completed".to_string()),
+            );
+        }
+        self.tasks.completed();
+    }
+}
+
+fn finish<R, C: FnOnce(MaybeUninit<R>, *const c_char)>(complete: C, outcome: Result<R, String>) {
+    match outcome {
+        Ok(value) => complete(MaybeUninit::new(value), std::ptr::null()),
+        Err(message) => {
+            let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+            complete(MaybeUninit::uninit(), message.as_ptr());
+        }
+    }
+}
+
+/// Start a call to an `async` function of the plugin with `tasks`.
+/// `build` copies its arguments and creates its future, which runs on
+/// `spawner`, or a new thread if there isn't one. The outcome is given
+/// to `complete`, with a panic message if the function panicked.
+pub fn spawn_async<R: 'static, F, B, C>(
+    tasks: &'static RunningTasks,
+    spawner: Option<fn(PluginTask)>,
+    build: B,
+    complete: C,
+) where
+    F: Future<Output = R> + Send + 'static,
+    B: FnOnce() -> F,
+    C: FnOnce(MaybeUninit<R>, *const c_char) + Send + 'static,
+{
+    let task = Task::new(tasks, complete);
+    let Some(future) = catch(build) else {
+        task.finish(Err(take_last_panic()));
+        return;
+    };
+    let task: PluginTask = Box::pin(async move {
+        let mut future = pin!(future);
+        let outcome = poll_fn(|cx| match catch(|| future.as_mut().poll(cx)) {
+            Some(poll) => poll.map(Ok),
+            None => Poll::Ready(Err(take_last_panic())),
+        })
+        .await;
+        task.finish(outcome);
+    });
+    if let Some(spawn) = spawner {
+        spawn(task);
+        return;
+    }
+    // If the thread can't be started, the task is dropped and fails its
+    // call
+    let thread = thread::Builder::new()
+        .name("dynamic-plugin-task".to_string())
+        .spawn(move || block_on(task));
+    // Threads are joined before the plugin is unloaded, and as later
+    // tasks start once they have exited
+    let mut state = tasks.lock();
+    let (finished, running): (Vec<_>, _) = std::mem::take(&mut state.threads)
+        .into_iter()
+        .partition(JoinHandle::is_finished);
+    state.threads = running;
+    state.threads.extend(thread.ok());
+    drop(state);
+    for thread in finished {
+        let _ = thread.join();
+    }
+}
+
+/// Run `future` to completion on the current thread.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(Unparker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+/// Wakes a task by unparking the thread running it.
+struct Unparker(Thread);
+
+impl Wake for Unparker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn counts_tasks_until_they_have_completed() {
+        static TASKS: RunningTasks = RunningTasks::new();
+        let (sender, receiver) = mpsc::channel();
+        spawn_async(
+            &TASKS,
+            None,
+            || async { 42 },
+            move |value: MaybeUninit<i32>, panic| {
+                assert!(panic.is_null());
+                // The task is still counted while it calls back
+                let running = TASKS.lock().running;
+                sender
+                    .send((unsafe { value.assume_init() }, running))
+                    .unwrap();
+            },
+        );
+        assert!(TASKS.wait_for(Duration::from_secs(10)));
+        assert_eq!(receiver.try_recv(), Ok((42, 1)));
+        assert_eq!(TASKS.lock().running, 0);
+        assert!(TASKS.lock().threads.is_empty());
+    }
+
+    #[test]
+    fn stops_waiting_for_tasks_which_dont_complete() {
+        static TASKS: RunningTasks = RunningTasks::new();
+        let (sender, receiver) = mpsc::channel::<()>();
+        spawn_async(
+            &TASKS,
+            None,
+            move || async move {
+                let _ = receiver.recv();
+            },
+            |_, _| {},
+        );
+        assert!(!TASKS.wait_for(Duration::from_millis(50)));
+        drop(sender);
+        assert!(TASKS.wait_for(Duration::from_secs(10)));
+    }
+}
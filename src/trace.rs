@@ -0,0 +1,142 @@
+//! Reporting plugin calls and loading through `tracing`.
+//!
+//! With the `tracing` feature, each host method runs its call in a
+//! `plugin_call` span, and the loader functions emit an event for each
+//! file they load or reject. The future of an `async` call carries its
+//! span, so the span lasts until the call completes.
+
+use std::{path::Path, time::Instant};
+
+use tracing::{field, span::Entered, Span};
+
+use crate::{DiscoveryReport, Error, PluginFuture, PluginLibrary, Result};
+
+/// The span of a call to a plugin function, which records how long the
+/// call took, and the error it returned.
+pub(crate) struct CallSpan {
+    span: Span,
+    start: Instant,
+}
+
+impl CallSpan {
+    fn new(interface: &str, function: &str, library: &PluginLibrary) -> Self {
+        let span = tracing::info_span!(
+            "plugin_call",
+            interface,
+            function,
+            path = library.path().map(|path| field::display(path.display())),
+            duration_ms = field::Empty,
+            error = field::Empty,
+        );
+        Self {
+            span,
+            start: Instant::now(),
+        }
+    }
+
+    /// Enter the span until the guard is dropped.
+    pub(crate) fn enter(&self) -> Entered<'_> {
+        self.span.enter()
+    }
+
+    /// Record that the call completed with `result`.
+    pub(crate) fn finish<T>(&self, result: &Result<T>) {
+        let _entered = self.span.enter();
+        self.span
+            .record("duration_ms", self.start.elapsed().as_secs_f64() * 1000.0);
+        if let Err(error) = result {
+            self.span.record("error", field::display(error));
+            tracing::error!(%error, "plugin call failed");
+        }
+    }
+}
+
+/// Run `call`, a call to the function `function` of `library`, in a
+/// span which records how long it took, and the error it returned.
+///
+/// # Errors
+///
+/// - Any error returned by `call`.
+pub fn traced_call<T>(
+    interface: &str,
+    function: &str,
+    library: &PluginLibrary,
+    call: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let span = CallSpan::new(interface, function, library);
+    let result = {
+        let _entered = span.enter();
+        call()
+    };
+    span.finish(&result);
+    result
+}
+
+/// Start `call`, a call to the `async` function `function` of
+/// `library`, in a span which the returned future enters each time it
+/// is polled. The span records how long the call took to complete, and
+/// the error it returned.
+///
+/// # Errors
+///
+/// - Any error returned by `call`.
+pub fn traced_async_call<'a, T>(
+    interface: &str,
+    function: &str,
+    library: &PluginLibrary,
+    call: impl FnOnce() -> Result<PluginFuture<'a, T>>,
+) -> Result<PluginFuture<'a, T>> {
+    let span = CallSpan::new(interface, function, library);
+    let result = {
+        let _entered = span.enter();
+        call()
+    };
+    match result {
+        Ok(future) => Ok(future.instrument(span)),
+        Err(error) => {
+            let result = Err(error);
+            span.finish(&result);
+            result
+        }
+    }
+}
+
+/// Run `load`, which loads the plugin at `path`, and report whether it
+/// was loaded.
+///
+/// # Errors
+///
+/// - Any error returned by `load`.
+pub fn traced_load<T>(interface: &str, path: &Path, load: impl FnOnce() -> Result<T>) -> Result<T> {
+    let result = load();
+    match &result {
+        Ok(_) => loaded(interface, path),
+        Err(error) => rejected(interface, path, error),
+    }
+    result
+}
+
+/// Report each plugin loaded and each file rejected while searching for
+/// plugins. `path` gives the path a plugin was loaded from.
+pub fn trace_report<P>(
+    interface: &str,
+    report: &DiscoveryReport<P>,
+    path: impl Fn(&P) -> Option<&Path>,
+) {
+    for plugin in &report.loaded {
+        if let Some(path) = path(plugin) {
+            loaded(interface, path);
+        }
+    }
+    for rejected_plugin in &report.rejected {
+        rejected(interface, &rejected_plugin.path, &rejected_plugin.error);
+    }
+}
+
+fn loaded(interface: &str, path: &Path) {
+    tracing::info!(interface, path = %path.display(), "loaded plugin");
+}
+
+fn rejected(interface: &str, path: &Path, error: &Error) {
+    tracing::info!(interface, path = %path.display(), %error, "rejected plugin");
+}
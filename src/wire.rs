@@ -0,0 +1,410 @@
+//! Encoding values sent between the host and an isolated plugin's worker
+//! process.
+//!
+//! Strings, byte buffers, slices and [`PluginError`]s are copied by
+//! value. Other values must be [`WireValue`]s, which hold no pointers,
+//! since pointers mean nothing in the other process.
+
+use std::{
+    ffi::{c_void, CString},
+    mem,
+};
+
+use crate::{Error, PluginError, Result};
+
+/// A value which can be sent between the host and an isolated plugin's
+/// worker: an integer, a float, a `bool`, `()`, or an array of them.
+///
+/// Values are written in little-endian order, and read back checking
+/// they are valid, so a worker can't send a `bool` which is neither
+/// `true` nor `false`.
+pub trait WireValue: Sized + sealed::Sealed {
+    /// Write the value to `writer`.
+    fn write(&self, writer: &mut Writer);
+
+    /// Read a value from `reader`.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short or holds an invalid value.
+    fn read(reader: &mut Reader<'_>) -> Result<Self>;
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+macro_rules! wire_numbers {
+    ($($ty:ty),*) => {$(
+        impl sealed::Sealed for $ty {}
+
+        impl WireValue for $ty {
+            fn write(&self, writer: &mut Writer) {
+                writer.bytes.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn read(reader: &mut Reader<'_>) -> Result<Self> {
+                let mut bytes = [0; mem::size_of::<$ty>()];
+                bytes.copy_from_slice(reader.take(mem::size_of::<$ty>())?);
+                Ok(<$ty>::from_le_bytes(bytes))
+            }
+        }
+    )*};
+}
+
+wire_numbers!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+impl sealed::Sealed for bool {}
+
+impl WireValue for bool {
+    fn write(&self, writer: &mut Writer) {
+        writer.write_bool(*self);
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self> {
+        reader.read_bool()
+    }
+}
+
+impl sealed::Sealed for () {}
+
+impl WireValue for () {
+    fn write(&self, _writer: &mut Writer) {}
+
+    fn read(_reader: &mut Reader<'_>) -> Result<Self> {
+        Ok(())
+    }
+}
+
+impl<T: WireValue, const N: usize> sealed::Sealed for [T; N] {}
+
+impl<T: WireValue, const N: usize> WireValue for [T; N] {
+    fn write(&self, writer: &mut Writer) {
+        for value in self {
+            value.write(writer);
+        }
+    }
+
+    fn read(reader: &mut Reader<'_>) -> Result<Self> {
+        let values = (0..N)
+            .map(|_| T::read(reader))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(values
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("{N} values were read")))
+    }
+}
+
+/// A message being written.
+#[derive(Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    /// Create an empty message.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The message written so far.
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Append another message.
+    pub(crate) fn write_raw(&mut self, message: &[u8]) {
+        self.bytes.extend_from_slice(message);
+    }
+
+    /// Write a single byte.
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    /// Write a boolean.
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(u8::from(value));
+    }
+
+    /// Write a `u32`.
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write a `u64`.
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write the handle of a plugin instance, which is only meaningful
+    /// in the worker.
+    pub(crate) fn write_handle(&mut self, handle: *mut c_void) {
+        self.bytes
+            .extend_from_slice(&(handle as usize).to_ne_bytes());
+    }
+
+    /// Write a buffer of bytes.
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.write_u64(value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+
+    /// Write a string.
+    pub fn write_str(&mut self, value: &str) {
+        self.write_bytes(value.as_bytes());
+    }
+
+    /// Write a [`PluginError`].
+    pub fn write_plugin_error(&mut self, value: &PluginError) {
+        self.bytes.extend_from_slice(&value.code().to_le_bytes());
+        self.write_str(value.message());
+    }
+
+    /// Write a value.
+    pub fn write_value<T: WireValue>(&mut self, value: &T) {
+        value.write(self);
+    }
+
+    /// Write a slice of values.
+    pub fn write_slice<T: WireValue>(&mut self, values: &[T]) {
+        self.write_u64(values.len() as u64);
+        for value in values {
+            value.write(self);
+        }
+    }
+}
+
+/// A message being read.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Read the message in `bytes`.
+    #[must_use]
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    /// Take the next `len` bytes of the message.
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(Error::Isolation("a message was cut short".to_string()));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    /// Take the rest of the message.
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        mem::take(&mut self.bytes)
+    }
+
+    /// Read a length, checking that it fits in the rest of the message
+    /// as `size` byte items.
+    fn read_len(&mut self, size: usize) -> Result<usize> {
+        usize::try_from(self.read_u64()?)
+            .ok()
+            .filter(|len| len.saturating_mul(size) <= self.bytes.len())
+            .ok_or_else(|| Error::Isolation("a message was cut short".to_string()))
+    }
+
+    /// Read a single byte.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short.
+    pub fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Read a boolean.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short or the byte read is not 0 or 1.
+    pub fn read_bool(&mut self) -> Result<bool> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(Error::Isolation(format!("{byte} is not a valid boolean"))),
+        }
+    }
+
+    /// Read a `u32`.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short.
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Read a `u64`.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short.
+    pub fn read_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// Read the handle of a plugin instance.
+    pub(crate) fn read_handle(&mut self) -> Result<*mut c_void> {
+        let mut bytes = [0; mem::size_of::<usize>()];
+        bytes.copy_from_slice(self.take(mem::size_of::<usize>())?);
+        Ok(usize::from_ne_bytes(bytes) as *mut c_void)
+    }
+
+    /// Read a buffer of bytes.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short.
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_len(1)?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Read a string.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short or the string is not valid UTF-8.
+    pub fn read_str(&mut self) -> Result<String> {
+        String::from_utf8(self.read_bytes()?)
+            .map_err(|_| Error::Isolation("a string is not valid UTF-8".to_string()))
+    }
+
+    /// Read a string to pass to a plugin as a C string.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short.
+    /// - [`Error::InteriorNul`] if the string contains a nul byte.
+    pub fn read_c_string(&mut self) -> Result<CString> {
+        CString::new(self.read_bytes()?).map_err(|_| Error::InteriorNul)
+    }
+
+    /// Read a [`PluginError`].
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short or the error's message is not valid UTF-8.
+    pub fn read_plugin_error(&mut self) -> Result<PluginError> {
+        let mut code = [0; 4];
+        code.copy_from_slice(self.take(4)?);
+        Ok(PluginError::new(i32::from_le_bytes(code), self.read_str()?))
+    }
+
+    /// Read a value.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short or holds an invalid value.
+    pub fn read_value<T: WireValue>(&mut self) -> Result<T> {
+        T::read(self)
+    }
+
+    /// Read a slice of values.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short or holds an invalid value.
+    pub fn read_vec<T: WireValue>(&mut self) -> Result<Vec<T>> {
+        let len = self.read_len(mem::size_of::<T>())?;
+        (0..len).map(|_| T::read(self)).collect()
+    }
+
+    /// Read a slice of values into `values`, which must be the same
+    /// length.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::Isolation`] if the message is too short, holds an invalid value, or the slice is a different length.
+    pub fn read_slice_into<T: WireValue>(&mut self, values: &mut [T]) -> Result<()> {
+        let len = self.read_len(mem::size_of::<T>())?;
+        if len != values.len() {
+            return Err(Error::Isolation(
+                "a slice changed length in the plugin".to_string(),
+            ));
+        }
+        for value in values {
+            *value = T::read(self)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reader, Writer};
+    use crate::{Error, PluginError, Result};
+
+    #[test]
+    fn round_trips() {
+        let mut writer = Writer::new();
+        writer.write_value(&-7i32);
+        writer.write_value(&1.5f64);
+        writer.write_value(&true);
+        writer.write_value(&[[1u16, 2], [3, 4]]);
+        writer.write_slice(&[u64::MAX, 0]);
+        writer.write_str("héllo");
+        writer.write_bytes(&[0, 255]);
+        writer.write_plugin_error(&PluginError::new(3, "failed"));
+
+        let mut reader = Reader::new(writer.as_bytes());
+        assert_eq!(reader.read_value::<i32>().unwrap(), -7);
+        assert!((reader.read_value::<f64>().unwrap() - 1.5).abs() < f64::EPSILON);
+        assert!(reader.read_value::<bool>().unwrap());
+        assert_eq!(
+            reader.read_value::<[[u16; 2]; 2]>().unwrap(),
+            [[1, 2], [3, 4]]
+        );
+        let mut values = [1, 1];
+        reader.read_slice_into(&mut values).unwrap();
+        assert_eq!(values, [u64::MAX, 0]);
+        assert_eq!(reader.read_str().unwrap(), "héllo");
+        assert_eq!(reader.read_bytes().unwrap(), [0, 255]);
+        let error = reader.read_plugin_error().unwrap();
+        assert_eq!((error.code(), error.message()), (3, "failed"));
+        assert!(reader.rest().is_empty());
+    }
+
+    fn isolation<T>(result: &Result<T>) -> bool {
+        matches!(result, Err(Error::Isolation(_)))
+    }
+
+    #[test]
+    fn rejects_invalid_messages() {
+        assert!(isolation(&Reader::new(&[2]).read_value::<bool>()));
+        assert!(isolation(&Reader::new(&[1, 2]).read_value::<[bool; 2]>()));
+        assert!(isolation(&Reader::new(&[1, 0, 0]).read_value::<u32>()));
+
+        // A length longer than the rest of the message
+        let mut writer = Writer::new();
+        writer.write_u64(u64::MAX);
+        assert!(isolation(&Reader::new(writer.as_bytes()).read_vec::<u32>()));
+        assert!(isolation(&Reader::new(writer.as_bytes()).read_bytes()));
+
+        // A slice which changed length
+        let mut writer = Writer::new();
+        writer.write_slice(&[1u8, 2, 3]);
+        let mut values = [0u8; 2];
+        assert!(isolation(
+            &Reader::new(writer.as_bytes()).read_slice_into(&mut values)
+        ));
+
+        let mut writer = Writer::new();
+        writer.write_bytes(&[0xff]);
+        assert!(isolation(&Reader::new(writer.as_bytes()).read_str()));
+    }
+}